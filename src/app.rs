@@ -1,6 +1,8 @@
-use kira::manager::{backend::DefaultBackend, AudioManager};
+use std::{path::{Path, PathBuf}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use crate::{control_panel::{MusicControl, MusicControlPanel}, music::{Music, MusicLoader, MusicMeta}, settings_window::{Settings, SettingsWindow}, style::load_style, synesthetizer::Synesthetizer};
+use crate::{audio_device::{self, AudioManager}, chromagram, control_panel::{MusicControl, MusicControlPanel}, frame_stats::FrameStats, midi_export::MidiExport, note_readout, music::{self, Music, MusicLoader, MusicMeta}, palette_preview, playlist::{self, Playlist}, scrub_preview::ScrubPreview, session_recording::SessionRecording, settings_window::{Settings, SettingsControl, SettingsWindow, Theme}, style::{self, load_style, reload_style}, synesthetizer::{self, ColorPalette, Synesthetizer, SynesthetizerConfig}, toast::Toast, video_export::VideoExport};
+#[cfg(feature = "server")]
+use crate::server::{self, OverlayServer};
 
 pub enum MusicState {
     Silence,
@@ -30,62 +32,468 @@ pub struct App {
     control_panel: MusicControlPanel,
     settings_window: SettingsWindow,
     settings: Settings,
+    video_export: Option<VideoExport>,
+    midi_export: Option<MidiExport>,
+    session_recording: Option<SessionRecording>,
+    scrub_preview: ScrubPreview,
+    #[cfg(feature = "server")]
+    overlay_server: Option<OverlayServer>,
+    /// The debug overlay toggled with F3
+    frame_stats: Option<FrameStats>,
+    /// A frozen frame shown beside the live one, to compare two moments or two sets of settings
+    reference_frame: Option<egui::TextureHandle>,
+    /// Freeze the next frame as `reference_frame`
+    capture_reference: bool,
+    last_file: Option<PathBuf>,
+    playlist: Playlist,
+    toast: Toast,
+    /// Kept open, since on some systems a copied image only lasts as long as the clipboard does
+    clipboard: Option<arboard::Clipboard>,
+    /// The theme, accent color and system theme the style was last built for
+    applied_style: (Theme, [u8; 3], Option<eframe::Theme>),
 }
 
+const SETTINGS_KEY: &str = "settings";
+const LAST_FILE_KEY: &str = "last_file";
+
 impl App {
-    pub fn new(cc: &eframe::CreationContext) -> Self {
+    /// `input` is what to play on startup: a file, an HTTP(S) URL, or `-` for stdin.
+    /// Without one, the last opened file is reopened.
+    pub fn new(cc: &eframe::CreationContext, input: Option<String>) -> Self {
         log::trace!("Starting app...");
     
         egui_extras::install_image_loaders(&cc.egui_ctx);
 
-        load_style(&cc.egui_ctx);
+        let mut settings: Settings = cc.storage
+            .and_then(|storage| eframe::get_value(storage, SETTINGS_KEY))
+            .unwrap_or_default();
+        let system_theme = cc.integration_info.system_theme;
+        if let Err(e) = load_style(&cc.egui_ctx, &settings, system_theme) {
+            log::warn!("Couldn't reload the font, using the bundled ones: {e:?}");
+            settings.font_path = None;
+        }
+        let applied_style = (settings.theme, settings.accent_color, system_theme);
+        let last_file: Option<PathBuf> = cc.storage
+            .and_then(|storage| eframe::get_value(storage, LAST_FILE_KEY));
 
-        let image = egui::ColorImage::new([1600, 900], egui::Color32::BLACK);
-        let texture = cc.egui_ctx.load_texture("screen", image.clone(), egui::TextureOptions {
-            magnification: egui::TextureFilter::Nearest,
-            minification: egui::TextureFilter::Nearest,
-        });
+        let (width, height) = settings.resolution;
+        let image = egui::ColorImage::new([width as usize, height as usize], egui::Color32::BLACK);
+        let texture = cc.egui_ctx.load_texture("screen", image.clone(), settings.canvas_filter.texture_options());
 
-        let audio_manager = AudioManager::<DefaultBackend>::new(Default::default()).unwrap();
+        let mut has_audio_output = true;
+        let audio_manager = audio_device::new_audio_manager(settings.output_device.clone())
+            .or_else(|e| {
+                log::error!("Couldn't open the chosen output device, using the default one: {e:?}");
+                audio_device::new_audio_manager(None)
+            })
+            .unwrap_or_else(|e| {
+                log::error!("Couldn't open any output device, playing silently: {e:?}");
+                has_audio_output = false;
+                audio_device::new_silent_audio_manager()
+            });
 
-        Self {
+        let palette = match settings.palette_path.clone() {
+            Some(path) => ColorPalette::load(&path)
+                .inspect(|_| log::info!("Palette loaded from {}.", path.display()))
+                .unwrap_or_else(|e| {
+                    log::warn!("Couldn't reload the palette from {}, using the default one: {e:?}", path.display());
+                    settings.palette_path = None;
+                    ColorPalette::bundled()
+                }),
+            None => ColorPalette::bundled(),
+        };
+        let synesthetizer = Synesthetizer::with_config(SynesthetizerConfig::from_settings(&settings, palette));
+
+        let mut app = Self {
             texture,
-            synesthetizer: Synesthetizer::new(),
+            synesthetizer,
             music_state: MusicState::Silence,
             music_loader: MusicLoader::new(audio_manager),
             control_panel: MusicControlPanel::new(),
             settings_window: SettingsWindow::new(),
-            settings: Settings::default(),
+            settings,
+            video_export: None,
+            midi_export: None,
+            session_recording: None,
+            scrub_preview: ScrubPreview::default(),
+            #[cfg(feature = "server")]
+            overlay_server: None,
+            frame_stats: None,
+            reference_frame: None,
+            capture_reference: false,
+            last_file,
+            playlist: Playlist::new(),
+            toast: Toast::new(),
+            clipboard: None,
+            applied_style,
+        };
+
+        if !has_audio_output {
+            app.toast.show_message(&cc.egui_ctx, "No audio output device was found. Music will play silently, but visuals and snapshots still work.");
+        }
+
+        match input {
+            Some(input) if input == "-" => app.load_stdin(),
+            Some(input) if is_url(&input) => app.load_url(input),
+            Some(input) => app.play_files(vec![PathBuf::from(input)]),
+            None => app.reopen_last_file(),
+        }
+
+        app
+    }
+
+    /// Starts or stops the overlay server to match the settings, and gives it the frame if anything is connected.
+    #[cfg(feature = "server")]
+    fn update_overlay_server(&mut self, ctx: &egui::Context) {
+        let port = self.settings.server_enabled.then_some(self.settings.server_port);
+        if self.overlay_server.as_ref().map(|server| server.port()) != port {
+            // Stopped first, so restarting on the same port can bind it again
+            self.overlay_server = None;
+            if let Some(port) = port {
+                match OverlayServer::start(port) {
+                    Ok(server) => self.overlay_server = Some(server),
+                    Err(e) => {
+                        log::error!("Couldn't start the overlay server on port {port}: {e:?}");
+                        self.toast.show_message(ctx, format!("Couldn't serve on port {port}: {e}"));
+                        self.settings.server_enabled = false;
+                    }
+                }
+            }
+        }
+
+        if let Some(server) = &self.overlay_server {
+            if server.wants_frame() {
+                let state = server::state_json(&self.music_state, self.synesthetizer.current_notes());
+                server.publish(self.synesthetizer.snapshot_image(&self.settings), state);
+            }
+        }
+    }
+
+    /// Waits for the last frames of the recording to be written, then says where they went.
+    fn finish_session_recording(&mut self, ctx: &egui::Context) {
+        let Some(recording) = self.session_recording.take() else {
+            return;
+        };
+        match recording.finish() {
+            Ok(folder) => self.toast.show_message(ctx, format!("Session recording saved to {}", folder.display())),
+            Err(e) => {
+                log::error!("There was a problem recording the session: {e:?}");
+                self.toast.show_message(ctx, format!("Couldn't record the session: {e}"));
+            }
+        }
+    }
+
+    /// Stops the current music and starts loading the file at `path`.
+    fn load_music(&mut self, path: PathBuf) {
+        self.stop_music();
+        self.last_file = Some(path.clone());
+        self.music_state = MusicState::Loading(self.music_loader.load_from_file(path));
+    }
+
+    /// Stops the current music and starts downloading the audio at `url`.
+    fn load_url(&mut self, url: String) {
+        self.stop_music();
+        self.playlist.replace(Vec::new());
+        self.music_state = MusicState::Loading(self.music_loader.load_from_url(url));
+    }
+
+    fn load_stdin(&mut self) {
+        self.stop_music();
+        self.playlist.replace(Vec::new());
+        self.music_state = MusicState::Loading(self.music_loader.load_from_reader(String::from("Standard input"), std::io::stdin()));
+    }
+
+    fn stop_music(&mut self) {
+        if let MusicState::Loaded(music) = &mut self.music_state {
+            music.stop();
+        }
+    }
+
+    /// Replaces the playlist with `files` and starts playing the first one.
+    /// Playlist files among them are replaced with the files they list.
+    fn play_files(&mut self, files: Vec<PathBuf>) {
+        self.playlist.replace(playlist::expand_playlists(files));
+        if let Some(path) = self.playlist.current() {
+            self.load_music(path.to_path_buf());
+        }
+    }
+
+    /// Rebuilds the audio manager on `settings.output_device`, carrying the music over to it.
+    fn change_output_device(&mut self, ctx: &egui::Context) {
+        let mut audio_manager = match audio_device::new_audio_manager(self.settings.output_device.clone()) {
+            Ok(audio_manager) => audio_manager,
+            Err(e) => {
+                log::error!("Couldn't open the output device: {e:?}");
+                self.toast.show_message(ctx, format!("Couldn't open the output device: {e}"));
+                return;
+            }
+        };
+
+        if let MusicState::Loaded(music) = &mut self.music_state {
+            music.move_to(&mut audio_manager);
+        }
+
+        self.music_loader.replace_audio_manager(audio_manager);
+        log::info!("Switched output device.");
+    }
+
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped_files = ctx.input(|i| i.raw.dropped_files.clone());
+        if dropped_files.is_empty() {
+            return;
+        }
+
+        let audio_files: Vec<PathBuf> = dropped_files.into_iter()
+            .filter_map(|file| file.path)
+            .filter(|path| music::is_audio_file(path) || playlist::is_playlist_file(path))
+            .collect();
+
+        if audio_files.is_empty() {
+            self.toast.show_message(ctx, format!(
+                "That isn't a supported audio file. Try one of: {}, or a {} playlist",
+                music::supported_extensions().join(", "),
+                playlist::PLAYLIST_EXTENSIONS.join(" or "),
+            ));
+        } else {
+            self.play_files(audio_files);
+        }
+    }
+
+    /// Saves the next frame to the snapshot folder, or wherever the user picks if there isn't one.
+    fn take_snapshot(&mut self, ctx: &egui::Context) {
+        let format = self.settings.snapshot_format;
+
+        let path = match &self.settings.snapshot_folder {
+            Some(folder) => {
+                let track_name = match &self.music_state {
+                    MusicState::Loaded(music) => synesthetizer::safe_file_name(music.name()),
+                    _ => String::from("snapshot"),
+                };
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                Some(folder.join(format!("{track_name}_{timestamp}.{}", format.extensions()[0])))
+            }
+            None => {
+                self.music_state.pause();
+                let path = rfd::FileDialog::new().add_filter(format.label(), format.extensions()).save_file();
+                self.music_state.play(self.music_loader.audio_manager_mut());
+                path
+            }
+        };
+
+        if let Some(path) = path {
+            if let Err(e) = self.synesthetizer.request_snapshot(path, &self.settings) {
+                self.toast.show_message(ctx, format!("Couldn't take snapshot: {e}"));
+            }
+        }
+    }
+
+    /// Puts the frame on screen on the clipboard.
+    fn copy_snapshot(&mut self) -> anyhow::Result<()> {
+        let image = self.synesthetizer.snapshot_image(&self.settings);
+        let clipboard = match &mut self.clipboard {
+            Some(clipboard) => clipboard,
+            None => self.clipboard.insert(arboard::Clipboard::new()?),
+        };
+
+        clipboard.set_image(arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: image.into_raw().into(),
+        })?;
+        Ok(())
+    }
+
+    fn reopen_last_file(&mut self) {
+        match self.last_file.clone() {
+            Some(path) if path.exists() => self.play_files(vec![path]),
+            Some(path) => {
+                log::warn!("The last opened file, {}, no longer exists.", path.display());
+                self.last_file = None;
+            }
+            None => {}
         }
     }
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if let Some(music) = self.music_loader.check_loaded() {
-            self.synesthetizer.load_music(&music);
-            self.music_state = MusicState::Loaded(music);
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, SETTINGS_KEY, &self.settings);
+        eframe::set_value(storage, LAST_FILE_KEY, &self.last_file);
+    }
+
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let style = (self.settings.theme, self.settings.accent_color, frame.info().system_theme);
+        if style != self.applied_style {
+            reload_style(ctx, &self.settings, style.2);
+            self.applied_style = style;
         }
 
-        match self.control_panel.show(&mut self.music_state, self.music_loader.audio_manager_mut(), ctx) {
+        match self.music_loader.check_loaded() {
+            Some(Ok(mut music)) => {
+                music.set_fade(self.settings.fade_seconds);
+                self.control_panel.attach(&mut music);
+                self.scrub_preview.clear();
+                self.synesthetizer.load_music(&music, &self.settings);
+                self.music_state = MusicState::Loaded(music);
+
+                // Decode the next track while this one plays, so there's no gap between them
+                if let Some(next) = self.playlist.peek_next() {
+                    self.music_loader.preload(next);
+                }
+            }
+            Some(Err(e)) => {
+                log::error!("There was a problem loading the music: {e:?}");
+                self.toast.show_message(ctx, format!("{e:#}"));
+                self.music_state = MusicState::Silence;
+            }
+            None => {}
+        }
+
+        if let MusicState::Loaded(music) = &self.music_state {
+            if music.is_stopped() {
+                if let Some(next) = self.playlist.next() {
+                    self.load_music(next);
+                }
+            }
+        }
+
+        if self.video_export.as_ref().is_some_and(|export| export.is_finished()) {
+            if let Err(e) = self.video_export.take().unwrap().finish() {
+                log::error!("There was a problem exporting the video: {e:?}");
+            }
+        }
+
+        if self.midi_export.as_ref().is_some_and(|export| export.is_finished()) {
+            match self.midi_export.take().unwrap().finish() {
+                Ok(path) => self.toast.show_message(ctx, format!("MIDI saved to {}", path.display())),
+                Err(e) => {
+                    log::error!("There was a problem exporting the MIDI: {e:?}");
+                    self.toast.show_message(ctx, format!("Couldn't export MIDI: {e}"));
+                }
+            }
+        }
+
+        self.handle_dropped_files(ctx);
+
+        // Shortcuts are read first so that focused widgets don't also react to the keys.
+        let shortcut = keyboard_shortcut(ctx);
+
+        let is_fullscreen = ctx.input(|i| i.viewport().fullscreen.unwrap_or(false));
+        self.control_panel.set_auto_hide(is_fullscreen || self.settings.auto_hide_panel);
+        // Side by side, the frames are half as wide
+        let (width, height) = self.settings.resolution;
+        let frames_across = if self.reference_frame.is_some() { 2 } else { 1 };
+        self.control_panel.set_frame_size((width * frames_across, height));
+
+        let control = match self.control_panel.show(
+            &mut self.music_state,
+            self.music_loader.audio_manager_mut(),
+            self.video_export.as_ref().map(|export| export.progress()),
+            self.last_file.as_deref(),
+            &self.playlist,
+            ctx,
+        ) {
+            MusicControl::Nothing => shortcut,
+            control => control,
+        };
+
+        match (self.control_panel.scrub_hover(), &self.music_state) {
+            (Some(position), MusicState::Loaded(music)) => {
+                let preview = self.scrub_preview.frame_at(&self.synesthetizer, music, position, &self.settings, ctx);
+                self.control_panel.show_scrub_preview(ctx, &preview);
+            }
+            // Rendered again next time, in case the settings changed in between
+            _ => self.scrub_preview.clear(),
+        }
+
+        match control {
             MusicControl::Settings => {
                 self.settings_window.toggle_open();
             }
             MusicControl::LoadMusic => {
-                if let Some(path) = rfd::FileDialog::new().pick_file() {
-                    if let MusicState::Loaded(music) = &mut self.music_state {
-                        music.stop();
+                if let Some(files) = pick_audio_files() {
+                    self.play_files(files);
+                }
+            }
+            MusicControl::LoadUrl(url) => {
+                self.load_url(url);
+            }
+            MusicControl::Enqueue => {
+                if let Some(files) = pick_audio_files() {
+                    self.playlist.extend(playlist::expand_playlists(files));
+                    if let Some(next) = self.playlist.peek_next() {
+                        self.music_loader.preload(next);
                     }
-
-                    self.music_state = MusicState::Loading(self.music_loader.load_from_file(path));
                 }
             }
+            MusicControl::NextTrack => {
+                if let Some(path) = self.playlist.next() {
+                    self.load_music(path);
+                }
+            }
+            MusicControl::PreviousTrack => {
+                if let Some(path) = self.playlist.previous() {
+                    self.load_music(path);
+                }
+            }
+            MusicControl::ReopenLastFile => {
+                self.reopen_last_file();
+            }
             MusicControl::Snapshot => {
-                self.music_state.pause();
-                if let Some(path) = rfd::FileDialog::new().save_file() {
-                    self.synesthetizer.request_snapshot(path);
+                self.take_snapshot(ctx);
+            }
+            MusicControl::CopySnapshot => match self.copy_snapshot() {
+                Ok(()) => self.toast.show_message(ctx, "Snapshot copied to the clipboard"),
+                Err(e) => {
+                    log::error!("There was a problem copying the snapshot: {e:?}");
+                    self.toast.show_message(ctx, format!("Couldn't copy snapshot: {e}"));
+                }
+            },
+            MusicControl::ExportMidi => {
+                if let (MusicState::Loaded(music), None) = (&self.music_state, &self.midi_export) {
+                    if let Some(path) = rfd::FileDialog::new().add_filter("MIDI", &["mid", "midi"]).save_file() {
+                        self.midi_export = Some(MidiExport::start(&self.synesthetizer, music, &self.settings, path));
+                    }
+                }
+            }
+            MusicControl::Filmstrip => {
+                if self.synesthetizer.is_filmstrip_running() {
+                    self.synesthetizer.stop_filmstrip();
+                    self.toast.show_message(ctx, "Filmstrip stopped");
+                } else if let MusicState::Loaded(music) = &mut self.music_state {
+                    if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                        // From the top, so the filmstrip covers the whole song
+                        music.seek_to(0.0, self.music_loader.audio_manager_mut());
+                        music.play(self.music_loader.audio_manager_mut());
+                        self.synesthetizer.start_filmstrip(folder, self.settings.filmstrip_interval);
+                    }
+                }
+            }
+            MusicControl::RecordSession => {
+                if self.session_recording.is_some() {
+                    self.finish_session_recording(ctx);
+                } else if let MusicState::Loaded(music) = &mut self.music_state {
+                    if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                        // From the top, so the frames cover the whole song
+                        music.seek_to(0.0, self.music_loader.audio_manager_mut());
+                        music.play(self.music_loader.audio_manager_mut());
+                        let audio_path = music.path().map(Path::to_path_buf);
+                        self.session_recording = Some(SessionRecording::start(folder, audio_path, self.settings.fps));
+                        self.toast.show_message(ctx, "Recording the session");
+                    }
+                }
+            }
+            MusicControl::CompareFrame => {
+                if self.reference_frame.take().is_none() {
+                    self.capture_reference = true;
+                }
+            }
+            MusicControl::ExportVideo => {
+                if let MusicState::Loaded(music) = &self.music_state {
+                    if let Some(path) = rfd::FileDialog::new().add_filter("Video", &["mp4", "webm", "mkv"]).save_file() {
+                        self.video_export = Some(VideoExport::start(&self.synesthetizer, music, &self.settings, path));
+                    }
                 }
-                self.music_state.play(self.music_loader.audio_manager_mut());
             }
             MusicControl::TogglePause => {
                 if let MusicState::Loaded(music) = &mut self.music_state {
@@ -96,21 +504,241 @@ impl eframe::App for App {
                     }
                 }
             }
+            MusicControl::Seek(amount) => {
+                if let MusicState::Loaded(music) = &mut self.music_state {
+                    music.scrub(amount, self.music_loader.audio_manager_mut());
+                }
+            }
+            MusicControl::PlayTestTone(tone) => {
+                self.stop_music();
+                self.playlist.replace(Vec::new());
+                self.music_state = MusicState::Loading(self.music_loader.load_test_tone(tone, self.settings.frequency_range()));
+            }
+            MusicControl::ToggleFullscreen => {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(!is_fullscreen));
+            }
+            MusicControl::ToggleFrameStats => {
+                self.frame_stats = match self.frame_stats.take() {
+                    Some(_) => None,
+                    None => Some(FrameStats::start()),
+                };
+                self.synesthetizer.set_timing(self.frame_stats.is_some());
+            }
             MusicControl::Nothing => {}
         }
 
-        self.texture.set(
-            self.synesthetizer.new_frame(&self.music_state, &self.settings).clone(),
-            egui::TextureOptions::default()
-        );
+        let new_frame_start = self.frame_stats.is_some().then(Instant::now);
+        let frame = self.synesthetizer.new_frame(&self.music_state, &self.settings);
+        if let (Some(stats), Some(start)) = (&mut self.frame_stats, new_frame_start) {
+            let repaint_interval = Duration::from_secs_f32(ctx.input(|i| i.unstable_dt));
+            stats.record(repaint_interval, start.elapsed(), self.synesthetizer.timings());
+            stats.show(ctx);
+        }
+        // Every frame, so a change of filter shows right away
+        let texture_options = self.settings.canvas_filter.texture_options();
+        if std::mem::take(&mut self.capture_reference) {
+            self.reference_frame = Some(ctx.load_texture("reference", frame.clone(), texture_options));
+        }
+        self.texture.set(frame, texture_options);
+
+        if let Some(recording) = &mut self.session_recording {
+            match &self.music_state {
+                MusicState::Loaded(music) if !music.is_stopped() => {
+                    recording.record(&self.synesthetizer.snapshot_image(&self.settings), music.position());
+                }
+                _ => self.finish_session_recording(ctx),
+            }
+        }
+
+        #[cfg(feature = "server")]
+        self.update_overlay_server(ctx);
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.add(egui::Image::new(&self.texture).fit_to_exact_size(ui.available_size()));
+        match self.synesthetizer.take_snapshot_result() {
+            Some(Ok(path)) => self.toast.show_message(ctx, format!("Snapshot saved to {}", path.display())),
+            Some(Err(e)) => self.toast.show_message(ctx, format!("Couldn't save snapshot: {e}")),
+            None => {}
+        }
+
+        // The visualization stays on black whatever the theme
+        let canvas_frame = egui::Frame::central_panel(&ctx.style()).fill(egui::Color32::BLACK);
+        egui::CentralPanel::default().frame(canvas_frame).show(ctx, |ui| {
+            // Letterboxed when the window's shape doesn't match the frame's
+            let live_frame = |ui: &mut egui::Ui| ui.centered_and_justified(|ui| ui.add(egui::Image::new(&self.texture).shrink_to_fit())).inner;
+            let image = match &self.reference_frame {
+                Some(reference) => ui.columns(2, |columns| {
+                    let frozen = columns[0].centered_and_justified(|ui| ui.add(egui::Image::new(reference).shrink_to_fit())).inner;
+                    columns[0].painter().text(
+                        frozen.rect.left_top() + egui::vec2(8.0, 8.0),
+                        egui::Align2::LEFT_TOP,
+                        "Frozen",
+                        egui::FontId::proportional(16.0),
+                        egui::Color32::from_white_alpha(180),
+                    );
+                    live_frame(&mut columns[1])
+                }),
+                None => live_frame(ui),
+            };
+
+            if self.settings.show_dominant_note {
+                note_readout::paint_dominant_note(
+                    ui.painter(),
+                    image.rect,
+                    self.synesthetizer.current_notes(),
+                    self.settings.dominant_note_position,
+                );
+            }
+            if self.settings.show_tempo {
+                note_readout::paint_tempo(
+                    ui.painter(),
+                    image.rect,
+                    self.synesthetizer.bpm(),
+                    self.synesthetizer.beat_pulse(),
+                    self.settings.tempo_position,
+                );
+            }
+
+            if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+                ui.painter().rect_filled(image.rect, 0.0, egui::Color32::from_black_alpha(180));
+                ui.painter().text(
+                    image.rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    "Drop an audio file to open it",
+                    egui::FontId::proportional(24.0),
+                    egui::Color32::WHITE,
+                );
+            }
         });
 
-        self.settings_window.show(ctx, &mut self.settings);
+        note_readout::show(
+            ctx,
+            &mut self.settings.show_note_readout,
+            self.synesthetizer.current_notes(),
+            chromagram::estimate_key(self.synesthetizer.chroma()),
+        );
+
+        self.toast.show(ctx);
+
+        match self.settings_window.show(ctx, &mut self.settings, self.synesthetizer.palette_mut()) {
+            SettingsControl::ReloadFps => {
+                if let MusicState::Loaded(music) = &self.music_state {
+                    self.synesthetizer.load_music(music, &self.settings);
+                }
+            }
+            SettingsControl::ChangeOutputDevice => {
+                self.change_output_device(ctx);
+            }
+            SettingsControl::ChangeFade => {
+                if let MusicState::Loaded(music) = &mut self.music_state {
+                    music.set_fade(self.settings.fade_seconds);
+                }
+            }
+            SettingsControl::Resize => {
+                self.synesthetizer.set_resolution(self.settings.resolution);
+            }
+            SettingsControl::ExportPalettePreview => {
+                if let Some(mut path) = rfd::FileDialog::new().add_filter("PNG", &["png"]).save_file() {
+                    if path.extension().is_none() {
+                        path.set_extension("png");
+                    }
+                    match palette_preview::save(self.synesthetizer.palette(), &path) {
+                        Ok(()) => self.toast.show_message(ctx, format!("Palette preview saved to {}", path.display())),
+                        Err(e) => {
+                            log::error!("Couldn't save the palette preview: {e:?}");
+                            self.toast.show_message(ctx, format!("Couldn't save the palette preview: {e}"));
+                        }
+                    }
+                }
+            }
+            SettingsControl::LoadPalette => {
+                if let Some(path) = rfd::FileDialog::new().add_filter("YAML", &["yaml", "yml"]).pick_file() {
+                    let previous = self.synesthetizer.palette().clone();
+                    match self.synesthetizer.load_palette(&path) {
+                        Ok(()) => {
+                            self.settings_window.record_palette_change(previous);
+                            self.settings.palette_path = Some(path);
+                            self.settings_window.set_palette_error(None);
+                        }
+                        Err(e) => {
+                            log::error!("There was a problem loading the palette: {e:?}");
+                            self.settings_window.set_palette_error(Some(format!("Couldn't load palette: {e}")));
+                        }
+                    }
+                }
+            }
+            SettingsControl::LoadFont => {
+                if let Some(path) = rfd::FileDialog::new().add_filter("Fonts", &["ttf", "otf"]).pick_file() {
+                    match style::load_fonts(ctx, Some(&path)) {
+                        Ok(()) => self.settings.font_path = Some(path),
+                        Err(e) => {
+                            log::error!("There was a problem loading the font: {e:?}");
+                            self.toast.show_message(ctx, format!("Couldn't load font: {e:#}"));
+                            // Put back the font from before
+                            if let Err(e) = style::load_fonts(ctx, self.settings.font_path.as_deref()) {
+                                log::error!("There was a problem putting back the previous font: {e:?}");
+                            }
+                        }
+                    }
+                }
+            }
+            SettingsControl::UseBundledFonts => {
+                self.settings.font_path = None;
+                if let Err(e) = style::load_fonts(ctx, None) {
+                    log::error!("There was a problem loading the bundled fonts: {e:?}");
+                }
+            }
+            SettingsControl::PickSnapshotFolder => {
+                if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                    self.settings.snapshot_folder = Some(folder);
+                }
+            }
+            SettingsControl::Nothing => {}
+        }
 
         // Repaint every frame
         ctx.request_repaint();
     }
 }
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+const SHORTCUT_SEEK_SECONDS: f64 = 5.0;
+
+/// Asks for audio files, or playlists of them.
+fn pick_audio_files() -> Option<Vec<PathBuf>> {
+    let mut extensions = music::supported_extensions().to_vec();
+    extensions.extend(playlist::PLAYLIST_EXTENSIONS);
+    rfd::FileDialog::new().add_filter("Audio and playlists", &extensions).pick_files()
+}
+
+fn keyboard_shortcut(ctx: &egui::Context) -> MusicControl {
+    // Leave the keys alone while the user is typing in a text field
+    if ctx.wants_keyboard_input() {
+        return MusicControl::Nothing;
+    }
+
+    // Ctrl (Cmd on macOS) and the arrows change tracks, so they're matched before the plain arrows,
+    // which seek. At either end of the playlist they do nothing, like the buttons.
+    ctx.input_mut(|i| {
+        if i.consume_key(egui::Modifiers::NONE, egui::Key::Space) {
+            MusicControl::TogglePause
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::F11) {
+            MusicControl::ToggleFullscreen
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::F3) {
+            MusicControl::ToggleFrameStats
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::S) {
+            MusicControl::Snapshot
+        } else if i.consume_key(egui::Modifiers::COMMAND, egui::Key::ArrowRight) || i.consume_key(egui::Modifiers::NONE, egui::Key::N) {
+            MusicControl::NextTrack
+        } else if i.consume_key(egui::Modifiers::COMMAND, egui::Key::ArrowLeft) || i.consume_key(egui::Modifiers::NONE, egui::Key::P) {
+            MusicControl::PreviousTrack
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft) {
+            MusicControl::Seek(-SHORTCUT_SEEK_SECONDS)
+        } else if i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight) {
+            MusicControl::Seek(SHORTCUT_SEEK_SECONDS)
+        } else {
+            MusicControl::Nothing
+        }
+    })
+}
@@ -0,0 +1,159 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc}, thread, time::{Duration, Instant}};
+
+use anyhow::Context;
+use cpal::{traits::{DeviceTrait, HostTrait, StreamTrait}, Device, Stream, StreamConfig};
+use kira::manager::{backend::{Backend, Renderer}, AudioManagerSettings};
+
+pub type AudioManager = kira::manager::AudioManager<DeviceBackend>;
+
+/// The sample rate sounds are mixed at when there's no output device
+const SILENT_SAMPLE_RATE: u32 = 48000;
+/// How often the silent output mixes the sounds
+const SILENT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Where a `DeviceBackend` plays.
+pub enum Output {
+    /// The output device with this name, or the system's default one if `None`
+    Device(Option<String>),
+    /// Nowhere. Sounds still play in real time, so everything else works as usual.
+    Silent,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self::Device(None)
+    }
+}
+
+/// Creates an audio manager that plays on the output device called `device_name`,
+/// or the system's default output device if `None`.
+pub fn new_audio_manager(device_name: Option<String>) -> anyhow::Result<AudioManager> {
+    let settings = AudioManagerSettings {
+        backend_settings: Output::Device(device_name),
+        ..Default::default()
+    };
+
+    AudioManager::new(settings)
+}
+
+/// Creates an audio manager that doesn't need an output device, for when there isn't one.
+pub fn new_silent_audio_manager() -> AudioManager {
+    let settings = AudioManagerSettings {
+        backend_settings: Output::Silent,
+        ..Default::default()
+    };
+
+    AudioManager::new(settings).expect("the silent output can't fail to start")
+}
+
+/// The names of the available output devices.
+pub fn output_device_names() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            log::error!("Couldn't list the output devices: {e:?}");
+            Vec::new()
+        }
+    }
+}
+
+/// A kira backend like its cpal one, except that it can play on any output device, or none.
+pub enum DeviceBackend {
+    Device {
+        device: Device,
+        config: StreamConfig,
+        stream: Option<Stream>,
+    },
+    Silent {
+        /// Set when the backend is dropped, to stop the mixing thread
+        stopped: Arc<AtomicBool>,
+    },
+}
+
+impl Backend for DeviceBackend {
+    type Settings = Output;
+
+    type Error = anyhow::Error;
+
+    fn setup(output: Self::Settings) -> anyhow::Result<(Self, u32)> {
+        let device_name = match output {
+            Output::Device(device_name) => device_name,
+            Output::Silent => return Ok((Self::Silent { stopped: Arc::default() }, SILENT_SAMPLE_RATE)),
+        };
+
+        let host = cpal::default_host();
+
+        let device = match device_name {
+            Some(name) => host.output_devices()?
+                .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+                .with_context(|| format!("there is no output device called '{name}'"))?,
+            None => host.default_output_device().context("there is no default output device")?,
+        };
+        let config = device.default_output_config()?.config();
+        let sample_rate = config.sample_rate.0;
+
+        Ok((Self::Device { device, config, stream: None }, sample_rate))
+    }
+
+    fn start(&mut self, mut renderer: Renderer) -> anyhow::Result<()> {
+        let (device, config, stream_slot) = match self {
+            Self::Device { device, config, stream } => (device, config, stream),
+            Self::Silent { stopped } => {
+                start_silent(renderer, stopped.clone());
+                return Ok(());
+            }
+        };
+        let channels = config.channels as usize;
+
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [f32], _| {
+                renderer.on_start_processing();
+                for frame in data.chunks_exact_mut(channels) {
+                    let out = renderer.process();
+                    if channels == 1 {
+                        frame[0] = (out.left + out.right) / 2.0;
+                    } else {
+                        frame[0] = out.left;
+                        frame[1] = out.right;
+                        // Any other channels get silence
+                        for channel in frame.iter_mut().skip(2) {
+                            *channel = 0.0;
+                        }
+                    }
+                }
+            },
+            |e| log::error!("Audio output error: {e:?}"),
+            None,
+        )?;
+        stream.play()?;
+
+        *stream_slot = Some(stream);
+        Ok(())
+    }
+}
+
+impl Drop for DeviceBackend {
+    fn drop(&mut self) {
+        if let Self::Silent { stopped } = self {
+            stopped.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Mixes the sounds on a thread as fast as they'd play, throwing the output away.
+fn start_silent(mut renderer: Renderer, stopped: Arc<AtomicBool>) {
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut mixed_frames = 0u64;
+        while !stopped.load(Ordering::Relaxed) {
+            let due_frames = (start.elapsed().as_secs_f64() * SILENT_SAMPLE_RATE as f64) as u64;
+            renderer.on_start_processing();
+            for _ in mixed_frames..due_frames {
+                renderer.process();
+            }
+            mixed_frames = mixed_frames.max(due_frames);
+            thread::sleep(SILENT_INTERVAL);
+        }
+    });
+}
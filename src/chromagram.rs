@@ -0,0 +1,123 @@
+use image::RgbaImage;
+use imageproc::{drawing, rect::Rect};
+
+use crate::{note::{Note, Pitch, PITCH_CLASS_NAMES}, settings_window::Settings, synesthetizer::ColorPalette};
+
+/// How much each pitch class fits a major key, starting from the tonic (Krumhansl and Kessler).
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+/// How much each pitch class fits a minor key, starting from the tonic (Krumhansl and Kessler).
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    /// Pitch class of the tonic, starting from C.
+    pub tonic: usize,
+    pub is_minor: bool,
+}
+
+impl Key {
+    /// Like "A minor" or "C# major".
+    pub fn name(&self) -> String {
+        let mode = if self.is_minor { "minor" } else { "major" };
+        format!("{} {mode}", PITCH_CLASS_NAMES[self.tonic])
+    }
+}
+
+/// The major or minor key whose profile correlates best with `chroma`, the energy
+/// in each pitch class. `None` if there's no energy to go by.
+pub fn estimate_key(chroma: &[f32; 12]) -> Option<Key> {
+    if chroma.iter().sum::<f32>() <= 0. {
+        return None;
+    }
+
+    (0..12)
+        .flat_map(|tonic| [(tonic, false), (tonic, true)])
+        .map(|(tonic, is_minor)| {
+            let profile = if is_minor { &MINOR_PROFILE } else { &MAJOR_PROFILE };
+            let rotated: [f32; 12] = std::array::from_fn(|pitch_class| profile[(pitch_class + 12 - tonic) % 12]);
+            (Key { tonic, is_minor }, correlation(chroma, &rotated))
+        })
+        .filter(|(_, correlation)| correlation.is_finite())
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(key, _)| key)
+}
+
+/// Pearson correlation between `a` and `b`.
+fn correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean = |values: &[f32; 12]| values.iter().sum::<f32>() / 12.;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+
+    let (mut covariance, mut variance_a, mut variance_b) = (0., 0., 0.);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a).powi(2);
+        variance_b += (b - mean_b).powi(2);
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+/// The total amplitude of `notes` in each pitch class, starting from C.
+pub fn pitch_class_energy(notes: &[Note]) -> [f32; 12] {
+    let mut energy = [0.; 12];
+    for note in notes {
+        let pitch_class = note.midi().round().rem_euclid(12.) as usize;
+        energy[pitch_class] += note.amplitude();
+    }
+    energy
+}
+
+/// Paints one bar per pitch class along the bottom `height` pixels of `image`,
+/// scaled so the loudest pitch class fills the whole height.
+pub fn paint(image: &mut RgbaImage, notes: &[Note], color_palette: &ColorPalette, settings: &Settings, height: u32) {
+    let energy = pitch_class_energy(notes);
+    let loudest = energy.iter().copied().fold(0., f32::max);
+    if loudest <= 0. {
+        return;
+    }
+
+    let (frame_width, frame_height) = image.dimensions();
+    let slot_width = frame_width / 12;
+    let bar_width = (slot_width * 4 / 5).max(1);
+
+    for (pitch_class, energy) in energy.into_iter().enumerate() {
+        let bar_height = (height as f32 * energy / loudest).round() as u32;
+        if bar_height == 0 {
+            continue;
+        }
+
+        // A note right on the pitch class gets exactly its palette color
+        let color = Note::new(Pitch::from_midi(pitch_class as f32), energy).color(color_palette, settings);
+        let x = pitch_class as u32 * slot_width + (slot_width - bar_width) / 2;
+        let rect = Rect::at(x as i32, (frame_height - bar_height) as i32).of_size(bar_width, bar_height);
+        drawing::draw_filled_rect_mut(image, rect, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_key_of_its_profile() {
+        // A minor, starting from C
+        let chroma = std::array::from_fn(|pitch_class| MINOR_PROFILE[(pitch_class + 12 - 9) % 12]);
+        assert_eq!(estimate_key(&chroma), Some(Key { tonic: 9, is_minor: true }));
+        assert_eq!(estimate_key(&chroma).unwrap().name(), "A minor");
+    }
+
+    #[test]
+    fn c_major_triad() {
+        let mut chroma = [0.; 12];
+        for pitch_class in [0, 4, 7] {
+            chroma[pitch_class] = 1.;
+        }
+        assert_eq!(estimate_key(&chroma), Some(Key { tonic: 0, is_minor: false }));
+    }
+
+    #[test]
+    fn no_key_in_silence() {
+        assert_eq!(estimate_key(&[0.; 12]), None);
+        // Every pitch class equally loud has no variance to correlate
+        assert_eq!(estimate_key(&[1.; 12]), None);
+    }
+}
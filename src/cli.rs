@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use crate::{music, settings_window::{Settings, SnapshotFormat}, synesthetizer::{self, Synesthetizer}};
+
+const USAGE: &str = "usage: synesthetic_screen render <input> <output> [--at <seconds>]";
+
+/// Runs a command given on the command line, without opening a window.
+///
+/// Returns `None` if the arguments don't name a command, so the app should start as usual.
+pub fn run(args: &[String]) -> Option<anyhow::Result<()>> {
+    match args.first().map(String::as_str) {
+        Some("render") => Some(render(&args[1..])),
+        _ => None,
+    }
+}
+
+/// `render <input> <output> [--at <seconds>]`
+fn render(args: &[String]) -> anyhow::Result<()> {
+    let mut paths = Vec::new();
+    let mut position = 0.0;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--at" {
+            let seconds = args.next().context(USAGE)?;
+            position = seconds.parse().with_context(|| format!("'{seconds}' isn't a number of seconds"))?;
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+
+    let [input, output] = paths.as_slice() else {
+        anyhow::bail!(USAGE);
+    };
+
+    let settings = Settings {
+        snapshot_format: snapshot_format(output)?,
+        ..Default::default()
+    };
+
+    let sound_data = music::decode_file(input)
+        .with_context(|| format!("couldn't load {}", input.display()))?;
+
+    let mut synesthetizer = Synesthetizer::new(&settings);
+    let image = synesthetizer.render_at(&sound_data, position, &settings);
+    synesthetizer::save_snapshot(&image, output, &settings)?;
+
+    log::info!("Rendered {} at {position}s to {}.", input.display(), output.display());
+    Ok(())
+}
+
+fn snapshot_format(path: &Path) -> anyhow::Result<SnapshotFormat> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(SnapshotFormat::from_extension)
+        .with_context(|| format!("can't tell the image format of {}, use .png, .jpg or .webp", path.display()))
+}
@@ -1,12 +1,33 @@
-use kira::manager::AudioManager;
+use std::path::Path;
 
-use crate::app::MusicState;
+use crate::{app::MusicState, audio_device::AudioManager, music::Music, playlist::Playlist, synesthetizer::{MIDI_MAX_FREQ, MIDI_MIN_FREQ}, test_tone::{TestTone, Waveform}};
 
 pub enum MusicControl {
     Settings,
     LoadMusic,
+    LoadUrl(String),
+    /// Add files to the end of the playlist.
+    Enqueue,
+    NextTrack,
+    PreviousTrack,
+    ReopenLastFile,
     Snapshot,
+    CopySnapshot,
+    /// Start or stop saving a snapshot at regular intervals of the song.
+    Filmstrip,
+    /// Start or stop saving every frame of the song as a numbered image.
+    RecordSession,
+    /// Freeze the current frame beside the live one, or stop comparing.
+    CompareFrame,
+    ExportVideo,
+    ExportMidi,
     TogglePause,
+    /// Seek by this many seconds from the current position.
+    Seek(f64),
+    ToggleFullscreen,
+    /// Show or hide the frame time and allocation stats.
+    ToggleFrameStats,
+    PlayTestTone(TestTone),
     Nothing,
 }
 
@@ -14,16 +35,104 @@ pub struct MusicControlPanel {
     music_position: f64,
     music_len: f64,
     scrub_bar_rect: egui::Rect,
+    /// The song position under the pointer and its x coordinate, while hovering or dragging the scrub bar
+    scrub_hover: Option<(f64, f32)>,
     is_playing: bool,
+    volume: f64,
+    playback_rate: f64,
+    is_looping: bool,
+    /// The A marker, in seconds
+    loop_start: Option<f64>,
+    /// The B marker, in seconds
+    loop_end: Option<f64>,
+    /// Show the time left instead of the length of the song
+    show_remaining_time: bool,
+    /// Min and max sample for each pixel of the scrub bar
+    waveform: Vec<(f32, f32)>,
+    /// The URL being typed in, while the URL field is open
+    url_input: Option<String>,
+    /// The time being typed into the seek field, like "2:35"
+    timestamp_input: String,
+    /// The tone the test tone controls are set to
+    test_tone: TestTone,
+    /// Hide the panel while the music plays and the mouse is still
+    auto_hide: bool,
+    /// Frame height over width
+    frame_aspect: f32,
+    /// When the mouse last moved, in seconds of `egui::InputState::time`
+    last_pointer_movement: f64,
 }
 
+/// How long the mouse has to stay still before the panel hides
+const AUTO_HIDE_SECONDS: f64 = 3.0;
+/// The control panel never gets shorter than this, even when the frame is narrow and tall
+const MIN_HEIGHT: f32 = 75.0;
+/// Seconds seeked per notch of the mouse wheel over the scrub bar
+const SCROLL_SEEK_SECONDS: f64 = 1.0;
+/// How far egui scrolls for one notch of the mouse wheel
+const POINTS_PER_SCROLL_NOTCH: f32 = 50.0;
+
 impl MusicControlPanel {
     pub fn new() -> Self {
         Self {
             music_position: 0.0,
             music_len: 0.0,
             scrub_bar_rect: egui::Rect::ZERO,
-            is_playing: false
+            scrub_hover: None,
+            is_playing: false,
+            volume: 1.0,
+            playback_rate: 1.0,
+            is_looping: false,
+            loop_start: None,
+            loop_end: None,
+            show_remaining_time: false,
+            waveform: Vec::new(),
+            url_input: None,
+            timestamp_input: String::new(),
+            test_tone: TestTone::default(),
+            auto_hide: false,
+            frame_aspect: 9. / 16.,
+            last_pointer_movement: 0.0,
+        }
+    }
+
+    pub fn set_auto_hide(&mut self, auto_hide: bool) {
+        self.auto_hide = auto_hide;
+    }
+
+    /// Sets the size of the frames shown above the panel, which the panel fits under.
+    pub fn set_frame_size(&mut self, (width, height): (u32, u32)) {
+        self.frame_aspect = height as f32 / width.max(1) as f32;
+    }
+
+    /// Whether the panel should be hidden this frame.
+    fn is_hidden(&mut self, music_state: &MusicState, ctx: &egui::Context) -> bool {
+        let (time, pointer_moved) = ctx.input(|i| (i.time, i.pointer.is_moving() || i.pointer.any_down()));
+        if pointer_moved {
+            self.last_pointer_movement = time;
+        }
+
+        let is_playing = matches!(music_state, MusicState::Loaded(music) if music.is_playing());
+        self.auto_hide && is_playing && self.url_input.is_none() && self.timestamp_input.is_empty() && time - self.last_pointer_movement > AUTO_HIDE_SECONDS
+    }
+
+    /// Applies the panel's volume, speed and loop settings to newly loaded music.
+    pub fn attach(&mut self, music: &mut Music) {
+        music.set_volume(self.volume);
+        music.set_playback_rate(self.playback_rate);
+
+        // Markers and waveform belong to the previous song
+        self.loop_start = None;
+        self.loop_end = None;
+        self.waveform.clear();
+        self.update_loop(music);
+    }
+
+    fn update_loop(&self, music: &mut Music) {
+        if self.is_looping {
+            music.set_loop_region(self.loop_start, self.loop_end);
+        } else {
+            music.stop_looping();
         }
     }
 
@@ -31,10 +140,25 @@ impl MusicControlPanel {
         &mut self,
         music_state: &mut MusicState,
         audio_manager: &mut AudioManager,
+        export_progress: Option<f32>,
+        last_file: Option<&Path>,
+        playlist: &Playlist,
         ctx: &egui::Context
     ) -> MusicControl {
+        self.scrub_hover = None;
+
+        // Slide away and back instead of popping
+        let is_hidden = self.is_hidden(music_state, ctx);
+        let visibility = ctx.animate_bool(egui::Id::new("control_panel_visibility"), !is_hidden);
+        if visibility == 0.0 {
+            return MusicControl::Nothing;
+        }
+
+        // Whatever's left under the frame at full width, or enough for the controls if that's too little
+        let height = (ctx.available_rect().height() - ctx.available_rect().width() * self.frame_aspect - 10.).max(MIN_HEIGHT);
+
         egui::TopBottomPanel::bottom("control_panel")
-            .exact_height(ctx.available_rect().height() - ctx.available_rect().width() * 9./16. - 10.)
+            .exact_height(height * visibility)
             .resizable(false)
             .show(ctx, |ui| {
                 let mut control = MusicControl::Nothing;
@@ -48,52 +172,221 @@ impl MusicControlPanel {
                         self.music_len = music.len();
 
                         ui.horizontal(|ui| {
-                            ui.label(music.name());
+                            ui.label(music.display_name());
                             ui.add_space(10.0);
                             if ui.button("Open another file...").clicked() {
                                 control = MusicControl::LoadMusic;
                             }
+                            if ui.button("Open URL...").clicked() {
+                                self.url_input = Some(String::new());
+                            }
+                            if ui.button("Add to queue...").clicked() {
+                                control = MusicControl::Enqueue;
+                            }
                             if ui.button("Settings...").clicked() {
                                 control = MusicControl::Settings;
                             }
+                            if ui.button("Fullscreen").clicked() {
+                                control = MusicControl::ToggleFullscreen;
+                            }
                             if ui.button("Take snapshot").clicked() {
                                 control = MusicControl::Snapshot;
                             }
+                            if ui.button("Copy to clipboard").clicked() {
+                                control = MusicControl::CopySnapshot;
+                            }
+                            if ui.button("Freeze and compare").on_hover_text("Keep this frame next to the live one, or stop comparing").clicked() {
+                                control = MusicControl::CompareFrame;
+                            }
+                            if ui.button("Filmstrip...").on_hover_text("Save a snapshot every few seconds, or stop saving them").clicked() {
+                                control = MusicControl::Filmstrip;
+                            }
+                            if ui.button("Record session...").on_hover_text("Save every frame as a PNG while the song plays, to mux with the audio later, or stop saving them").clicked() {
+                                control = MusicControl::RecordSession;
+                            }
+                            if let Some(progress) = export_progress {
+                                ui.add(egui::ProgressBar::new(progress)
+                                    .desired_width(150.0)
+                                    .text(format!("Exporting video... {:.0}%", progress * 100.0)));
+                            } else if ui.button("Export video...").clicked() {
+                                control = MusicControl::ExportVideo;
+                            }
+                            if ui.button("Export MIDI...").clicked() {
+                                control = MusicControl::ExportMidi;
+                            }
+                            ui.add_space(10.0);
+                            if ui.checkbox(&mut self.is_looping, "Loop").changed() {
+                                self.update_loop(music);
+                            }
+                            if (self.loop_start.is_some() || self.loop_end.is_some()) && ui.button("Clear A/B").clicked() {
+                                self.loop_start = None;
+                                self.loop_end = None;
+                                self.update_loop(music);
+                            }
                         });
 
                         ui.horizontal(|ui| {
+                            if playlist.len() > 1 && ui.add_enabled(playlist.position() > 0, egui::Button::new("Previous")).clicked() {
+                                control = MusicControl::PreviousTrack;
+                            }
                             if self.pause_toggle(ui).changed() {
                                 control = MusicControl::TogglePause;
                             }
+                            if playlist.len() > 1 {
+                                if ui.add_enabled(playlist.peek_next().is_some(), egui::Button::new("Next")).clicked() {
+                                    control = MusicControl::NextTrack;
+                                }
+                                ui.label(format!("{}/{}", playlist.position() + 1, playlist.len()));
+                            }
+                            ui.add_space(10.0);
+                            let volume_slider = egui::Slider::new(&mut self.volume, 0.0..=1.0)
+                                .text("Volume")
+                                .show_value(false);
+                            if ui.add(volume_slider).changed() {
+                                music.set_volume(self.volume);
+                            }
+                            let speed_slider = egui::Slider::new(&mut self.playback_rate, 0.25..=2.0)
+                                .text("Speed")
+                                .suffix("×")
+                                .max_decimals(2);
+                            if ui.add(speed_slider).changed() {
+                                music.set_playback_rate(self.playback_rate);
+                            }
+                            self.timestamp_field(ui, music, audio_manager);
                             ui.add_space(10.0);
-                            let scrub_response = self.scrub_bar(ui);
-                            if scrub_response.dragged() {
-                                let amount_percent = scrub_response.drag_delta().x / self.scrub_bar_rect.width();
-                                let amount = self.music_len * amount_percent as f64;
-                                music.scrub(amount, audio_manager);
+                            let scrub_response = self.scrub_bar(ui, music);
+                            if scrub_response.clicked() || scrub_response.dragged() {
+                                // Seek to right under the cursor, so the position can't drift from it
+                                if let Some(pointer) = scrub_response.interact_pointer_pos() {
+                                    let frac = (pointer.x - self.scrub_bar_rect.min.x) / self.scrub_bar_rect.width();
+                                    music.seek_to_fraction(frac as f64, audio_manager);
+                                }
+                            }
+                            // Dragging keeps the preview up when the pointer strays off the bar
+                            let pointer = if scrub_response.dragged() { scrub_response.interact_pointer_pos() } else { scrub_response.hover_pos() };
+                            self.scrub_hover = pointer.map(|pointer| {
+                                let x = pointer.x.clamp(self.scrub_bar_rect.min.x, self.scrub_bar_rect.max.x);
+                                let frac = (x - self.scrub_bar_rect.min.x) / self.scrub_bar_rect.width();
+                                (self.music_len * frac as f64, x)
+                            });
+                            if scrub_response.hovered() {
+                                // Either direction, so horizontal scrolling on a trackpad works too
+                                let scroll = ui.input(|i| i.scroll_delta.y + i.scroll_delta.x);
+                                if scroll != 0.0 {
+                                    music.scrub((scroll / POINTS_PER_SCROLL_NOTCH) as f64 * SCROLL_SEEK_SECONDS, audio_manager);
+                                }
+                            }
+                            if scrub_response.secondary_clicked() {
+                                if let Some(pointer) = scrub_response.interact_pointer_pos() {
+                                    let frac = (pointer.x - self.scrub_bar_rect.min.x) / self.scrub_bar_rect.width();
+                                    self.set_loop_marker(self.music_len * frac.clamp(0.0, 1.0) as f64);
+                                    self.update_loop(music);
+                                }
                             }
                         });
                     }
                     MusicState::Loading(meta) => {
                         ui.horizontal(|ui| {
-                            ui.label(format!("Loading {}... (this may take several seconds)", meta.name));
+                            ui.add(egui::Spinner::new());
+                            let size = meta.size.map(|size| format!(", {}", format_file_size(size))).unwrap_or_default();
+                            ui.label(format!("Loading {}{size}... (this may take several seconds)", meta.display_name()));
                         });
                     }
                     MusicState::Silence => {
-                        if ui.horizontal(|ui| {
+                        ui.horizontal(|ui| {
                             ui.label("No audio input selected.");
                             ui.add_space(10.0);
-                            ui.button("Open file...")
-                        }).inner.clicked() {
-                            control = MusicControl::LoadMusic;
-                        }
+                            if ui.button("Open file...").clicked() {
+                                control = MusicControl::LoadMusic;
+                            }
+                            if ui.button("Open URL...").clicked() {
+                                self.url_input = Some(String::new());
+                            }
+                            if last_file.is_some() && ui.button("Reopen last file").clicked() {
+                                control = MusicControl::ReopenLastFile;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Test tone:");
+                            egui::ComboBox::from_id_source("test_tone_waveform")
+                                .selected_text(self.test_tone.waveform.label())
+                                .show_ui(ui, |ui| {
+                                    for waveform in Waveform::ALL {
+                                        ui.selectable_value(&mut self.test_tone.waveform, waveform, waveform.label());
+                                    }
+                                });
+                            ui.add_enabled(
+                                self.test_tone.waveform != Waveform::Sweep,
+                                egui::DragValue::new(&mut self.test_tone.frequency)
+                                    .clamp_range(MIDI_MIN_FREQ..=MIDI_MAX_FREQ)
+                                    .speed(1.0)
+                                    .suffix(" Hz"),
+                            );
+                            if ui.button("Play").on_hover_text("Check the visualization against a known signal, like a 440 Hz sine lighting up A4").clicked() {
+                                control = MusicControl::PlayTestTone(self.test_tone);
+                            }
+                        });
                     }
                 }
 
+                if let Some(url) = self.url_input_row(ui) {
+                    control = MusicControl::LoadUrl(url);
+                }
+
                 control
             }).inner
     }
 
+    /// A field to type a time into and jump there with Enter. Times that can't be
+    /// read are left in the field, marked in red, and don't seek.
+    fn timestamp_field(&mut self, ui: &mut egui::Ui, music: &mut Music, audio_manager: &mut AudioManager) {
+        let timestamp = parse_min_sec(&self.timestamp_input);
+        let is_malformed = timestamp.is_none() && !self.timestamp_input.trim().is_empty();
+        let mut field = egui::TextEdit::singleline(&mut self.timestamp_input)
+            .hint_text("m:ss")
+            .desired_width(50.0);
+        if is_malformed {
+            field = field.text_color(ui.visuals().error_fg_color);
+        }
+
+        let response = ui.add(field).on_hover_text("Type a time and press Enter to jump there");
+        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            if let Some(seconds) = timestamp {
+                music.seek_to(seconds, audio_manager);
+                self.timestamp_input.clear();
+            }
+        }
+    }
+
+    /// Shows the URL field while it's open, returning the URL once it's submitted.
+    fn url_input_row(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let url_input = self.url_input.as_mut()?;
+        let mut submitted = false;
+        let mut cancelled = false;
+
+        ui.horizontal(|ui| {
+            let field = ui.add(egui::TextEdit::singleline(url_input).hint_text("https://..."));
+            if field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                submitted = true;
+            }
+            if ui.button("Open").clicked() {
+                submitted = true;
+            }
+            if ui.button("Cancel").clicked() {
+                cancelled = true;
+            }
+        });
+
+        if submitted && !url_input.trim().is_empty() {
+            return self.url_input.take().map(|url| url.trim().to_owned());
+        }
+        if cancelled {
+            self.url_input = None;
+        }
+
+        None
+    }
+
     fn pause_toggle(&mut self, ui: &mut egui::Ui) -> egui::Response {
         let size = (ui.spacing().interact_size.x, ui.spacing().interact_size.x);
         let (rect, mut response) = ui.allocate_exact_size(size.into(), egui::Sense::click());
@@ -122,12 +415,28 @@ impl MusicControlPanel {
         response
     }
 
-    fn scrub_bar(&mut self, ui: &mut egui::Ui) -> egui::Response {
+    /// Sets A, then B, then starts over with a new A. B on top of A is ignored, since
+    /// there would be nothing to loop.
+    fn set_loop_marker(&mut self, position: f64) {
+        match (self.loop_start, self.loop_end) {
+            (Some(start), None) if start == position => {}
+            (Some(start), None) => {
+                self.loop_start = Some(start.min(position));
+                self.loop_end = Some(start.max(position));
+            }
+            _ => {
+                self.loop_start = Some(position);
+                self.loop_end = None;
+            }
+        }
+    }
+
+    fn scrub_bar(&mut self, ui: &mut egui::Ui, music: &Music) -> egui::Response {
         let size = (
             ui.available_width() - 10.0,
             ui.spacing().interact_size.y,
         );
-        let (rect, response) = ui.allocate_exact_size(size.into(), egui::Sense::drag());
+        let (rect, response) = ui.allocate_exact_size(size.into(), egui::Sense::click_and_drag());
 
         if ui.is_rect_visible(rect) {
             let slider_visuals = ui.style().noninteractive();
@@ -137,30 +446,200 @@ impl MusicControlPanel {
             let radius = rect.height() * 0.5;
             ui.painter().rect(rect, radius, slider_visuals.weak_bg_fill, slider_visuals.bg_stroke);
 
+            // Recompute when the song or the width changes
+            let pixels = rect.width().max(0.0) as usize;
+            if self.waveform.len() != pixels {
+                self.waveform = music.peak_envelope(pixels);
+            }
+            let half_height = rect.height() * 0.5;
+            for (x, (min, max)) in self.waveform.iter().enumerate() {
+                let x = rect.min.x + x as f32 + 0.5;
+                let y_range = (rect.center().y - max.min(1.0) * half_height)..=(rect.center().y - min.max(-1.0) * half_height);
+                ui.painter().vline(x, y_range, (1.0, slider_visuals.fg_stroke.color.gamma_multiply(0.4)));
+            }
+
+            let x_at = |seconds: f64| rect.min.x + rect.width() * fraction_of(seconds, self.music_len);
+            if let (true, Some(start), Some(end)) = (self.is_looping, self.loop_start, self.loop_end) {
+                let loop_rect = egui::Rect::from_x_y_ranges(x_at(start)..=x_at(end), rect.y_range());
+                ui.painter().rect_filled(loop_rect, 0.0, slider_visuals.bg_fill);
+            }
+            for (marker, label) in [(self.loop_start, "A"), (self.loop_end, "B")] {
+                if let Some(marker) = marker {
+                    let x = x_at(marker);
+                    ui.painter().vline(x, rect.y_range(), ui.style().visuals.selection.stroke);
+                    ui.painter().text(
+                        (x, rect.min.y - 2.0).into(),
+                        egui::Align2::CENTER_BOTTOM,
+                        label,
+                        egui::FontId::monospace(10.0),
+                        ui.style().visuals.text_color(),
+                    );
+                }
+            }
+
             let cursor_visuals = ui.style().interact(&response);
             let mut cursor_rect = rect;
             cursor_rect.set_width(rect.height());
             cursor_rect = cursor_rect.expand(2.0);
-            let position = rect.min.x + (rect.width() * (self.music_position / self.music_len) as f32);
+            let position = rect.min.x + rect.width() * fraction_of(self.music_position, self.music_len);
             cursor_rect.set_center((position, rect.center().y).into());
             let radius = cursor_rect.width() * 0.5;
             ui.painter().circle(cursor_rect.center(), radius, cursor_visuals.bg_fill, cursor_visuals.bg_stroke);
 
-            ui.painter().text(
+            // Both are the same width, so the text doesn't jump when toggled
+            let right_time = if self.show_remaining_time {
+                format!("-{}", format_min_sec(self.music_len - self.music_position))
+            } else {
+                format!(" {}", format_min_sec(self.music_len))
+            };
+            let time_rect = ui.painter().text(
                 (rect.min.x, rect.max.y + 15.0).into(),
                 egui::Align2::LEFT_BOTTOM,
-                format!("{} / {}", format_min_sec(self.music_position), format_min_sec(self.music_len)),
+                format!("{} /{right_time}", format_min_sec(self.music_position)),
                 egui::FontId::monospace(12.0),
                 ui.style().visuals.text_color(),
             );
+            let time_response = ui.interact(time_rect, ui.id().with("time_label"), egui::Sense::click())
+                .on_hover_cursor(egui::CursorIcon::PointingHand);
+            if time_response.clicked() {
+                self.show_remaining_time = !self.show_remaining_time;
+            }
         }
 
         response
     }
+
+    /// The song position the pointer is over on the scrub bar, if it's there.
+    pub fn scrub_hover(&self) -> Option<f64> {
+        self.scrub_hover.map(|(position, _)| position)
+    }
+
+    /// Shows `preview`, the frame at `scrub_hover`, just above the scrub bar under the pointer.
+    pub fn show_scrub_preview(&self, ctx: &egui::Context, preview: &egui::TextureHandle) {
+        let Some((position, x)) = self.scrub_hover else {
+            return;
+        };
+
+        egui::Area::new("scrub_preview")
+            .order(egui::Order::Tooltip)
+            .interactable(false)
+            .pivot(egui::Align2::CENTER_BOTTOM)
+            .fixed_pos((x, self.scrub_bar_rect.min.y - 8.0))
+            .constrain(true)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.add(egui::Image::new(preview).fit_to_exact_size(preview.size_vec2()));
+                    ui.vertical_centered(|ui| {
+                        ui.monospace(format_min_sec(position));
+                        ui.weak("Right-click to set loop markers");
+                    });
+                });
+            });
+    }
+}
+
+/// How far `seconds` is through something `len` seconds long, from 0.0 to 1.0.
+/// Songs too short to have a length are at the start, rather than at NaN.
+fn fraction_of(seconds: f64, len: f64) -> f32 {
+    if len > 0.0 {
+        (seconds / len).clamp(0.0, 1.0) as f32
+    } else {
+        0.0
+    }
 }
 
 fn format_min_sec(seconds: f64) -> String {
+    let seconds = seconds.max(0.0);
     let minutes = seconds as u32 / 60;
     let seconds = (seconds % 60.0) as u32;
     format!("{minutes:02}:{seconds:02}")
 }
+
+/// Reads times like `format_min_sec` writes them, and the ones people type: "2:35", "02:35",
+/// "1:02:35" for hours, plain seconds like "155", and fractions like "2:35.5".
+/// `None` for anything else, like empty text, negative times or "1:75".
+fn parse_min_sec(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let mut fields: Vec<&str> = text.split(':').collect();
+    if fields.len() > 3 {
+        return None;
+    }
+    let seconds: f64 = fields.pop()?.parse().ok()?;
+    if !seconds.is_finite() || seconds < 0.0 || (!fields.is_empty() && seconds >= 60.0) {
+        return None;
+    }
+
+    // Hours and minutes are whole numbers, and minutes only stop at 60 when there are hours
+    let mut total = seconds;
+    for (idx, field) in fields.iter().rev().enumerate() {
+        let value: u32 = field.parse().ok()?;
+        if idx == 0 && fields.len() == 2 && value >= 60 {
+            return None;
+        }
+        total += value as f64 * 60f64.powi(idx as i32 + 1);
+    }
+    Some(total)
+}
+
+/// Like "850 KB" or "12.3 MB".
+fn format_file_size(bytes: u64) -> String {
+    const KB: f64 = 1000.0;
+    const MB: f64 = KB * 1000.0;
+    const GB: f64 = MB * 1000.0;
+
+    let bytes = bytes as f64;
+    if bytes >= GB {
+        format!("{:.1} GB", bytes / GB)
+    } else if bytes >= MB {
+        format!("{:.1} MB", bytes / MB)
+    } else {
+        format!("{:.0} KB", (bytes / KB).ceil())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_songs_show_zero_without_nan() {
+        assert_eq!(fraction_of(0.0, 0.0), 0.0);
+        assert_eq!(fraction_of(0.5, 0.0), 0.0);
+        assert_eq!(fraction_of(0.0125, 0.0125), 1.0);
+        assert_eq!(fraction_of(30.0, 120.0), 0.25);
+        assert_eq!(format_min_sec(0.0), "00:00");
+        assert_eq!(format_min_sec(f64::NAN), "00:00");
+        assert_eq!(format_min_sec(0.0125), "00:00");
+    }
+
+    #[test]
+    fn typed_timestamps_are_read() {
+        for seconds in [0.0, 59.0, 155.0, 3600.0, 5999.0] {
+            assert_eq!(parse_min_sec(&format_min_sec(seconds)), Some(seconds));
+        }
+        assert_eq!(parse_min_sec(" 2:35 "), Some(155.0));
+        assert_eq!(parse_min_sec("155"), Some(155.0));
+        assert_eq!(parse_min_sec("2:35.5"), Some(155.5));
+        assert_eq!(parse_min_sec("1:02:35"), Some(3755.0));
+
+        for malformed in ["", "  ", "abc", "2:", ":35", "1:75", "1:60:00", "-1:00", "1:-5", "1:2:3:4", "inf", "NaN", "1.5:00"] {
+            assert_eq!(parse_min_sec(malformed), None, "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn loop_markers_are_ordered_and_never_empty() {
+        let mut panel = MusicControlPanel::new();
+        panel.set_loop_marker(20.0);
+        panel.set_loop_marker(20.0);
+        assert_eq!((panel.loop_start, panel.loop_end), (Some(20.0), None));
+        panel.set_loop_marker(10.0);
+        assert_eq!((panel.loop_start, panel.loop_end), (Some(10.0), Some(20.0)));
+        panel.set_loop_marker(30.0);
+        assert_eq!((panel.loop_start, panel.loop_end), (Some(30.0), None));
+    }
+}
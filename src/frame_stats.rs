@@ -0,0 +1,182 @@
+use std::time::Duration;
+#[cfg(any(test, feature = "alloc-stats"))]
+use std::{alloc::{GlobalAlloc, Layout, System}, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+/// How much each repaint moves the averages shown, so the numbers are readable instead of flickering
+const AVERAGE_WEIGHT: f32 = 0.1;
+
+/// Counts allocations while `COUNTING` is on. Off, it still costs one relaxed load per
+/// allocation, so it's only in builds with the `alloc-stats` feature, and in tests.
+#[cfg(any(test, feature = "alloc-stats"))]
+struct CountingAllocator;
+
+#[cfg(any(test, feature = "alloc-stats"))]
+static COUNTING: AtomicBool = AtomicBool::new(false);
+#[cfg(any(test, feature = "alloc-stats"))]
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(any(test, feature = "alloc-stats"))]
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(any(test, feature = "alloc-stats"))]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(any(test, feature = "alloc-stats"))]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Starts or stops counting allocations on every thread.
+#[cfg(any(test, feature = "alloc-stats"))]
+pub fn count_allocations(on: bool) {
+    COUNTING.store(on, Ordering::Relaxed);
+}
+
+#[cfg(not(any(test, feature = "alloc-stats")))]
+pub fn count_allocations(_on: bool) {}
+
+/// How many allocations there have been, and how many bytes they were for, while counting was on.
+#[cfg(any(test, feature = "alloc-stats"))]
+pub fn allocation_counts() -> Option<(usize, usize)> {
+    Some((ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed)))
+}
+
+/// Allocations aren't counted without the `alloc-stats` feature.
+#[cfg(not(any(test, feature = "alloc-stats")))]
+pub fn allocation_counts() -> Option<(usize, usize)> {
+    None
+}
+
+/// How long the parts of the last frame the synesthetizer rendered took,
+/// kept only while the frame stats are shown.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameTimings {
+    pub fft: Duration,
+    pub find_tones: Duration,
+    /// Everything else, which is mostly painting
+    pub paint: Duration,
+}
+
+/// The debug overlay's running averages, in milliseconds, of how long repaints and their parts take.
+///
+/// With the `alloc-stats` feature, allocations are counted for as long as it exists.
+pub struct FrameStats {
+    repaint_interval: f32,
+    new_frame: f32,
+    fft: f32,
+    find_tones: f32,
+    paint: f32,
+    allocations: f32,
+    allocated_bytes: f32,
+    /// `None` when allocations can't be counted
+    last_counts: Option<(usize, usize)>,
+}
+
+impl FrameStats {
+    pub fn start() -> Self {
+        count_allocations(true);
+        Self {
+            repaint_interval: 0.0,
+            new_frame: 0.0,
+            fft: 0.0,
+            find_tones: 0.0,
+            paint: 0.0,
+            allocations: 0.0,
+            allocated_bytes: 0.0,
+            last_counts: allocation_counts(),
+        }
+    }
+
+    /// Adds a repaint, `repaint_interval` after the last one, that spent `new_frame` making
+    /// the frame. `timings` are the synesthetizer's, if it rendered one this time.
+    pub fn record(&mut self, repaint_interval: Duration, new_frame: Duration, timings: Option<FrameTimings>) {
+        let average = |average: &mut f32, value: f32| *average += (value - *average) * AVERAGE_WEIGHT;
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+
+        average(&mut self.repaint_interval, ms(repaint_interval));
+        average(&mut self.new_frame, ms(new_frame));
+        if let Some(timings) = timings {
+            average(&mut self.fft, ms(timings.fft));
+            average(&mut self.find_tones, ms(timings.find_tones));
+            average(&mut self.paint, ms(timings.paint));
+        }
+
+        if let (Some(counts), Some(last_counts)) = (allocation_counts(), self.last_counts) {
+            average(&mut self.allocations, counts.0.wrapping_sub(last_counts.0) as f32);
+            average(&mut self.allocated_bytes, counts.1.wrapping_sub(last_counts.1) as f32);
+            self.last_counts = Some(counts);
+        }
+    }
+
+    /// Shows the averages in the top right corner of the window.
+    pub fn show(&self, ctx: &egui::Context) {
+        egui::Area::new("frame_stats")
+            .anchor(egui::Align2::RIGHT_TOP, (-10.0, 10.0))
+            .interactable(false)
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    egui::Grid::new("frame_stats_grid").show(ui, |ui| {
+                        let fps = if self.repaint_interval > 0.0 { 1000.0 / self.repaint_interval } else { 0.0 };
+                        let allocations = match self.last_counts {
+                            Some(_) => format!("{:.0} ({:.0} KiB) per repaint", self.allocations, self.allocated_bytes / 1024.0),
+                            None => String::from("n/a without the alloc-stats feature"),
+                        };
+                        let rows = [
+                            ("Repaint", format!("{:.2} ms ({fps:.0} FPS)", self.repaint_interval)),
+                            ("new_frame", format!("{:.2} ms", self.new_frame)),
+                            ("FFT", format!("{:.2} ms", self.fft)),
+                            ("find_tones", format!("{:.2} ms", self.find_tones)),
+                            ("Paint", format!("{:.2} ms", self.paint)),
+                            ("Allocations", allocations),
+                        ];
+                        for (label, value) in rows {
+                            ui.label(label);
+                            ui.monospace(value);
+                            ui.end_row();
+                        }
+                    });
+                    ui.weak("F3 to hide");
+                });
+            });
+    }
+}
+
+impl Drop for FrameStats {
+    fn drop(&mut self) {
+        count_allocations(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_counted_while_shown() {
+        let mut stats = FrameStats::start();
+        let (allocations, bytes) = allocation_counts().unwrap();
+        let buffer = std::hint::black_box(vec![0u8; 4096]);
+        let (after_allocations, after_bytes) = allocation_counts().unwrap();
+        assert!(after_allocations > allocations);
+        assert!(after_bytes - bytes >= buffer.len());
+
+        let timings = FrameTimings { fft: Duration::from_millis(2), ..Default::default() };
+        for _ in 0..100 {
+            stats.record(Duration::from_millis(16), Duration::from_millis(4), Some(timings));
+        }
+        assert!((stats.repaint_interval - 16.0).abs() < 0.01);
+        assert!((stats.fft - 2.0).abs() < 0.01);
+        assert_eq!(stats.paint, 0.0);
+    }
+}
@@ -2,27 +2,75 @@ use app::App;
 use egui::ViewportBuilder;
 
 mod app;
+mod audio_device;
+mod chromagram;
+mod cli;
 mod control_panel;
+mod frame_stats;
+mod json;
+mod midi_export;
 mod music;
 mod note;
+mod note_readout;
+mod osc;
+mod palette_preview;
+mod playlist;
+mod scrub_preview;
+#[cfg(feature = "server")]
+mod server;
+mod session_recording;
 mod settings_window;
+mod spectrogram;
 mod style;
 mod synesthetizer;
+mod tempo;
+mod test_tone;
+mod text;
+mod toast;
+mod trails;
+mod video_export;
+
+const USAGE: &str = "usage: synesthetic_screen [<file> | <url> | -]
+       synesthetic_screen render <input> <output> [--at <seconds>]";
 
 fn main() -> eframe::Result<()> {
     env_logger::init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args) {
+        if let Err(e) = result {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    // A lone `-` is stdin, anything else starting with one is an option there isn't
+    if let Some(option) = args.iter().find(|arg| arg.starts_with('-') && *arg != "-") {
+        eprintln!("error: unknown option {option}\n{USAGE}");
+        std::process::exit(2);
+    }
+    let input = args.first().cloned();
+
     let native_options = eframe::NativeOptions {
         viewport: ViewportBuilder::default()
             .with_inner_size((1000., 650.))
             .with_min_inner_size((500., 500.))
             .with_title("Synesthetic Screen"),
+        follow_system_theme: true,
+        // Reopens the window where it was closed, moved back on screen if that monitor is gone.
+        // The size above is only for the first launch.
+        persist_window: true,
         ..Default::default()
     };
 
     eframe::run_native(
         "Synesthetic Screen",
         native_options,
-        Box::new(|cc| Box::new(App::new(cc)))
+        Box::new(|cc| Box::new(App::new(cc, input)))
     )
 }
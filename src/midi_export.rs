@@ -0,0 +1,195 @@
+use std::{collections::HashMap, fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}, thread::{self, JoinHandle}};
+
+use crate::{music::Music, settings_window::Settings, synesthetizer::Synesthetizer};
+
+/// Ticks per quarter note. At the default tempo of 120 BPM, that's 960 ticks a second.
+const TICKS_PER_QUARTER: u16 = 480;
+const TICKS_PER_SECOND: f64 = TICKS_PER_QUARTER as f64 * 2.0;
+
+/// A detected note, held from `start` to `end` seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiNote {
+    pub key: u8,
+    pub velocity: u8,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// A Standard MIDI File being transcribed from the song in the background.
+pub struct MidiExport {
+    join_handle: JoinHandle<anyhow::Result<PathBuf>>,
+}
+
+impl MidiExport {
+    /// Starts transcribing `music` on another thread with a copy of `synesthetizer`,
+    /// so the one on screen keeps running.
+    pub fn start(synesthetizer: &Synesthetizer, music: &Music, settings: &Settings, path: PathBuf) -> Self {
+        let mut synesthetizer = synesthetizer.clone();
+        let sound_data = music.data().clone();
+        let settings = settings.clone();
+
+        let join_handle = thread::spawn(move || {
+            let notes = transcribe(&mut synesthetizer, &sound_data, &settings);
+            write_midi_file(&path, &notes)?;
+            log::info!("MIDI saved to {}.", path.display());
+            Ok(path)
+        });
+
+        Self { join_handle }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    /// Waits for the transcription thread and returns where the file was saved.
+    /// Call once `is_finished` returns true.
+    pub fn finish(self) -> anyhow::Result<PathBuf> {
+        match self.join_handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("the MIDI export thread panicked")),
+        }
+    }
+}
+
+/// Turns the notes found in each frame of the song into held MIDI notes.
+///
+/// Notes are rounded to the nearest key, and a key is held for as long as consecutive frames have it.
+fn transcribe(synesthetizer: &mut Synesthetizer, sound_data: &kira::sound::static_sound::StaticSoundData, settings: &Settings) -> Vec<MidiNote> {
+    // Key to (start, loudest amplitude)
+    let mut held: HashMap<u8, (f64, f32)> = HashMap::new();
+    let mut finished = Vec::new();
+    let mut last_position = 0.0;
+
+    synesthetizer.analyze_song(sound_data, settings, |position, notes| {
+        let mut keys = HashMap::new();
+        for note in notes {
+            let key = note.midi().round().clamp(0., 127.) as u8;
+            let amplitude: &mut f32 = keys.entry(key).or_default();
+            *amplitude = amplitude.max(note.amplitude());
+        }
+
+        held.retain(|key, (start, amplitude)| {
+            let is_held = keys.contains_key(key);
+            if !is_held {
+                finished.push((*key, *start, position, *amplitude));
+            }
+            is_held
+        });
+        for (key, amplitude) in keys {
+            let (_, loudest) = held.entry(key).or_insert((position, amplitude));
+            *loudest = loudest.max(amplitude);
+        }
+
+        last_position = position;
+    });
+
+    let end = last_position + 1.0 / settings.fps as f64;
+    finished.extend(held.into_iter().map(|(key, (start, amplitude))| (key, start, end, amplitude)));
+
+    // Velocity is relative to the loudest note of the song
+    let loudest = finished.iter().map(|&(_, _, _, amplitude)| amplitude).fold(0.0, f32::max);
+    let notes = finished.into_iter()
+        .map(|(key, start, end, amplitude)| MidiNote {
+            key,
+            velocity: if loudest > 0.0 { (1.0 + 126.0 * (amplitude / loudest).sqrt()) as u8 } else { 64 },
+            start,
+            end,
+        })
+        .collect();
+
+    tidy_notes(notes, settings.midi_quantization, settings.midi_min_note_duration)
+}
+
+/// Snaps note starts and ends to a grid of `quantization` seconds, if it's above 0,
+/// then drops the notes shorter than `min_duration` seconds.
+fn tidy_notes(mut notes: Vec<MidiNote>, quantization: f64, min_duration: f64) -> Vec<MidiNote> {
+    if quantization > 0.0 {
+        for note in &mut notes {
+            note.start = (note.start / quantization).round() * quantization;
+            note.end = ((note.end / quantization).round() * quantization).max(note.start + quantization);
+        }
+    }
+
+    notes.retain(|note| note.end - note.start >= min_duration);
+    notes.sort_by(|a, b| a.start.total_cmp(&b.start));
+    notes
+}
+
+/// Writes `notes` to a single-track Standard MIDI File at 120 BPM.
+fn write_midi_file(path: &Path, notes: &[MidiNote]) -> anyhow::Result<()> {
+    // (tick, note off first, status, key, velocity)
+    let mut events: Vec<(u64, bool, u8, u8, u8)> = Vec::with_capacity(notes.len() * 2);
+    for note in notes {
+        let tick = |seconds: f64| (seconds * TICKS_PER_SECOND).round() as u64;
+        events.push((tick(note.start), false, 0x90, note.key, note.velocity));
+        events.push((tick(note.end), true, 0x80, note.key, 0));
+    }
+    // Offs before ons at the same tick, so a repeated key isn't cut short
+    events.sort_by_key(|&(tick, is_off, ..)| (tick, !is_off));
+
+    let mut track = Vec::new();
+    let mut last_tick = 0;
+    for (tick, _, status, key, velocity) in events {
+        write_variable_length(&mut track, tick - last_tick);
+        track.extend([status, key, velocity]);
+        last_tick = tick;
+    }
+    // End of track
+    track.extend([0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"MThd")?;
+    file.write_all(&6u32.to_be_bytes())?;
+    // Format 0, one track
+    file.write_all(&0u16.to_be_bytes())?;
+    file.write_all(&1u16.to_be_bytes())?;
+    file.write_all(&TICKS_PER_QUARTER.to_be_bytes())?;
+    file.write_all(b"MTrk")?;
+    file.write_all(&(track.len() as u32).to_be_bytes())?;
+    file.write_all(&track)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// MIDI's variable-length quantity: 7 bits per byte, most significant first,
+/// with the top bit set on every byte but the last.
+fn write_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.extend(groups.into_iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable_length(value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_variable_length(&mut bytes, value);
+        bytes
+    }
+
+    #[test]
+    fn variable_length_quantities() {
+        assert_eq!(variable_length(0), [0x00]);
+        assert_eq!(variable_length(0x7F), [0x7F]);
+        assert_eq!(variable_length(0x80), [0x81, 0x00]);
+        assert_eq!(variable_length(0x2000), [0xC0, 0x00]);
+        assert_eq!(variable_length(0x0FFF_FFFF), [0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn short_notes_are_dropped_after_quantizing() {
+        let note = |start, end| MidiNote { key: 60, velocity: 100, start, end };
+        let notes = tidy_notes(vec![note(0.26, 0.74), note(1.0, 1.05)], 0.25, 0.3);
+
+        // The second one snaps to a single grid step, still under the minimum
+        assert_eq!(notes, [note(0.25, 0.75)]);
+    }
+}
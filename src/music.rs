@@ -1,16 +1,86 @@
-use std::{path::Path, thread::{self, JoinHandle}};
+use std::{fs::File, io::{Cursor, ErrorKind, Read}, path::{Path, PathBuf}, sync::Arc, thread::{self, JoinHandle}, time::Duration};
 
-use kira::{manager::AudioManager, sound::{static_sound::{StaticSoundData, StaticSoundHandle}, PlaybackState, Region}};
+use anyhow::{anyhow, Context};
+use kira::{dsp::Frame, sound::{static_sound::{StaticSoundData, StaticSoundHandle}, EndPosition, FromFileError, PlaybackPosition, PlaybackState, Region}, tween::{Easing, Tween}};
+use symphonia::core::{
+    audio::{Channels, SampleBuffer},
+    codecs::{CodecType, DecoderOptions, CODEC_TYPE_OPUS},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream},
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::Hint,
+};
+
+use crate::{audio_device::AudioManager, test_tone::TestTone};
+
+/// Ogg files are Vorbis. Opus, which also comes in Ogg, can't be decoded.
+const AUDIO_EXTENSIONS: [&str; 8] = ["mp3", "wav", "ogg", "oga", "flac", "m4a", "aac", "alac"];
+
+/// Extensions of the audio files that can be loaded, for the file dialog and dropped files.
+///
+/// Mono and stereo files play as they are. Files with more channels, like 5.1 surround,
+/// are downmixed to stereo when they're loaded, with the LFE channel left out.
+pub fn supported_extensions() -> &'static [&'static str] {
+    &AUDIO_EXTENSIONS
+}
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| supported_extensions().contains(&ext.to_lowercase().as_str()))
+}
 
 #[derive(Debug, Clone)]
 pub struct MusicMeta {
+    /// `None` when the music didn't come from a local file.
+    pub path: Option<PathBuf>,
     pub file_name: String,
     pub name: String,
+    /// From the file's tags, if it has any
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Size of the file in bytes, when it's known before loading
+    pub size: Option<u64>,
+}
+
+impl MusicMeta {
+    /// "Artist — Title" from the tags when there are some, otherwise the name.
+    pub fn display_name(&self) -> String {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{artist} — {title}"),
+            (None, Some(title)) => title.clone(),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// How long `sound_data` plays for in seconds, which is 0.0 for files with no samples
+/// or no sample rate rather than NaN.
+pub fn duration(sound_data: &StaticSoundData) -> f64 {
+    if sound_data.sample_rate == 0 {
+        return 0.0;
+    }
+    sound_data.frames.len() as f64 / sound_data.sample_rate as f64
+}
+
+/// Sound data for `frames` at `sample_rate`, for samples that didn't come from a file.
+pub fn sound_data_from_frames(frames: Vec<kira::dsp::Frame>, sample_rate: u32) -> StaticSoundData {
+    StaticSoundData {
+        sample_rate,
+        frames: frames.into(),
+        settings: Default::default(),
+    }
 }
 
 pub struct Music {
     meta: MusicMeta,
     len: f64,
+    volume: f64,
+    playback_rate: f64,
+    loop_region: Option<Region>,
+    /// How long playing, pausing and stopping take to fade in or out
+    fade: Duration,
     sound_data: StaticSoundData,
     sound: StaticSoundHandle,
 }
@@ -24,6 +94,14 @@ impl Music {
         &self.meta.name
     }
 
+    pub fn display_name(&self) -> String {
+        self.meta.display_name()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.meta.path.as_deref()
+    }
+
     pub fn is_playing(&self) -> bool {
         self.sound.state() == PlaybackState::Playing
     }
@@ -34,40 +112,164 @@ impl Music {
 
     pub fn play(&mut self, audio_manager: &mut AudioManager) {
         if self.is_stopped() {
-            self.sound = audio_manager.play(self.sound_data.clone()).unwrap();
+            self.restart(audio_manager);
         } else {
-            self.sound.resume(Default::default()).unwrap();
+            self.sound.resume(self.fade_tween()).unwrap();
         }
     }
 
     pub fn pause(&mut self) {
-        self.sound.pause(Default::default()).unwrap();
+        self.sound.pause(self.fade_tween()).unwrap();
     }
 
     pub fn stop(&mut self) {
-        self.sound.stop(Default::default()).unwrap();
+        self.sound.stop(self.fade_tween()).unwrap();
+    }
+
+    /// Sets how many seconds playing, pausing and stopping fade in or out for, to avoid clicks.
+    pub fn set_fade(&mut self, seconds: f64) {
+        self.fade = Duration::from_secs_f64(seconds.max(0.0));
+    }
+
+    fn fade_tween(&self) -> Tween {
+        Tween {
+            duration: self.fade,
+            easing: Easing::InOutPowi(2),
+            ..Default::default()
+        }
     }
 
     pub fn position(&self) -> f64 {
         self.sound.position()
     }
     
+    /// Seeks by `amount` seconds from the current position.
     pub fn scrub(&mut self, amount: f64, audio_manager: &mut AudioManager) {
+        let position = self.position();
+        self.seek_to(position + amount, audio_manager);
+    }
+
+    /// Seeks to `pos_seconds`, clamped to the length of the song.
+    pub fn seek_to(&mut self, pos_seconds: f64, audio_manager: &mut AudioManager) {
         if self.is_stopped() {
-            self.sound = audio_manager.play(self.sound_data.clone()).unwrap();
+            self.restart(audio_manager);
         }
 
-        if self.position() + amount <= 0.0 {
-            self.sound.seek_to(0.0).unwrap();
-        } else {
-            self.sound.seek_by(amount).unwrap();
-        }
+        self.sound.seek_to(pos_seconds.clamp(0.0, self.len)).unwrap();
+    }
+
+    /// Seeks to `frac` of the way through the song, where 0.0 is the start and 1.0 the end.
+    pub fn seek_to_fraction(&mut self, frac: f64, audio_manager: &mut AudioManager) {
+        self.seek_to(self.len * frac, audio_manager);
     }
 
     pub fn len(&self) -> f64 {
         self.len
     }
 
+    /// Sets the playback volume, from 0.0 (muted) to 1.0 (full).
+    ///
+    /// Only the audio output is affected; the samples fed to the
+    /// synesthetizer stay the same.
+    pub fn set_volume(&mut self, volume: f64) {
+        self.volume = volume;
+        self.sound.set_volume(volume, Default::default()).unwrap();
+    }
+
+    /// Sets the playback speed as a factor of the original (1.0 is normal speed).
+    ///
+    /// `position` is still reported in seconds of the track, so the
+    /// synesthetizer keeps reading the right samples at any rate.
+    ///
+    /// The rate is kept even if the audio thread can't take it right now, and
+    /// applies the next time the sound restarts, like after it stops or the output device changes.
+    pub fn set_playback_rate(&mut self, rate: f64) {
+        self.playback_rate = rate;
+        if let Err(e) = self.sound.set_playback_rate(rate, Default::default()) {
+            log::warn!("Couldn't change the playback speed: {e}");
+        }
+    }
+
+    /// Loops between `start` and `end` seconds. `None` means the start or end of the song.
+    ///
+    /// The two are swapped if `end` comes first. A region with nothing in it is ignored.
+    pub fn set_loop_region(&mut self, start: Option<f64>, end: Option<f64>) {
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start == end => {
+                log::warn!("Not looping at {start}s, since there's nothing between A and B");
+                return;
+            }
+            (Some(start), Some(end)) => (Some(start.min(end)), Some(start.max(end))),
+            _ => (start, end),
+        };
+        let region = Region {
+            start: PlaybackPosition::Seconds(start.unwrap_or(0.0)),
+            end: end.map_or(EndPosition::EndOfAudio, |end| EndPosition::Custom(PlaybackPosition::Seconds(end))),
+        };
+        self.loop_region = Some(region);
+        if let Err(e) = self.sound.set_loop_region(region) {
+            log::warn!("Couldn't change the loop: {e}");
+        }
+    }
+
+    pub fn stop_looping(&mut self) {
+        self.loop_region = None;
+        if let Err(e) = self.sound.set_loop_region(None) {
+            log::warn!("Couldn't stop looping: {e}");
+        }
+    }
+
+    /// Moves playback over to `audio_manager`, keeping the position and whether it's paused.
+    ///
+    /// Call before the old audio manager is dropped, so the position can still be read.
+    pub fn move_to(&mut self, audio_manager: &mut AudioManager) {
+        let was_stopped = self.is_stopped();
+        let was_playing = self.is_playing();
+        let position = self.position();
+
+        self.stop();
+        if was_stopped {
+            return;
+        }
+
+        self.restart(audio_manager);
+        self.sound.seek_to(position).unwrap();
+        if !was_playing {
+            self.pause();
+        }
+    }
+
+    /// Starts a fresh sound handle, fading in and keeping the current volume, playback rate and loop.
+    fn restart(&mut self, audio_manager: &mut AudioManager) {
+        let volume = self.volume;
+        let playback_rate = self.playback_rate;
+        let loop_region = self.loop_region;
+        let fade_in = self.fade_tween();
+        let sound_data = self.sound_data.with_modified_settings(|settings| {
+            settings.volume(volume).playback_rate(playback_rate).loop_region(loop_region).fade_in_tween(fade_in)
+        });
+        self.sound = audio_manager.play(sound_data).unwrap();
+    }
+
+    /// The lowest and highest sample in each of `buckets` equal slices of the song.
+    pub fn peak_envelope(&self, buckets: usize) -> Vec<(f32, f32)> {
+        let frames = &self.sound_data.frames;
+        if buckets == 0 || frames.is_empty() {
+            return Vec::new();
+        }
+
+        (0..buckets)
+            .map(|bucket| {
+                let start = bucket * frames.len() / buckets;
+                let end = ((bucket + 1) * frames.len() / buckets).max(start + 1).min(frames.len());
+
+                frames[start..end].iter()
+                    .map(|frame| frame.as_mono().left)
+                    .fold((0.0f32, 0.0f32), |(min, max), sample| (min.min(sample), max.max(sample)))
+            })
+            .collect()
+    }
+
     pub fn data(&self) -> &StaticSoundData {
         &self.sound_data
     }
@@ -80,6 +282,7 @@ impl Music {
 pub struct MusicLoader {
     audio_manager: AudioManager,
     active_channel: Option<LoadingChannel>,
+    preloaded_channel: Option<LoadingChannel>,
 }
 
 impl MusicLoader {
@@ -87,76 +290,541 @@ impl MusicLoader {
         Self {
             audio_manager,
             active_channel: None,
+            preloaded_channel: None,
         }
     }
 
     pub fn load_from_file(&mut self, path: impl AsRef<Path>) -> MusicMeta {
-        let file_name = path.as_ref()
-            .file_name()
-            .map(|s| s.to_str().unwrap().to_owned())
-            .unwrap_or(String::from("<unreadable file name>"));
-        let name = path.as_ref()
-            .with_extension("")
-            .file_name()
-            .map(|s| s.to_str().unwrap().to_string())
-            .unwrap_or(String::from("Unknown"));
-
-        let path = path.as_ref().to_path_buf();
-
-        let join_handle = thread::spawn(move || -> anyhow::Result<StaticSoundData> {
-            let sound_data = StaticSoundData::from_file(&path, Default::default())?;
-            log::info!("Loaded");
-            Ok(sound_data)
-        });
+        let channel = match self.preloaded_channel.take() {
+            Some(channel) if channel.music_meta.path.as_deref() == Some(path.as_ref()) => channel,
+            _ => LoadingChannel::from_file(path.as_ref()),
+        };
+
+        let music_meta = channel.music_meta.clone();
+        self.active_channel = Some(channel);
+
+        music_meta
+    }
+
+    /// Starts decoding the file at `path` in the background, so that loading it later is quick.
+    pub fn preload(&mut self, path: impl AsRef<Path>) {
+        if self.preloaded_channel.as_ref().is_some_and(|channel| channel.music_meta.path.as_deref() == Some(path.as_ref())) {
+            return;
+        }
+
+        self.preloaded_channel = Some(LoadingChannel::from_file(path.as_ref()));
+    }
 
+    /// Reads all of `reader` into memory on a background thread and decodes it.
+    /// `name` is shown in place of a file name.
+    pub fn load_from_reader(&mut self, name: String, mut reader: impl Read + Send + 'static) -> MusicMeta {
         let music_meta = MusicMeta {
-            file_name,
+            path: None,
+            file_name: name.clone(),
             name,
+            title: None,
+            artist: None,
+            size: None,
         };
 
-        self.active_channel = Some(LoadingChannel {
-            music_meta: music_meta.clone(),
-            join_handle,
-        });
+        self.start_loading(music_meta, move || decode_bytes(&mut reader))
+    }
+
+    /// Downloads the audio at `url` on a background thread and decodes it.
+    pub fn load_from_url(&mut self, url: String) -> MusicMeta {
+        let music_meta = MusicMeta {
+            path: None,
+            file_name: url.clone(),
+            name: url.clone(),
+            title: None,
+            artist: None,
+            size: None,
+        };
+
+        self.start_loading(music_meta, move || {
+            let response = ureq::get(&url).call()?;
+            decode_bytes(&mut response.into_reader())
+        })
+    }
+
+    /// Generates `tone` on a background thread, with sweeps going over `sweep_range` in Hz.
+    pub fn load_test_tone(&mut self, tone: TestTone, sweep_range: (f32, f32)) -> MusicMeta {
+        let name = tone.name();
+        let music_meta = MusicMeta {
+            path: None,
+            file_name: name.clone(),
+            name,
+            title: None,
+            artist: None,
+            size: None,
+        };
+
+        self.start_loading(music_meta, move || Ok(tone.render(sweep_range)))
+    }
 
+    fn start_loading(
+        &mut self,
+        music_meta: MusicMeta,
+        load: impl FnOnce() -> anyhow::Result<StaticSoundData> + Send + 'static,
+    ) -> MusicMeta {
+        self.active_channel = Some(LoadingChannel::spawn(music_meta.clone(), move || Ok((load()?, (None, None)))));
         music_meta
     }
 
-    pub fn check_loaded(&mut self) -> Option<Music> {
-        if self.active_channel.is_some() {
-            let channel = self.active_channel.as_ref().unwrap();
-
-            if channel.join_handle.is_finished() {
-                let channel = std::mem::replace(&mut self.active_channel, None).unwrap();
-
-                match channel.join_handle.join().unwrap() {
-                    Ok(sound_data) => {
-                        let sound = self.audio_manager.play(sound_data.clone()).unwrap();
-                        let len = sound_data.frames.len() as f64 / sound_data.sample_rate as f64;
-                        
-                        return Some(Music {
-                            meta: channel.music_meta,
-                            len,
-                            sound_data,
-                            sound,
-                        });
-                    }
-                    Err(e) => {
-                        log::error!("There was a problem loading the music: {e:?}");
-                    }
-                }
-            }
+    /// Returns the music once it's done loading, or why it couldn't be loaded.
+    pub fn check_loaded(&mut self) -> Option<anyhow::Result<Music>> {
+        if !self.active_channel.as_ref()?.join_handle.is_finished() {
+            return None;
         }
 
-        None
+        let channel = self.active_channel.take().unwrap();
+        let file_name = channel.music_meta.file_name.clone();
+        let music = channel.join_handle.join()
+            .map_err(|_| anyhow::anyhow!("the loading thread panicked"))
+            .and_then(|result| result)
+            .and_then(|(sound_data, (title, artist))| {
+                let sound = self.audio_manager.play(sound_data.clone())?;
+                let len = duration(&sound_data);
+
+                Ok(Music {
+                    meta: MusicMeta { title, artist, ..channel.music_meta },
+                    len,
+                    volume: 1.0,
+                    playback_rate: 1.0,
+                    loop_region: None,
+                    fade: Duration::ZERO,
+                    sound_data,
+                    sound,
+                })
+            })
+            .with_context(|| format!("Couldn't load {file_name}"));
+
+        Some(music)
     }
 
     pub fn audio_manager_mut(&mut self) -> &mut AudioManager {
         &mut self.audio_manager
     }
+
+    /// Swaps in a new audio manager, returning the old one.
+    pub fn replace_audio_manager(&mut self, audio_manager: AudioManager) -> AudioManager {
+        std::mem::replace(&mut self.audio_manager, audio_manager)
+    }
 }
 
+/// The title and artist from a file's tags
+type Tags = (Option<String>, Option<String>);
+
 struct LoadingChannel {
     pub music_meta: MusicMeta,
-    pub join_handle: JoinHandle<anyhow::Result<StaticSoundData>>,
+    pub join_handle: JoinHandle<anyhow::Result<(StaticSoundData, Tags)>>,
+}
+
+impl LoadingChannel {
+    fn spawn(music_meta: MusicMeta, load: impl FnOnce() -> anyhow::Result<(StaticSoundData, Tags)> + Send + 'static) -> Self {
+        let join_handle = thread::spawn(move || {
+            let loaded = load()?;
+            log::info!("Loaded");
+            Ok(loaded)
+        });
+
+        Self {
+            music_meta,
+            join_handle,
+        }
+    }
+
+    /// The tags are read on the loading thread too, so they arrive with the sound data.
+    fn from_file(path: &Path) -> Self {
+        let (file_name, name) = names_from_path(path);
+        let path = path.to_path_buf();
+        let size = std::fs::metadata(&path).ok().map(|metadata| metadata.len());
+
+        let music_meta = MusicMeta {
+            path: Some(path.clone()),
+            file_name,
+            name,
+            title: None,
+            artist: None,
+            size,
+        };
+
+        Self::spawn(music_meta, move || {
+            let tags = read_tags(&path);
+            Ok((decode_file(&path)?, tags))
+        })
+    }
+}
+
+/// The file name and the name without its extension. Names that aren't valid
+/// UTF-8 are shown with replacement characters rather than refusing to load.
+fn names_from_path(path: &Path) -> (String, String) {
+    let file_name = path
+        .file_name()
+        .map_or(String::from("<unreadable file name>"), |s| s.to_string_lossy().into_owned());
+    let name = path
+        .file_stem()
+        .map_or(String::from("Unknown"), |s| s.to_string_lossy().into_owned());
+
+    (file_name, name)
+}
+
+/// Decodes a whole audio file, downmixing surround audio to stereo.
+pub fn decode_file(path: &Path) -> anyhow::Result<StaticSoundData> {
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    match StaticSoundData::from_file(path, Default::default()) {
+        Err(FromFileError::UnsupportedChannelConfiguration) => decode_downmixed(Box::new(File::open(path)?), extension),
+        Err(FromFileError::SymphoniaError(SymphoniaError::Unsupported(_))) => Err(unsupported_format(Box::new(File::open(path)?), extension)),
+        result => Ok(result?),
+    }
+}
+
+fn decode_bytes(reader: &mut impl Read) -> anyhow::Result<StaticSoundData> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let bytes: Arc<[u8]> = bytes.into();
+    match StaticSoundData::from_cursor(Cursor::new(bytes.clone()), Default::default()) {
+        Err(FromFileError::UnsupportedChannelConfiguration) => decode_downmixed(Box::new(Cursor::new(bytes)), None),
+        Err(FromFileError::SymphoniaError(SymphoniaError::Unsupported(_))) => Err(unsupported_format(Box::new(Cursor::new(bytes)), None)),
+        result => Ok(result?),
+    }
+}
+
+/// The error for audio that couldn't be decoded, saying what to do about it.
+///
+/// The format is told from the contents rather than the extension, so a misnamed file
+/// still gets the right message. Opus is called out, since its Ogg container can be read
+/// but there's no decoder for the audio inside.
+fn unsupported_format(source: Box<dyn MediaSource>, extension: Option<&str>) -> anyhow::Error {
+    if probe_codec(source, extension) == Some(CODEC_TYPE_OPUS) {
+        anyhow!("Opus audio isn't supported. Converting it to Ogg Vorbis or FLAC will make it playable")
+    } else {
+        anyhow!("this audio format isn't supported. Try one of: {}", supported_extensions().join(", "))
+    }
+}
+
+/// The codec of the default track, if the container can be read.
+fn probe_codec(source: Box<dyn MediaSource>, extension: Option<&str>) -> Option<CodecType> {
+    let source = MediaSourceStream::new(source, Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+    let codec = probed.format.default_track()?.codec_params.codec;
+    Some(codec)
+}
+
+/// Decodes audio with any number of channels to stereo, which kira only does for mono and stereo audio.
+fn decode_downmixed(source: Box<dyn MediaSource>, extension: Option<&str>) -> anyhow::Result<StaticSoundData> {
+    let source = MediaSourceStream::new(source, Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let mut format = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?
+        .format;
+
+    let track = format.default_track().context("there's no audio track")?;
+    let track_id = track.id;
+    let sample_rate = track.codec_params.sample_rate.context("the sample rate is unknown")?;
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut frames = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged packet is skipped, like kira does
+            Err(SymphoniaError::DecodeError(e)) => {
+                log::warn!("Skipped a packet that couldn't be decoded: {e}");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let weights = downmix_weights(spec.channels);
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        frames.extend(samples.samples().chunks_exact(weights.len()).map(|samples| downmix(samples, &weights)));
+    }
+
+    Ok(StaticSoundData {
+        sample_rate,
+        frames: frames.into(),
+        settings: Default::default(),
+    })
+}
+
+/// How much each of `channels`, in order, goes into the left and right channels of a stereo downmix.
+///
+/// Centre channels go into both sides at -3 dB, surrounds into their side at -3 dB, and LFE is dropped.
+/// The weights are scaled down so the downmix can't clip.
+fn downmix_weights(channels: Channels) -> Vec<(f32, f32)> {
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    let left = Channels::FRONT_LEFT | Channels::FRONT_LEFT_CENTRE | Channels::FRONT_LEFT_WIDE | Channels::FRONT_LEFT_HIGH;
+    let right = Channels::FRONT_RIGHT | Channels::FRONT_RIGHT_CENTRE | Channels::FRONT_RIGHT_WIDE | Channels::FRONT_RIGHT_HIGH;
+    let left_surround = Channels::REAR_LEFT | Channels::SIDE_LEFT | Channels::REAR_LEFT_CENTRE | Channels::TOP_FRONT_LEFT | Channels::TOP_REAR_LEFT;
+    let right_surround = Channels::REAR_RIGHT | Channels::SIDE_RIGHT | Channels::REAR_RIGHT_CENTRE | Channels::TOP_FRONT_RIGHT | Channels::TOP_REAR_RIGHT;
+    let lfe = Channels::LFE1 | Channels::LFE2;
+
+    let weights: Vec<(f32, f32)> = channels.iter()
+        .map(|channel| {
+            if left.contains(channel) {
+                (1.0, 0.0)
+            } else if right.contains(channel) {
+                (0.0, 1.0)
+            } else if left_surround.contains(channel) {
+                (FRAC_1_SQRT_2, 0.0)
+            } else if right_surround.contains(channel) {
+                (0.0, FRAC_1_SQRT_2)
+            } else if lfe.contains(channel) {
+                (0.0, 0.0)
+            } else {
+                (FRAC_1_SQRT_2, FRAC_1_SQRT_2)
+            }
+        })
+        .collect();
+
+    let (left_total, right_total) = weights.iter().fold((0.0, 0.0), |(l, r), (left, right)| (l + left, r + right));
+    let scale = 1.0 / f32::max(left_total, right_total).max(1.0);
+    weights.into_iter().map(|(left, right)| (left * scale, right * scale)).collect()
+}
+
+fn downmix(samples: &[f32], weights: &[(f32, f32)]) -> Frame {
+    samples.iter().zip(weights).fold(Frame::ZERO, |frame, (&sample, &(left, right))| {
+        Frame::new(frame.left + sample * left, frame.right + sample * right)
+    })
+}
+
+/// The title and artist in the ID3, Vorbis or other tags of the file at `path`.
+///
+/// Missing tags, or files that can't be read, give `None`.
+fn read_tags(path: &Path) -> Tags {
+    let Ok(file) = File::open(path) else {
+        return (None, None);
+    };
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let Ok(mut probed) = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+    else {
+        return (None, None);
+    };
+
+    let (mut title, mut artist) = (None, None);
+    let mut read_revision = |revision: &MetadataRevision| {
+        for tag in revision.tags() {
+            let value = tag.value.to_string().trim().to_owned();
+            if value.is_empty() {
+                continue;
+            }
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => title = Some(value),
+                Some(StandardTagKey::Artist) => artist = Some(value),
+                _ => {}
+            }
+        }
+    };
+
+    // Tags can come before the container, like ID3 in MP3s, or inside it
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|metadata| metadata.current()) {
+        read_revision(revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read_revision(revision);
+    }
+
+    (title, artist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{settings_window::Settings, synesthetizer::{self, Synesthetizer}};
+
+    fn meta(title: Option<&str>, artist: Option<&str>) -> MusicMeta {
+        MusicMeta {
+            path: None,
+            file_name: String::from("track.mp3"),
+            name: String::from("track"),
+            title: title.map(String::from),
+            artist: artist.map(String::from),
+            size: None,
+        }
+    }
+
+    #[test]
+    fn display_name_prefers_tags() {
+        assert_eq!(meta(Some("Title"), Some("Artist")).display_name(), "Artist — Title");
+        assert_eq!(meta(Some("Title"), None).display_name(), "Title");
+        // An artist alone doesn't say which track it is
+        assert_eq!(meta(None, Some("Artist")).display_name(), "track");
+        assert_eq!(meta(None, None).display_name(), "track");
+    }
+
+    /// A 16-bit, 8000 Hz PCM WAV file of interleaved `samples` with `channels` channels.
+    fn wav(channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes());
+        wav.extend(channels.to_le_bytes());
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend((8000 * 2 * channels as u32).to_le_bytes());
+        wav.extend((2 * channels).to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        for sample in samples {
+            wav.extend(sample.to_le_bytes());
+        }
+        wav
+    }
+
+    #[test]
+    fn untagged_and_missing_files_have_no_tags() {
+        let path = std::env::temp_dir().join(format!("synesthetic_screen_untagged_{}.wav", std::process::id()));
+        std::fs::write(&path, wav(1, &[0; 2])).unwrap();
+
+        assert_eq!(read_tags(&path), (None, None));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_tags(&path), (None, None));
+    }
+
+    #[test]
+    fn mono_files_have_the_same_samples_on_both_sides() {
+        let samples = [0, 8192, 16384, -16384];
+        let sound_data = decode_bytes(&mut wav(1, &samples).as_slice()).unwrap();
+        assert_eq!(sound_data.frames.len(), samples.len());
+        for (frame, sample) in sound_data.frames.iter().zip(samples) {
+            let expected = sample as f32 / 32768.0;
+            assert_eq!((frame.left, frame.right), (expected, expected));
+            assert_eq!(frame.as_mono().left, expected);
+        }
+    }
+
+    #[test]
+    fn files_shorter_than_a_frame_can_be_visualized() {
+        let samples: Vec<i16> = (0..100).map(|i| ((i as f32 * 0.3).sin() * 16384.0) as i16).collect();
+        let sound_data = decode_bytes(&mut wav(1, &samples).as_slice()).unwrap();
+        assert_eq!(duration(&sound_data), 100.0 / 8000.0);
+        assert_eq!(duration(&sound_data_from_frames(Vec::new(), 8000)), 0.0);
+        assert_eq!(duration(&sound_data_from_frames(Vec::new(), 0)), 0.0);
+
+        let settings = Settings::default();
+        let mut synesthetizer = Synesthetizer::new(&settings);
+        // At the start, and past the end where there's nothing left to read
+        for position in [0.0, 0.01, 1.0] {
+            synesthetizer.render_at(&sound_data, position, &settings);
+            assert!(synesthetizer.current_notes().iter().all(|note| note.amplitude().is_finite() && note.frequency().is_finite()));
+        }
+        assert_eq!(synesthetizer::video_frame_count(&sound_data, settings.fps), 1);
+    }
+
+    #[test]
+    fn surround_files_are_downmixed_to_stereo() {
+        // 5.1 is front left, front right, centre, LFE, rear left and rear right
+        let frames = [
+            [16384, 0, 0, 0, 0, 0],
+            [0, 0, 16384, 0, 0, 0],
+            [0, 0, 0, 16384, 0, 0],
+            [0, 0, 0, 0, 0, 16384],
+        ];
+        let sound_data = decode_bytes(&mut wav(6, frames.as_flattened()).as_slice()).unwrap();
+        assert_eq!(sound_data.frames.len(), frames.len());
+
+        let [left, centre, lfe, rear_right] = [0, 1, 2, 3].map(|idx| sound_data.frames[idx]);
+        assert!(left.left > 0.0 && left.right == 0.0);
+        assert!(centre.left > 0.0 && centre.left == centre.right);
+        assert_eq!((lfe.left, lfe.right), (0.0, 0.0));
+        assert!(rear_right.left == 0.0 && rear_right.right > 0.0);
+        // Full scale on every channel still fits
+        let loudest = downmix(&[1.0; 6], &downmix_weights(Channels::from_bits(0b11_1111).unwrap()));
+        assert!(loudest.left <= 1.0 && loudest.right <= 1.0);
+    }
+
+    #[test]
+    fn names_from_path_strips_the_extension() {
+        assert_eq!(names_from_path(Path::new("music/song.flac")), (String::from("song.flac"), String::from("song")));
+        assert_eq!(names_from_path(Path::new("/")), (String::from("<unreadable file name>"), String::from("Unknown")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_dont_panic() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new(OsStr::from_bytes(b"music/caf\xe9.mp3"));
+        assert_eq!(names_from_path(path), (String::from("caf\u{FFFD}.mp3"), String::from("caf\u{FFFD}")));
+    }
+
+    #[test]
+    fn only_decodable_extensions_are_accepted() {
+        for name in ["song.flac", "song.OGG", "song.oga", "song.mp3", "song.wav"] {
+            assert!(is_audio_file(Path::new(name)), "{name}");
+        }
+        for name in ["song.opus", "song.txt", "song"] {
+            assert!(!is_audio_file(Path::new(name)), "{name}");
+        }
+    }
+
+    /// Page `number` of an Ogg stream, holding one packet, with the checksum Ogg readers verify.
+    /// `flags` are 2 for the first page and 4 for the last.
+    fn ogg_page(number: u32, flags: u8, granule_position: u64, packet: &[u8]) -> Vec<u8> {
+        let mut page = Vec::new();
+        page.extend(b"OggS");
+        page.extend([0, flags]);
+        page.extend(granule_position.to_le_bytes());
+        // Serial number, page number, and the checksum, filled in below
+        page.extend([1, number, 0].iter().flat_map(|n| n.to_le_bytes()));
+        page.extend([1, packet.len() as u8]);
+        page.extend(packet);
+
+        let crc = page.iter().fold(0u32, |crc, &byte| {
+            (0..8).fold(crc ^ (byte as u32) << 24, |crc, _| if crc & 0x8000_0000 != 0 { crc << 1 ^ 0x04c1_1db7 } else { crc << 1 })
+        });
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn unsupported_formats_say_so() {
+        // The identification header of a stereo, 48 kHz Ogg Opus stream
+        let mut opus_head = b"OpusHead".to_vec();
+        opus_head.extend([1, 2]);
+        opus_head.extend(312u16.to_le_bytes());
+        opus_head.extend(48000u32.to_le_bytes());
+        opus_head.extend([0, 0, 0]);
+        let mut opus_tags = b"OpusTags".to_vec();
+        opus_tags.extend([0; 8]);
+        // One 20 ms frame of silence
+        let mut opus = ogg_page(0, 2, 0, &opus_head);
+        opus.extend(ogg_page(1, 0, 0, &opus_tags));
+        opus.extend(ogg_page(2, 4, 960 + 312, &[0xf8, 0xff, 0xfe]));
+        let error = decode_bytes(&mut opus.as_slice()).unwrap_err();
+        assert!(error.to_string().contains("Opus"), "{error}");
+
+        let error = decode_bytes(&mut b"This isn't audio at all.".as_slice()).unwrap_err();
+        assert!(error.to_string().contains("isn't supported"), "{error}");
+    }
 }
@@ -1,9 +1,13 @@
 use std::ops::Range;
 
-use image::{imageops, Rgba, RgbaImage};
-use imageproc::{drawing, pixelops, point::Point};
+use image::{Rgba, RgbaImage};
+use imageproc::{pixelops, rect::Rect};
 
-use crate::synesthetizer::{ColorPalette, FRAME_HEIGHT_F32, FRAME_WIDTH_F32};
+use crate::{settings_window::{BlendMode, ColorMode, NoteLayout, NoteShape, NoteSizing, PitchLayout, Settings}, synesthetizer::ColorPalette};
+
+pub const STANDARD_A4_FREQ: f32 = 440.;
+
+pub const PITCH_CLASS_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
 
 #[derive(Debug, Clone, Copy)]
 pub struct Pitch {
@@ -12,18 +16,31 @@ pub struct Pitch {
 }
 
 impl Pitch {
+    /// At standard tuning, where A4 is 440 Hz.
+    pub fn from_midi(midi: f32) -> Self {
+        Self::from_midi_tuned(midi, STANDARD_A4_FREQ)
+    }
+
+    /// At standard tuning, where A4 is 440 Hz. The app always goes by `Settings::a4_frequency`.
+    #[cfg(test)]
     pub fn from_frequency(fr: f32) -> Self {
+        Self::from_frequency_tuned(fr, STANDARD_A4_FREQ)
+    }
+
+    /// With A4, MIDI note 69, at `a4_frequency` Hz.
+    pub fn from_frequency_tuned(fr: f32, a4_frequency: f32) -> Self {
         // source: <https://newt.phys.unsw.edu.au/jw/notes.html>
         Self {
             frequency: fr,
-            midi: 12. * (fr / 440.).log2() + 69.
+            midi: 12. * (fr / a4_frequency).log2() + 69.
         }
     }
 
-    pub fn from_midi(midi: f32) -> Self {
+    /// With A4, MIDI note 69, at `a4_frequency` Hz.
+    pub fn from_midi_tuned(midi: f32, a4_frequency: f32) -> Self {
         // source: <https://newt.phys.unsw.edu.au/jw/notes.html>
         Self {
-            frequency: 2f32.powf((midi - 69.) / 12.) * 440.,
+            frequency: 2f32.powf((midi - 69.) / 12.) * a4_frequency,
             midi,
         }
     }
@@ -35,6 +52,18 @@ impl Pitch {
     pub fn frequency(&self) -> f32 {
         self.frequency
     }
+
+    /// How far the pitch is from the nearest note, from -50 to 50 cents.
+    pub fn cents(&self) -> f32 {
+        (self.midi - self.midi.round()) * 100.
+    }
+
+    /// The nearest note in scientific pitch notation, like "A4" or "C#5".
+    pub fn name(&self) -> String {
+        let midi = self.midi.round() as i32;
+        let octave = midi.div_euclid(12) - 1;
+        format!("{}{octave}", PITCH_CLASS_NAMES[midi.rem_euclid(12) as usize])
+    }
 }
 
 impl PartialEq for Pitch {
@@ -45,24 +74,38 @@ impl PartialEq for Pitch {
 
 pub struct SoundInclusionError;
 
+/// Bounds for either side of a note, at a frame height of 900 pixels
+const MIN_NOTE_SIZE: f32 = 3.;
+const MAX_NOTE_SIZE: f32 = 600.;
+/// Width times height of a note, at a frame height of 900 pixels
+const NOTE_AREA: f32 = 5000.;
+/// With decibel sizing, notes this many dB below full amplitude are the smallest,
+/// and full amplitude is as tall as with linear sizing
+const DECIBEL_FLOOR: f32 = -60.;
+const FULL_AMPLITUDE_HEIGHT: f32 = 100. + MIN_NOTE_SIZE;
+/// At full `color_jitter`, how far a note's hue can move either way, as a fraction of the color wheel
+const MAX_HUE_JITTER: f32 = 1. / 24.;
+/// At full `color_jitter`, how much brighter or darker a note can get
+const MAX_BRIGHTNESS_JITTER: f32 = 0.25;
+
 #[derive(Debug, Clone)]
 pub struct Note {
     pub peak_pitch: Pitch,
     pub peak_amplitude: f32,
     pub amp_range: Range<f32>,
     pub midi_range: Range<f32>,
+    /// Position of this note among the current notes, from lowest to highest pitch.
+    pub pitch_rank: usize,
 }
 
 impl Note {
-    const MAX_MIDI_RANGE: f32 = 1.0;
-    const MAX_AMPLITUDE_RANGE: f32 = 0.25;
-
     pub fn new(pitch: Pitch, amplitude: f32) -> Self {
         Self {
             peak_pitch: pitch,
             peak_amplitude: amplitude,
             amp_range: amplitude..amplitude,
             midi_range: pitch.midi..pitch.midi,
+            pitch_rank: 0,
         }
     }
 
@@ -82,115 +125,642 @@ impl Note {
         dist_from_range_bounds(midi, &self.midi_range)
     }
 
-    pub fn try_include(&mut self, pitch: Pitch, amplitude: f32) -> Result<(), SoundInclusionError> {
+    /// Widens the note to include a bin, unless that would stretch it past
+    /// `settings.max_midi_range` or `settings.max_amplitude_range`.
+    pub fn try_include(&mut self, pitch: Pitch, amplitude: f32, settings: &Settings) -> Result<(), SoundInclusionError> {
         if {
             dist_from_range_bounds(pitch.midi, &self.midi_range) + range_len(&self.midi_range)
-                > Self::MAX_MIDI_RANGE
+                > settings.max_midi_range
                 ||
             dist_from_range_bounds(amplitude, &self.amp_range) + range_len(&self.amp_range)
-                > Self::MAX_AMPLITUDE_RANGE
+                > settings.max_amplitude_range
         } {
             Err(SoundInclusionError)
         } else {
-            include_in_range(amplitude, &mut self.amp_range);
-            include_in_range(pitch.midi, &mut self.midi_range);
+            self.include(pitch, amplitude);
+            Ok(())
+        }
+    }
 
-            if amplitude > self.peak_amplitude {
-                self.peak_amplitude = amplitude;
-                self.peak_pitch = pitch;
-            }
+    /// Widens the note to include a bin, however far away it is.
+    pub fn include(&mut self, pitch: Pitch, amplitude: f32) {
+        include_in_range(amplitude, &mut self.amp_range);
+        include_in_range(pitch.midi, &mut self.midi_range);
 
-            Ok(())
+        if amplitude > self.peak_amplitude {
+            self.peak_amplitude = amplitude;
+            self.peak_pitch = pitch;
         }
     }
 
-    pub fn paint(&self, image: &mut RgbaImage, color_palette: &ColorPalette) {
-        let width = self.width() as i32;
-        let height = self.height() as i32;
+    /// Paints the note into `area` of `image`, which is treated as the whole frame.
+    /// `note_count` is the number of notes painted this frame.
+    ///
+    /// The shape is drawn straight into the frame, with edges smoothed by how much
+    /// of each pixel the shape covers, so nothing is allocated per note.
+    pub fn paint(&self, image: &mut RgbaImage, area: Rect, color_palette: &ColorPalette, settings: &Settings, note_count: usize) {
+        let frame_height = area.height();
+        let width = self.width(frame_height, settings.note_sizing);
+        let height = self.height(frame_height, settings.note_sizing);
 
         if width == 0 || height == 0 {
             return;
         }
 
-        let polygon = [
-            Point::new(0, height / 2),
-            Point::new(width / 2, 0),
-            Point::new(width, height / 2),
-            Point::new(width / 2, height),
-        ];
-        
-        let x = self.x() - (width / 2);
-        let y = self.y() - (height / 2);
+        let (center_x, center_y) = self.center(area, settings, note_count);
+        let (half_width, half_height) = (width as f32 / 2., height as f32 / 2.);
+
+        // Only the pixels under the note, and inside both the area and the image
+        let left = ((center_x - half_width).floor() as i32).max(area.left()).max(0);
+        let top = ((center_y - half_height).floor() as i32).max(area.top()).max(0);
+        let right = ((center_x + half_width).ceil() as i32).min(area.right() + 1).min(image.width() as i32);
+        let bottom = ((center_y + half_height).ceil() as i32).min(area.bottom() + 1).min(image.height() as i32);
+
+        let color = self.color(color_palette, settings);
+        for y in top..bottom {
+            for x in left..right {
+                let dx = x as f32 + 0.5 - center_x;
+                let dy = y as f32 + 0.5 - center_y;
+                let distance = signed_distance(settings.note_shape, dx, dy, half_width, half_height);
+                // A pixel is fully covered half a pixel inside the edge, and empty half a pixel outside
+                let coverage = (0.5 - distance).clamp(0., 1.);
+                if coverage > 0. {
+                    blend(image.get_pixel_mut(x as u32, y as u32), color, coverage, settings.blend_mode);
+                }
+            }
+        }
+    }
 
-        let mut star = RgbaImage::new(self.width(), self.height());
-        drawing::draw_polygon_mut(
-            &mut star,
-            polygon.as_slice(),
-            self.color(color_palette)
-        );
+    /// Where the middle of the note is painted in `area`.
+    ///
+    /// Notes at the very ends of the range are moved in so that all of them stays in `area`,
+    /// instead of being cut in half or, for wide ones, off the edge altogether.
+    pub fn center(&self, area: Rect, settings: &Settings, note_count: usize) -> (f32, f32) {
+        let x = area.left() + self.x(settings, area.width());
+        let y = area.top() + self.y(settings.note_layout, note_count, area.height());
+        let half_width = self.width(area.height(), settings.note_sizing) as f32 / 2.;
+        let half_height = self.height(area.height(), settings.note_sizing) as f32 / 2.;
+        (
+            clamp_inside(x as f32, area.left() as f32 + half_width, (area.right() + 1) as f32 - half_width),
+            clamp_inside(y as f32, area.top() as f32 + half_height, (area.bottom() + 1) as f32 - half_height),
+        )
+    }
 
-        imageops::overlay(image, &star, x as i64, y as i64);
+    /// Sizes are designed for a 900 pixel high frame and scaled to `frame_height`.
+    pub fn width(&self, frame_height: u32, sizing: NoteSizing) -> u32 {
+        // Louder notes are taller and narrower
+        let width = (NOTE_AREA / self.unscaled_height(sizing)).clamp(MIN_NOTE_SIZE, MAX_NOTE_SIZE);
+        (width * Self::scale(frame_height)).round() as u32
     }
 
-    pub fn width(&self) -> u32 {
-        (2500 / self.height()) * 2
+    pub fn height(&self, frame_height: u32, sizing: NoteSizing) -> u32 {
+        (self.unscaled_height(sizing) * Self::scale(frame_height)).round() as u32
     }
 
-    pub fn height(&self) -> u32 {
-        (self.amplitude() * 100.).ceil() as u32 + 3
+    /// The height at a frame height of 900 pixels
+    fn unscaled_height(&self, sizing: NoteSizing) -> f32 {
+        let amplitude = self.amplitude().max(0.);
+        let height = match sizing {
+            NoteSizing::Linear => amplitude * 100. + MIN_NOTE_SIZE,
+            NoteSizing::Decibels => {
+                // Silence is negative infinity dB, which the clamp below turns into the smallest size
+                let decibels = 20. * amplitude.log10();
+                MIN_NOTE_SIZE + (1. - decibels / DECIBEL_FLOOR) * (FULL_AMPLITUDE_HEIGHT - MIN_NOTE_SIZE)
+            }
+        };
+        height.clamp(MIN_NOTE_SIZE, MAX_NOTE_SIZE)
     }
 
-    pub fn x(&self) -> i32 {
-        (FRAME_WIDTH_F32 * (self.midi()/127.)).round() as i32
+    fn scale(frame_height: u32) -> f32 {
+        frame_height as f32 / 900.
     }
 
-    pub fn y(&self) -> i32 {
-        (FRAME_HEIGHT_F32 / 2.).round() as i32
+    pub fn x(&self, settings: &Settings, frame_width: u32) -> i32 {
+        let position = match settings.pitch_layout {
+            PitchLayout::Linear => self.midi() / 127.,
+            PitchLayout::Logarithmic => {
+                let (min_frequency, max_frequency) = settings.frequency_range();
+                (self.frequency() / min_frequency).ln() / (max_frequency / min_frequency).ln()
+            }
+        };
+
+        (frame_width as f32 * position.clamp(0., 1.)).round() as i32
     }
 
-    pub fn color(&self, color_palette: &ColorPalette) -> Rgba<u8> {
-        let midi = self.midi();
-        let diatonic_note = midi % 12.;
-
-        let ceil = color_palette.0[{
-            let ceil = diatonic_note.ceil() as usize;
-            if ceil == 12 {
-                0
-            } else {
-                ceil
+    pub fn y(&self, layout: NoteLayout, note_count: usize, frame_height: u32) -> i32 {
+        const OCTAVES: f32 = 9.; // C0 to B8
+
+        let position = match layout {
+            NoteLayout::Center => 0.5,
+            NoteLayout::ByOctave => {
+                let octave = (self.midi() / 12.).floor() - 1.;
+                1. - (octave.clamp(0., OCTAVES - 1.) + 0.5) / OCTAVES
             }
-        }];
-        let floor = color_palette.0[diatonic_note.floor() as usize];
-        
-        let fractional = diatonic_note % 1.;
+            NoteLayout::Spread => 1. - (self.pitch_rank + 1) as f32 / (note_count + 1) as f32,
+        };
+
+        (frame_height as f32 * position).round() as i32
+    }
 
-        let mut color = pixelops::interpolate(ceil, floor, fractional);
-        color = pixelops::interpolate(color, Rgba([0, 0, 0, 0]), self.amplitude().sqrt() * 0.5);
+    /// The palette color between the two nearest pitch classes, darkened for quieter notes
+    /// by `settings.color_brightness * amplitude ^ settings.color_gamma`.
+    pub fn color(&self, color_palette: &ColorPalette, settings: &Settings) -> Rgba<u8> {
+        let midi = self.midi();
+        let (floor, ceil, fractional) = pitch_classes_around(midi);
+        let (floor, ceil) = (color_palette.0[floor], color_palette.0[ceil]);
+
+        let mut color = match settings.color_mode {
+            ColorMode::ChromaticWheel => interpolate_hue(floor, ceil, fractional),
+            ColorMode::Palette | ColorMode::OctaveBrightness => pixelops::interpolate(ceil, floor, fractional),
+        };
+        if settings.color_jitter > 0. {
+            color = jitter(color, midi, settings.color_jitter);
+        }
+        if settings.color_mode == ColorMode::OctaveBrightness {
+            // From dim at C0 to full at C8
+            let brightness = 0.3 + 0.7 * ((midi - 12.) / 96.).clamp(0., 1.);
+            for channel in &mut color.0[..3] {
+                *channel = (*channel as f32 * brightness).round() as u8;
+            }
+        }
+        let intensity = self.amplitude().max(0.).powf(settings.color_gamma) * settings.color_brightness;
+        color = pixelops::interpolate(color, Rgba([0, 0, 0, 0]), intensity);
 
         color
     }
 }
 
-fn dist_from_range_bounds(v: f32, r: &Range<f32>) -> f32 {
-    if r.contains(&v) {
+/// The pitch classes just below and above `midi`, from 0 for C to 11 for B,
+/// and how far it is from the one below to the one above, from 0.0 to 1.0.
+///
+/// Works for any `midi`, including notes below C-1 and past G9 that a widened frequency
+/// range or an unusual tuning can find, so palette lookups never go out of bounds.
+fn pitch_classes_around(midi: f32) -> (usize, usize, f32) {
+    if !midi.is_finite() {
+        return (0, 0, 0.);
+    }
+    let pitch_class = midi.rem_euclid(12.);
+    // `rem_euclid` rounds up to 12.0 for tiny negative values
+    let floor = pitch_class.floor() as usize % 12;
+    let fractional = pitch_class.fract();
+    (floor, (floor + 1) % 12, fractional)
+}
+
+/// Nudges the hue and brightness of `color` by up to `amount` of their limits, by an amount
+/// that only depends on the semitone nearest `midi`, so a note keeps its exact color from
+/// frame to frame, through vibrato and while it fades at the center of its band.
+fn jitter(color: Rgba<u8>, midi: f32, amount: f32) -> Rgba<u8> {
+    // SplitMix64's finalizer, to scatter neighboring semitones
+    let mut hash = midi.round() as i64 as u64;
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+    // Two values from -1.0 to 1.0
+    let hue_offset = (hash & 0xffff) as f32 / 32767.5 - 1.;
+    let brightness_offset = ((hash >> 16) & 0xffff) as f32 / 32767.5 - 1.;
+
+    let (hue, saturation, value) = to_hsv(color);
+    let [r, g, b] = from_hsv(
+        (hue + hue_offset * amount * MAX_HUE_JITTER).rem_euclid(1.),
+        saturation,
+        (value * (1. + brightness_offset * amount * MAX_BRIGHTNESS_JITTER)).clamp(0., 1.),
+    );
+    Rgba([r, g, b, color[3]])
+}
+
+/// Mixes `from` and `to` the short way around the hue circle, `t` of the way to `to`.
+/// Saturation, value and alpha are mixed straight.
+fn interpolate_hue(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let (from_hue, from_saturation, from_value) = to_hsv(from);
+    let (to_hue, to_saturation, to_value) = to_hsv(to);
+    // Grays have no hue of their own, so they take the other color's
+    let (from_hue, to_hue) = match (from_saturation == 0., to_saturation == 0.) {
+        (true, false) => (to_hue, to_hue),
+        (false, true) => (from_hue, from_hue),
+        _ => (from_hue, to_hue),
+    };
+
+    let mut hue_change = (to_hue - from_hue).rem_euclid(1.);
+    if hue_change > 0.5 {
+        hue_change -= 1.;
+    }
+    let mix = |from: f32, to: f32| from + (to - from) * t;
+    let [r, g, b] = from_hsv(
+        (from_hue + hue_change * t).rem_euclid(1.),
+        mix(from_saturation, to_saturation),
+        mix(from_value, to_value),
+    );
+    Rgba([r, g, b, mix(from[3] as f32, to[3] as f32).round() as u8])
+}
+
+/// Hue, saturation and value, each from 0.0 to 1.0
+fn to_hsv(Rgba([r, g, b, _]): Rgba<u8>) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+    let hue = if chroma == 0. {
         0.
-    } else if v > r.start {
-        v - r.start
+    } else if max == r {
+        ((g - b) / chroma).rem_euclid(6.)
+    } else if max == g {
+        (b - r) / chroma + 2.
     } else {
-        r.end - v
-    }
+        (r - g) / chroma + 4.
+    };
+    let saturation = if max == 0. { 0. } else { chroma / max };
+    (hue / 6., saturation, max)
 }
 
-fn include_in_range(v: f32, r: &mut Range<f32>) {
-    if !r.contains(&v) {
-        if v > r.start {
-            r.start = v;
-        } else if v < r.end {
-            r.end = v;
+fn from_hsv(hue: f32, saturation: f32, value: f32) -> [u8; 3] {
+    let chroma = value * saturation;
+    let sector = hue * 6.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.),
+        1 => (x, chroma, 0.),
+        2 => (0., chroma, x),
+        3 => (0., x, chroma),
+        4 => (x, 0., chroma),
+        _ => (chroma, 0., x),
+    };
+    let m = value - chroma;
+    [r, g, b].map(|channel| ((channel + m) * 255.).round() as u8)
+}
+
+/// Roughly how far a point is outside a shape centered on the origin that fills a box
+/// `half_width` by `half_height` from the center, in pixels. Negative inside the shape.
+fn signed_distance(shape: NoteShape, dx: f32, dy: f32, half_width: f32, half_height: f32) -> f32 {
+    let (a, b) = (half_width, half_height);
+    match shape {
+        NoteShape::Star => {
+            // Distance to the nearest edge of the diamond
+            let level = dx.abs() / a + dy.abs() / b - 1.;
+            level / (1. / (a * a) + 1. / (b * b)).sqrt()
         }
+        NoteShape::Circle => {
+            let radius = ((dx / a).powi(2) + (dy / b).powi(2)).sqrt();
+            if radius == 0. {
+                return -a.min(b);
+            }
+            // First-order distance to the ellipse, from the gradient of `radius`
+            let gradient = ((dx / (a * a)).powi(2) + (dy / (b * b)).powi(2)).sqrt() / radius;
+            (radius - 1.) / gradient
+        }
+        NoteShape::Square => (dx.abs() - a).max(dy.abs() - b),
+        NoteShape::Triangle => {
+            // Apex at the top middle, base along the bottom
+            let side = (2. * b * dx.abs() - a * (dy + b)) / (4. * b * b + a * a).sqrt();
+            side.max(dy - b)
+        }
+    }
+}
+
+/// Draws `color` over a premultiplied pixel, scaled by `coverage` from 0.0 to 1.0.
+pub fn blend_over(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f32) {
+    blend(pixel, color, coverage, BlendMode::Over);
+}
+
+/// Combines `color` with a premultiplied pixel in `mode`, scaled by `coverage` from 0.0 to 1.0.
+pub fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f32, mode: BlendMode) {
+    let source_alpha = color[3] as f32 / 255. * coverage;
+    let destination_alpha = pixel[3] as f32 / 255.;
+    // Coverage adds up the same way whatever the colors do
+    let alpha = source_alpha + destination_alpha * (1. - source_alpha);
+    for (destination, source) in pixel.0.iter_mut().zip(color.0).take(3) {
+        let (source, destination_value) = (source as f32 / 255. * coverage, *destination as f32 / 255.);
+        let blended = match mode {
+            BlendMode::Over => source + destination_value * (1. - source_alpha),
+            BlendMode::Additive => source + destination_value,
+            BlendMode::Screen => source + destination_value - source * destination_value,
+            // The lighter of the two where both are there, and whichever is there where only one is
+            BlendMode::Max => (source * destination_alpha).max(destination_value * source_alpha)
+                + source * (1. - destination_alpha)
+                + destination_value * (1. - source_alpha),
+        };
+        // Premultiplied colors can't be brighter than they are opaque
+        *destination = (blended.min(alpha) * 255.).round() as u8;
     }
+    pixel[3] = (alpha.min(1.) * 255.).round() as u8;
+}
+
+// Ranges go from `start`, the lowest value, to `end`, the highest, both inclusive.
+
+fn dist_from_range_bounds(v: f32, r: &Range<f32>) -> f32 {
+    if v < r.start {
+        r.start - v
+    } else if v > r.end {
+        v - r.end
+    } else {
+        0.
+    }
+}
+
+fn include_in_range(v: f32, r: &mut Range<f32>) {
+    r.start = r.start.min(v);
+    r.end = r.end.max(v);
 }
 
 fn range_len(r: &Range<f32>) -> f32 {
     r.end - r.start
 }
+
+/// Clamps `value` to `min..=max`, or puts it halfway between them if the range is empty.
+fn clamp_inside(value: f32, min: f32, max: f32) -> f32 {
+    if min > max {
+        (min + max) / 2.
+    } else {
+        value.clamp(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!((actual - expected).abs() <= tolerance, "{actual} isn't within {tolerance} of {expected}");
+    }
+
+    #[test]
+    fn pitches_match_reference_values() {
+        // Frequency, MIDI note and name, at standard tuning
+        let references = [
+            (27.5, 21., "A0"),
+            (261.63, 60., "C4"),
+            (440., 69., "A4"),
+            (493.88, 71., "B4"),
+            (523.25, 72., "C5"),
+            (4186.01, 108., "C8"),
+        ];
+        for (frequency, midi, name) in references {
+            let pitch = Pitch::from_frequency(frequency);
+            assert_near(pitch.midi(), midi, 0.001);
+            assert_eq!(pitch.name(), name);
+            // The reference frequencies are rounded to the hundredth
+            assert_near(Pitch::from_midi(midi).frequency(), frequency, 0.01);
+        }
+    }
+
+    #[test]
+    fn frequency_and_midi_round_trip() {
+        for a4_frequency in [432., STANDARD_A4_FREQ, 444.] {
+            for tenths in 0..=1270 {
+                let midi = tenths as f32 / 10.;
+                let frequency = Pitch::from_midi_tuned(midi, a4_frequency).frequency();
+                let round_trip = Pitch::from_frequency_tuned(frequency, a4_frequency);
+                assert_near(round_trip.midi(), midi, 0.0005);
+                assert_near(Pitch::from_midi_tuned(round_trip.midi(), a4_frequency).frequency(), frequency, frequency * 0.00001);
+            }
+        }
+    }
+
+    #[test]
+    fn exact_notes_keep_their_pitch_class() {
+        // A note a hair under a C must still round to C, or it gets the color and name of the B below
+        for midi in 0..=127 {
+            let pitch = Pitch::from_frequency(Pitch::from_midi(midi as f32).frequency());
+            assert_eq!(pitch.midi().round() as i32, midi);
+            assert_near(pitch.cents(), 0., 0.05);
+        }
+    }
+
+    #[test]
+    fn notes_at_the_ends_of_the_range_stay_in_the_frame() {
+        let settings = Settings { pitch_layout: PitchLayout::Linear, ..Default::default() };
+        let area = Rect::at(0, 0).of_size(1600, 900);
+        let palette = ColorPalette([Rgba([255, 255, 255, 255]); 12]);
+
+        for midi in [0., 127.] {
+            let note = Note::new(Pitch::from_midi(midi), 1.);
+            let mut image = RgbaImage::new(area.width(), area.height());
+            note.paint(&mut image, area, &palette, &settings, 1);
+
+            let (x, _) = note.center(area, &settings, 1);
+            let half_width = note.width(area.height(), settings.note_sizing) as f32 / 2.;
+            assert!(x - half_width >= 0. && x + half_width <= area.width() as f32, "midi {midi} centered at {x}");
+            assert_ne!(*image.get_pixel(x as u32, area.height() / 2), Rgba([0; 4]), "midi {midi} isn't painted");
+        }
+    }
+
+    #[test]
+    fn include_above_range() {
+        let mut r = 1.0..2.0;
+        assert_eq!(dist_from_range_bounds(3.0, &r), 1.0);
+        include_in_range(3.0, &mut r);
+        assert_eq!(r, 1.0..3.0);
+        assert_eq!(range_len(&r), 2.0);
+    }
+
+    #[test]
+    fn include_below_range() {
+        let mut r = 1.0..2.0;
+        assert_eq!(dist_from_range_bounds(0.5, &r), 0.5);
+        include_in_range(0.5, &mut r);
+        assert_eq!(r, 0.5..2.0);
+        assert_eq!(range_len(&r), 1.5);
+    }
+
+    #[test]
+    fn include_inside_range() {
+        let mut r = 1.0..2.0;
+        for v in [1.0, 1.5, 2.0] {
+            assert_eq!(dist_from_range_bounds(v, &r), 0.0);
+            include_in_range(v, &mut r);
+            assert_eq!(r, 1.0..2.0);
+        }
+    }
+
+    #[test]
+    fn pitches_follow_the_tuning() {
+        assert_eq!(Pitch::from_frequency_tuned(432., 432.).midi(), 69.);
+        assert_eq!(Pitch::from_frequency_tuned(864., 432.).name(), "A5");
+        // 440 Hz is a bit sharp of A4 when A4 is 432 Hz
+        assert!(Pitch::from_frequency_tuned(440., 432.).midi() > 69.3);
+        assert!((Pitch::from_midi_tuned(60., 432.).frequency() - 256.87).abs() < 0.01);
+    }
+
+    #[test]
+    fn cents_are_from_the_nearest_note() {
+        assert_eq!(Pitch::from_midi(69.).cents(), 0.);
+        assert!((Pitch::from_midi(69.12).cents() - 12.).abs() < 0.01);
+        assert!((Pitch::from_midi(68.7).cents() + 30.).abs() < 0.01);
+        assert_eq!(Pitch::from_midi(68.7).name(), "A4");
+    }
+
+    #[test]
+    fn note_ranges_grow_in_both_directions() {
+        let settings = Settings::default();
+        let mut note = Note::new(Pitch::from_midi(60.), 0.5);
+        assert!(note.try_include(Pitch::from_midi(60.4), 0.4, &settings).is_ok());
+        assert!(note.try_include(Pitch::from_midi(59.8), 0.6, &settings).is_ok());
+
+        assert_eq!(note.midi_range.start, 59.8);
+        assert_eq!(note.midi_range.end, 60.4);
+        assert_eq!(note.amp_range.start, 0.4);
+        assert_eq!(note.amp_range.end, 0.6);
+        assert_eq!(note.peak_amplitude, 0.6);
+
+        // Would stretch the note past a semitone
+        assert!(note.try_include(Pitch::from_midi(61.), 0.5, &settings).is_err());
+    }
+
+    #[test]
+    fn notes_of_any_amplitude_have_a_reasonable_size() {
+        for amplitude in [0., 0.001, 1e6, f32::MAX] {
+            let note = Note::new(Pitch::from_midi(60.), amplitude);
+            for sizing in [NoteSizing::Linear, NoteSizing::Decibels] {
+                for frame_height in [900, 2160] {
+                    let (width, height) = (note.width(frame_height, sizing), note.height(frame_height, sizing));
+                    assert!(width > 0 && height > 0, "amplitude {amplitude} gave a {width}x{height} {sizing:?} note");
+                    assert!(width <= 2160 && height <= 2160, "amplitude {amplitude} gave a {width}x{height} {sizing:?} note");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn louder_notes_are_taller_and_narrower() {
+        let quiet = Note::new(Pitch::from_midi(60.), 0.1);
+        let loud = Note::new(Pitch::from_midi(60.), 1.);
+        for sizing in [NoteSizing::Linear, NoteSizing::Decibels] {
+            assert!(loud.height(900, sizing) > quiet.height(900, sizing));
+            assert!(loud.width(900, sizing) < quiet.width(900, sizing));
+        }
+    }
+
+    #[test]
+    fn decibel_sizing_evens_out_loudness() {
+        let size = |amplitude: f32, sizing| {
+            let note = Note::new(Pitch::from_midi(60.), amplitude);
+            (note.width(900, sizing), note.height(900, sizing))
+        };
+        // Full amplitude looks the same either way
+        assert_eq!(size(1., NoteSizing::Linear), size(1., NoteSizing::Decibels));
+
+        // Quiet notes are bigger, without turning into a thin line across the frame
+        let (quiet_width, quiet_height) = size(0.01, NoteSizing::Decibels);
+        assert!(quiet_height > size(0.01, NoteSizing::Linear).1);
+        assert!(quiet_width < MAX_NOTE_SIZE as u32 / 2, "{quiet_width}x{quiet_height}");
+        // Loud ones grow by less
+        assert!(size(4., NoteSizing::Decibels).1 < size(4., NoteSizing::Linear).1);
+    }
+
+    #[test]
+    fn the_color_wheel_blends_hues() {
+        let mut colors = [Rgba([255, 255, 255, 255]); 12];
+        colors[0] = Rgba([255, 0, 0, 255]);
+        colors[1] = Rgba([0, 0, 255, 255]);
+        let palette = ColorPalette(colors);
+        let settings = Settings { color_mode: ColorMode::ChromaticWheel, color_brightness: 1., ..Default::default() };
+
+        // Halfway from red to blue is magenta, not a dark purple
+        let color = Note::new(Pitch::from_midi(60.5), 1.).color(&palette, &settings);
+        assert_eq!(color, Rgba([255, 0, 255, 255]));
+        assert_eq!(interpolate_hue(Rgba([0, 255, 0, 255]), Rgba([0, 255, 0, 255]), 0.3), Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn jitter_depends_only_on_the_semitone() {
+        let color = Rgba([200, 100, 50, 255]);
+        assert_eq!(jitter(color, 69., 0.), color);
+        assert_eq!(jitter(color, 69., 1.), jitter(color, 69., 1.));
+
+        let jittered: Vec<_> = [69., 70., 71., 72.].map(|midi| jitter(color, midi, 1.)).into();
+        assert!(jittered.windows(2).any(|pair| pair[0] != pair[1]));
+        for Rgba([r, g, b, a]) in jittered {
+            assert_eq!(a, 255);
+            assert!(r.abs_diff(200) < 80 && g.abs_diff(100) < 80 && b.abs_diff(50) < 80);
+        }
+    }
+
+    #[test]
+    fn higher_octaves_are_brighter() {
+        let palette = ColorPalette([Rgba([200, 100, 50, 255]); 12]);
+        let settings = Settings { color_mode: ColorMode::OctaveBrightness, color_brightness: 1., ..Default::default() };
+        let low = Note::new(Pitch::from_midi(36.), 1.).color(&palette, &settings);
+        let high = Note::new(Pitch::from_midi(84.), 1.).color(&palette, &settings);
+        assert!(low[0] < high[0] && low[1] < high[1] && low[2] < high[2]);
+        assert_eq!(low[3], high[3]);
+    }
+
+    #[test]
+    fn notes_stay_inside_their_area() {
+        let settings = Settings::default();
+        let note = Note::new(Pitch::from_midi(60.), 5.);
+        let mut image = RgbaImage::new(400, 200);
+        let top_half = Rect::at(0, 0).of_size(400, 100);
+        note.paint(&mut image, top_half, &ColorPalette([Rgba([255, 255, 255, 255]); 12]), &settings, 1);
+
+        assert!(image.rows().take(100).flatten().any(|pixel| pixel[3] > 0));
+        assert!(image.rows().skip(100).flatten().all(|pixel| pixel[3] == 0));
+    }
+
+    #[test]
+    fn blend_modes_combine_overlapping_colors() {
+        let red = Rgba([128, 0, 0, 255]);
+        let blended = |mode, coverage| {
+            let mut pixel = Rgba([128, 64, 0, 255]);
+            blend(&mut pixel, red, coverage, mode);
+            pixel
+        };
+
+        assert_eq!(blended(BlendMode::Over, 1.), red);
+        assert_eq!(blended(BlendMode::Additive, 1.), Rgba([255, 64, 0, 255]));
+        assert_eq!(blended(BlendMode::Screen, 1.), Rgba([192, 64, 0, 255]));
+        assert_eq!(blended(BlendMode::Max, 1.), Rgba([128, 64, 0, 255]));
+        // Nothing changes where the note doesn't cover the pixel
+        for mode in [BlendMode::Over, BlendMode::Additive, BlendMode::Screen, BlendMode::Max] {
+            assert_eq!(blended(mode, 0.), Rgba([128, 64, 0, 255]), "{mode:?}");
+        }
+
+        // Onto transparent pixels, every mode is the same as painting over
+        for mode in [BlendMode::Additive, BlendMode::Screen, BlendMode::Max] {
+            let mut pixel = Rgba([0; 4]);
+            blend(&mut pixel, red, 0.5, mode);
+            assert_eq!(pixel, Rgba([64, 0, 0, 128]), "{mode:?}");
+        }
+
+        // Where both are translucent, the colors stay premultiplied
+        let translucent_red = Rgba([200, 0, 0, 200]);
+        for mode in [BlendMode::Additive, BlendMode::Screen] {
+            let mut pixel = translucent_red;
+            blend(&mut pixel, translucent_red, 1., mode);
+            assert!(pixel[0] <= pixel[3], "{mode:?} gave {pixel:?}");
+        }
+    }
+
+    #[test]
+    fn palette_lookups_stay_in_bounds_over_any_range() {
+        let settings = Settings { color_mode: ColorMode::Palette, color_gamma: 1., color_brightness: 1., ..Default::default() };
+        // A different color for every pitch class
+        let palette = ColorPalette(std::array::from_fn(|pitch_class| Rgba([pitch_class as u8 * 20, 0, 0, 255])));
+
+        for a4_frequency in [415., STANDARD_A4_FREQ, 466.] {
+            // Well past both ends of the MIDI range, in quarter tones
+            for quarter_tones in -100..=600 {
+                let note = Note::new(Pitch::from_midi_tuned(quarter_tones as f32 / 4. - 24., a4_frequency), 1.);
+                note.color(&palette, &settings);
+            }
+        }
+        for midi in [-1e-6, -0.5, 12.0 - 1e-6, 129., 140., f32::NAN, f32::INFINITY] {
+            Note::new(Pitch::from_midi(midi), 1.).color(&palette, &settings);
+        }
+
+        // Notes below C-1 still get their own pitch class's color
+        for midi in [-12i32, -3, 129, 60] {
+            let color = Note::new(Pitch::from_midi(midi as f32), 1.).color(&palette, &settings);
+            assert_eq!(color, palette.0[midi.rem_euclid(12) as usize], "midi {midi}");
+        }
+    }
+
+    #[test]
+    fn vibrato_keeps_the_jitter() {
+        let color = Rgba([200, 100, 50, 255]);
+        let jittered = |frequency: f32| jitter(color, Pitch::from_frequency(frequency).midi(), 1.);
+        // A few cents either way of A4, like neighboring FFT bins under vibrato,
+        // and the band center a fading note is moved to
+        for frequency in [437.5, 439., 441., 442.5] {
+            assert_eq!(jittered(frequency), jittered(440.), "{frequency} Hz");
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{chromagram::Key, note::{Note, Pitch}, settings_window::OverlayPosition};
+
+const MAX_LISTED_NOTES: usize = 12;
+/// Height of the loudest note's text, as a fraction of the frame's height
+const DOMINANT_NOTE_SIZE: f32 = 0.12;
+/// Height of the tempo's text, as a fraction of the frame's height
+const TEMPO_SIZE: f32 = 0.06;
+
+/// A window listing the loudest notes currently detected, and the key they seem to be in.
+pub fn show(ctx: &egui::Context, is_open: &mut bool, notes: &[Note], key: Option<Key>) {
+    egui::Window::new("Detected notes")
+        .open(is_open)
+        .resizable(false)
+        .show(ctx, |ui| {
+            if let Some(key) = key {
+                ui.label(format!("Key: {}", key.name()));
+            }
+
+            if notes.is_empty() {
+                ui.label("No notes detected.");
+                return;
+            }
+
+            egui::Grid::new("note_readout").striped(true).show(ui, |ui| {
+                ui.strong("Note");
+                ui.strong("Frequency");
+                ui.strong("Amplitude");
+                ui.end_row();
+
+                // Loudest first
+                for note in notes.iter().rev().take(MAX_LISTED_NOTES) {
+                    ui.monospace(note.peak_pitch.name());
+                    ui.monospace(format!("{:.1} Hz", note.frequency()));
+                    ui.monospace(format!("{:.3}", note.amplitude()));
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+/// Paints the loudest of `notes` and how far out of tune it is, like "A4 +12¢", in large text over `frame`.
+pub fn paint_dominant_note(painter: &egui::Painter, frame: egui::Rect, notes: &[Note], position: OverlayPosition) {
+    let Some(loudest) = notes.last() else {
+        return;
+    };
+
+    let font = egui::FontId::proportional(frame.height() * DOMINANT_NOTE_SIZE);
+    let margin = frame.height() * DOMINANT_NOTE_SIZE / 3.;
+    let align = position.align();
+    let anchor = align.pos_in_rect(&frame.shrink(margin));
+    let text = tuner_label(loudest.peak_pitch);
+
+    // A shadow keeps it readable over bright notes
+    let shadow_offset = egui::vec2(1., 1.) * (font.size / 30.).max(1.);
+    painter.text(anchor + shadow_offset, align, &text, font.clone(), egui::Color32::from_black_alpha(200));
+    painter.text(anchor, align, text, font, egui::Color32::WHITE);
+}
+
+/// Paints the estimated tempo, like "120 BPM", over `frame` with a light beside it
+/// that flashes with `beat_pulse` and fades until the next beat.
+pub fn paint_tempo(painter: &egui::Painter, frame: egui::Rect, bpm: Option<f32>, beat_pulse: f32, position: OverlayPosition) {
+    let font = egui::FontId::proportional(frame.height() * TEMPO_SIZE);
+    let margin = frame.height() * TEMPO_SIZE / 2.;
+    let align = position.align();
+    let text = match bpm {
+        Some(bpm) => format!("{bpm:.0} BPM"),
+        None => "-- BPM".to_string(),
+    };
+
+    // Laid out as one row, light first, then placed in the frame like the loudest note is.
+    // A shadow keeps the text readable over bright notes
+    let shadow = painter.layout_no_wrap(text.clone(), font.clone(), egui::Color32::from_black_alpha(200));
+    let galley = painter.layout_no_wrap(text, font.clone(), egui::Color32::WHITE);
+    let radius = font.size / 3.;
+    let gap = font.size / 3.;
+    let size = egui::vec2(radius * 2. + gap + galley.size().x, galley.size().y);
+    let row = align.align_size_within_rect(size, frame.shrink(margin));
+
+    let light_center = egui::pos2(row.left() + radius, row.center().y);
+    let brightness = (beat_pulse.clamp(0., 1.) * beat_pulse.clamp(0., 1.) * 255.) as u8;
+    painter.circle_filled(light_center, radius, egui::Color32::from_gray(40));
+    painter.circle_filled(light_center, radius, egui::Color32::from_rgba_unmultiplied(255, 255, 255, brightness));
+
+    let text_pos = egui::pos2(row.left() + radius * 2. + gap, row.top());
+    let shadow_offset = egui::vec2(1., 1.) * (font.size / 30.).max(1.);
+    painter.galley(text_pos + shadow_offset, shadow, egui::Color32::from_black_alpha(200));
+    painter.galley(text_pos, galley, egui::Color32::WHITE);
+}
+
+fn tuner_label(pitch: Pitch) -> String {
+    let cents = pitch.cents().round() as i32;
+    format!("{} {cents:+}¢", pitch.name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuner_labels_show_the_sign() {
+        assert_eq!(tuner_label(Pitch::from_midi(69.12)), "A4 +12¢");
+        assert_eq!(tuner_label(Pitch::from_midi(60.8)), "C#4 -20¢");
+        assert_eq!(tuner_label(Pitch::from_midi(69.001)), "A4 +0¢");
+        assert_eq!(tuner_label(Pitch::from_midi(68.999)), "A4 +0¢");
+    }
+}
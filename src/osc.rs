@@ -0,0 +1,96 @@
+use std::{net::{ToSocketAddrs, UdpSocket}, sync::Arc};
+
+use crate::note::Note;
+
+/// Sends the detected notes to other software over Open Sound Control, as one
+/// `/note <midi> <amplitude>` message per note, with the MIDI note as an int and the amplitude as a float.
+#[derive(Clone, Default)]
+pub struct OscSender {
+    target: Option<Target>,
+    /// Reused for every message
+    buffer: Vec<u8>,
+}
+
+/// Where messages go, and the socket connected there if it could be set up.
+#[derive(Clone)]
+struct Target {
+    host: String,
+    port: u16,
+    socket: Option<Arc<UdpSocket>>,
+}
+
+impl OscSender {
+    pub fn send_notes(&mut self, notes: &[Note], host: &str, port: u16) {
+        if self.target.as_ref().is_none_or(|target| target.host != host || target.port != port) {
+            let socket = connect(host, port)
+                .inspect_err(|e| log::warn!("Couldn't send OSC to {host}:{port}: {e:#}"))
+                .ok();
+            self.target = Some(Target { host: host.to_owned(), port, socket });
+        }
+        let Some(socket) = self.target.as_ref().and_then(|target| target.socket.as_ref()) else {
+            return;
+        };
+
+        for note in notes {
+            encode_note(&mut self.buffer, note.midi().round() as i32, note.amplitude());
+            // Nothing listening is normal, so don't complain about every frame
+            if let Err(e) = socket.send(&self.buffer) {
+                log::debug!("Couldn't send an OSC message: {e}");
+                break;
+            }
+        }
+    }
+}
+
+fn connect(host: &str, port: u16) -> anyhow::Result<Arc<UdpSocket>> {
+    let address = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("no address found for {host}"))?;
+    let socket = if address.is_ipv6() {
+        UdpSocket::bind(("::", 0))?
+    } else {
+        UdpSocket::bind(("0.0.0.0", 0))?
+    };
+    socket.connect(address)?;
+
+    Ok(Arc::new(socket))
+}
+
+/// Writes a `/note` message to `buffer`, replacing what was there.
+fn encode_note(buffer: &mut Vec<u8>, midi: i32, amplitude: f32) {
+    buffer.clear();
+    write_string(buffer, "/note");
+    write_string(buffer, ",if");
+    buffer.extend(midi.to_be_bytes());
+    buffer.extend(amplitude.to_be_bytes());
+}
+
+/// OSC strings end with at least one nul byte and are padded to a multiple of 4 bytes.
+fn write_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend(string.as_bytes());
+    buffer.extend(std::iter::repeat_n(0, 4 - string.len() % 4));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_message_layout() {
+        let mut buffer = Vec::new();
+        encode_note(&mut buffer, 69, 0.5);
+
+        let mut expected = b"/note\0\0\0,if\0".to_vec();
+        expected.extend([0, 0, 0, 69]);
+        expected.extend(0.5f32.to_be_bytes());
+        assert_eq!(buffer, expected);
+    }
+
+    #[test]
+    fn strings_always_end_with_a_nul() {
+        let mut buffer = Vec::new();
+        write_string(&mut buffer, "/abc");
+        assert_eq!(buffer, b"/abc\0\0\0\0");
+    }
+}
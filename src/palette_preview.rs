@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+
+use crate::{note::PITCH_CLASS_NAMES, synesthetizer::ColorPalette, text};
+
+/// Width and height of each color's square
+const SWATCH_SIZE: u32 = 120;
+const LABEL_SIZE: f32 = 28.;
+/// Space between the bottom of a swatch and its label's baseline
+const LABEL_MARGIN: f32 = 12.;
+
+/// A strip of the palette's 12 colors from C to B, each labeled with its pitch class.
+pub fn render(color_palette: &ColorPalette) -> RgbaImage {
+    let mut image = RgbaImage::new(SWATCH_SIZE * 12, SWATCH_SIZE);
+    let font = text::font();
+
+    for (idx, (&color, name)) in color_palette.0.iter().zip(PITCH_CLASS_NAMES).enumerate() {
+        let left = idx as u32 * SWATCH_SIZE;
+        let color = Rgba([color[0], color[1], color[2], 255]);
+        draw_filled_rect_mut(&mut image, Rect::at(left as i32, 0).of_size(SWATCH_SIZE, SWATCH_SIZE), color);
+
+        // Centered across the bottom of the swatch
+        let label_left = left as f32 + (SWATCH_SIZE as f32 - text::text_width(&font, name, LABEL_SIZE)) / 2.;
+        text::paint_text(&mut image, &font, name, LABEL_SIZE, label_left, SWATCH_SIZE as f32 - LABEL_MARGIN, label_color(color));
+    }
+
+    image
+}
+
+pub fn save(color_palette: &ColorPalette, path: &Path) -> anyhow::Result<()> {
+    render(color_palette).save_with_format(path, ImageFormat::Png)?;
+    log::info!("Palette preview saved to {}.", path.display());
+    Ok(())
+}
+
+/// Black on light colors and white on dark ones.
+fn label_color(Rgba([r, g, b, _]): Rgba<u8>) -> Rgba<u8> {
+    let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+    if luma > 140. {
+        Rgba([0, 0, 0, 255])
+    } else {
+        Rgba([255, 255, 255, 255])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_color_gets_a_labeled_swatch() {
+        let mut colors = [Rgba([20, 20, 20, 255]); 12];
+        colors[3] = Rgba([250, 200, 0, 255]);
+        let image = render(&ColorPalette(colors));
+        assert_eq!(image.dimensions(), (SWATCH_SIZE * 12, SWATCH_SIZE));
+
+        // The top of each swatch is its color, and the label is in the bottom part
+        let swatch = 3 * SWATCH_SIZE;
+        assert_eq!(*image.get_pixel(swatch + SWATCH_SIZE / 2, 5), colors[3]);
+        let has_label = (swatch..swatch + SWATCH_SIZE)
+            .flat_map(|x| (SWATCH_SIZE / 2..SWATCH_SIZE).map(move |y| (x, y)))
+            .any(|(x, y)| *image.get_pixel(x, y) != colors[3]);
+        assert!(has_label);
+    }
+}
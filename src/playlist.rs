@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+/// Playlist files whose entries can be opened in place of the playlist itself.
+pub const PLAYLIST_EXTENSIONS: [&str; 2] = ["m3u", "m3u8"];
+
+pub fn is_playlist_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| PLAYLIST_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Replaces every playlist file in `paths` with the files it lists. Playlists that can't be
+/// read and entries that don't exist are left out with a warning, so the rest still play.
+pub fn expand_playlists(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        if !is_playlist_file(&path) {
+            files.push(path);
+            continue;
+        }
+
+        match std::fs::read(&path) {
+            Ok(contents) => {
+                let base = path.parent().unwrap_or(Path::new(""));
+                for entry in parse_m3u(&String::from_utf8_lossy(&contents), base) {
+                    if entry.exists() {
+                        files.push(entry);
+                    } else {
+                        log::warn!("Skipping {}, listed in {}, since it doesn't exist.", entry.display(), path.display());
+                    }
+                }
+            }
+            Err(e) => log::warn!("Couldn't read the playlist {}: {e:?}", path.display()),
+        }
+    }
+    files
+}
+
+/// The entries of an M3U or extended M3U playlist, in order, with relative paths resolved against `base`.
+///
+/// Comments and `#EXT` directives are skipped, as are URLs, which can't be queued.
+fn parse_m3u(contents: &str, base: &Path) -> Vec<PathBuf> {
+    contents.trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let path = match line.strip_prefix("file://") {
+                Some(path) => percent_decode(path),
+                None if line.contains("://") => return None,
+                None => line.to_owned(),
+            };
+            Some(base.join(path))
+        })
+        .collect()
+}
+
+/// Turns the `%XX` escapes in a URL back into the bytes they stand for.
+/// A `%` that isn't followed by two hex digits is kept as it is.
+fn percent_decode(url: &str) -> String {
+    let mut bytes = Vec::with_capacity(url.len());
+    let mut rest = url.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2)
+            .filter(|hex| byte == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// The queue of files to play, in order.
+pub struct Playlist {
+    files: Vec<PathBuf>,
+    current: usize,
+}
+
+impl Playlist {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            current: 0,
+        }
+    }
+
+    /// Replaces the queue with `files`, starting from the first one.
+    pub fn replace(&mut self, files: Vec<PathBuf>) {
+        self.files = files;
+        self.current = 0;
+    }
+
+    /// Adds `files` to the end of the queue.
+    pub fn extend(&mut self, files: impl IntoIterator<Item = PathBuf>) {
+        self.files.extend(files);
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The index of the current file, from 0.
+    pub fn position(&self) -> usize {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.files.get(self.current).map(PathBuf::as_path)
+    }
+
+    pub fn peek_next(&self) -> Option<&Path> {
+        self.files.get(self.current + 1).map(PathBuf::as_path)
+    }
+
+    /// Moves on to the next file and returns it, or `None` at the end of the queue.
+    pub fn next(&mut self) -> Option<PathBuf> {
+        let next = self.files.get(self.current + 1)?.clone();
+        self.current += 1;
+        Some(next)
+    }
+
+    /// Moves back to the previous file and returns it, or `None` at the start of the queue.
+    pub fn previous(&mut self) -> Option<PathBuf> {
+        let previous = self.files.get(self.current.checked_sub(1)?)?.clone();
+        self.current -= 1;
+        Some(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn m3u_entries_are_resolved_against_the_playlist() {
+        let contents = "\u{feff}#EXTM3U\n#EXTINF:123,Artist - Song\nsong.mp3\r\n\n  albums/other.flac  \n/music/absolute.ogg\nhttps://example.com/stream.mp3\n";
+        let entries = parse_m3u(contents, Path::new("/playlists"));
+        assert_eq!(entries, [
+            PathBuf::from("/playlists/song.mp3"),
+            PathBuf::from("/playlists/albums/other.flac"),
+            PathBuf::from("/music/absolute.ogg"),
+        ]);
+    }
+
+    #[test]
+    fn file_urls_are_percent_decoded() {
+        let contents = "file:///music/My%20Song.mp3\nfile:///music/100%.mp3\nfile:///music/Caf%C3%A9.flac\n";
+        let entries = parse_m3u(contents, Path::new("/playlists"));
+        assert_eq!(entries, [
+            PathBuf::from("/music/My Song.mp3"),
+            PathBuf::from("/music/100%.mp3"),
+            PathBuf::from("/music/Café.flac"),
+        ]);
+    }
+}
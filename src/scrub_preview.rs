@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+use crate::{music::Music, settings_window::Settings, synesthetizer::Synesthetizer};
+
+/// Width of the preview frames, in pixels. The height follows the frame's aspect ratio.
+const PREVIEW_WIDTH: u32 = 192;
+/// Previews are rendered at this many positions per second of the song, so nearby pointer positions share one
+const PREVIEWS_PER_SECOND: f64 = 4.0;
+/// How many recent previews are kept, which is enough to drag back and forth over a few seconds for free
+const CACHED_PREVIEWS: usize = 48;
+
+/// Small frames showing what the visualization looks like at a point of the song,
+/// for hovering or dragging over the scrub bar.
+///
+/// They're rendered with a copy of the synesthetizer, so playback and the frame on screen
+/// aren't touched, and kept until `clear` is called, which the app does when the pointer
+/// leaves the scrub bar or the song changes, so previews never outlive the settings they had.
+#[derive(Default)]
+pub struct ScrubPreview {
+    synesthetizer: Option<Synesthetizer>,
+    /// Most recently used last, by which of the `PREVIEWS_PER_SECOND` positions they show
+    cache: VecDeque<(u64, egui::TextureHandle)>,
+}
+
+impl ScrubPreview {
+    pub fn clear(&mut self) {
+        self.synesthetizer = None;
+        self.cache.clear();
+    }
+
+    /// The preview of `music` at `position` seconds, rendered by a copy of `synesthetizer` if it isn't cached.
+    pub fn frame_at(&mut self, synesthetizer: &Synesthetizer, music: &Music, position: f64, settings: &Settings, ctx: &egui::Context) -> egui::TextureHandle {
+        let key = (position.max(0.0) * PREVIEWS_PER_SECOND).round() as u64;
+        if let Some(idx) = self.cache.iter().position(|(cached, _)| *cached == key) {
+            let entry = self.cache.remove(idx).unwrap();
+            self.cache.push_back(entry);
+            return self.cache.back().unwrap().1.clone();
+        }
+
+        let preview = self.synesthetizer.get_or_insert_with(|| {
+            let mut preview = synesthetizer.clone();
+            preview.set_resolution(preview_size(settings.resolution));
+            preview
+        });
+        preview.render_at(music.data(), key as f64 / PREVIEWS_PER_SECOND, settings);
+        let image = preview.snapshot_image(settings);
+        let image = egui::ColorImage::from_rgba_unmultiplied([image.width() as usize, image.height() as usize], image.as_raw());
+        let texture = ctx.load_texture("scrub_preview", image, settings.canvas_filter.texture_options());
+
+        if self.cache.len() >= CACHED_PREVIEWS {
+            self.cache.pop_front();
+        }
+        self.cache.push_back((key, texture.clone()));
+        texture
+    }
+}
+
+/// `PREVIEW_WIDTH` wide, in the shape of a `resolution` frame.
+fn preview_size((width, height): (u32, u32)) -> (u32, u32) {
+    let preview_height = (PREVIEW_WIDTH as f32 * height as f32 / width.max(1) as f32).round() as u32;
+    (PREVIEW_WIDTH, preview_height.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_keep_the_frame_shape() {
+        assert_eq!(preview_size((1600, 900)), (192, 108));
+        assert_eq!(preview_size((1080, 1080)), (192, 192));
+        assert_eq!(preview_size((0, 0)), (192, 1));
+    }
+}
@@ -0,0 +1,379 @@
+use std::{io::{BufRead, BufReader, Read, Write}, net::{Ipv4Addr, TcpListener, TcpStream}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::Duration};
+
+use base64::Engine as _;
+use image::{buffer::ConvertBuffer, codecs::jpeg::JpegEncoder, ImageFormat, RgbImage, RgbaImage};
+use sha1::{Digest, Sha1};
+
+use crate::{app::MusicState, json, note::Note};
+
+/// How long a request waits for the app to publish a frame before giving up
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+/// Requests with longer heads than this aren't from a browser or OBS
+const MAX_REQUEST_BYTES: usize = 8192;
+const MJPEG_QUALITY: u8 = 80;
+/// Appended to the client's key to accept a websocket, from RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const INDEX_PAGE: &str = "<!DOCTYPE html>\n<html><body style=\"margin: 0; background: transparent\">\
+<img src=\"/stream.mjpg\" style=\"width: 100vw; height: 100vh; object-fit: contain\"></body></html>\n";
+
+/// A tiny HTTP server on localhost for stream overlays, like an OBS browser source.
+///
+/// - `/` is a page showing the stream
+/// - `/frame.png` is the current frame, with alpha if snapshots are transparent
+/// - `/stream.mjpg` is an MJPEG stream of the frames
+/// - `/state.json` is the current position and notes
+/// - `/ws` is a websocket that gets the same JSON every frame
+///
+/// Frames are only encoded while something is asking for them, so a server nobody
+/// is connected to costs nothing but a sleeping thread.
+pub struct OverlayServer {
+    port: u16,
+    shared: Arc<Shared>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    latest: Mutex<Published>,
+    published: Condvar,
+    /// Connections waiting for the next frame, or streaming every frame
+    listeners: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+#[derive(Default)]
+struct Published {
+    /// Counts up with every frame, so connections can tell when there's a new one
+    generation: u64,
+    frame: Option<Arc<RgbaImage>>,
+    state: Arc<str>,
+}
+
+impl OverlayServer {
+    /// Starts listening on `port` of the loopback interface only, so it isn't reachable from other machines.
+    pub fn start(port: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let shared = Arc::new(Shared::default());
+        let accept_shared = shared.clone();
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let shared = accept_shared.clone();
+                thread::spawn(move || {
+                    // Closed connections are normal, so don't complain
+                    if let Err(e) = handle_connection(stream, &shared) {
+                        log::debug!("Overlay server connection ended: {e}");
+                    }
+                });
+            }
+        });
+
+        log::info!("Serving the visualization on http://localhost:{port}/");
+        Ok(Self {
+            port,
+            shared,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Whether any connection is waiting for a frame, so the app knows to call `publish`.
+    pub fn wants_frame(&self) -> bool {
+        self.shared.listeners.load(Ordering::Relaxed) > 0
+    }
+
+    /// Hands the frame on screen and the JSON from `state_json` to the connections.
+    pub fn publish(&self, frame: RgbaImage, state: String) {
+        let mut latest = self.shared.latest.lock().unwrap();
+        latest.generation += 1;
+        latest.frame = Some(Arc::new(frame));
+        latest.state = state.into();
+        self.shared.published.notify_all();
+    }
+}
+
+impl Drop for OverlayServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.shared.published.notify_all();
+        // Wakes the accept loop up so it sees it's stopped
+        let _ = TcpStream::connect((Ipv4Addr::LOCALHOST, self.port));
+        if let Some(accept_thread) = self.accept_thread.take() {
+            let _ = accept_thread.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Waits for a frame newer than `generation`, or `None` if the server stopped or the app
+    /// didn't publish in time, like while the window is minimized.
+    fn next_frame(&self, generation: u64) -> Option<(u64, Arc<RgbaImage>, Arc<str>)> {
+        let mut latest = self.latest.lock().unwrap();
+        loop {
+            if self.stopped.load(Ordering::Relaxed) {
+                return None;
+            }
+            if latest.generation > generation {
+                if let Some(frame) = &latest.frame {
+                    return Some((latest.generation, frame.clone(), latest.state.clone()));
+                }
+            }
+            let (guard, timeout) = self.published.wait_timeout(latest, FRAME_TIMEOUT).unwrap();
+            latest = guard;
+            if timeout.timed_out() {
+                return None;
+            }
+        }
+    }
+
+    fn current_generation(&self) -> u64 {
+        self.latest.lock().unwrap().generation
+    }
+}
+
+/// Counts a connection as listening for as long as it's alive.
+struct Listening<'a>(&'a Shared);
+
+impl<'a> Listening<'a> {
+    fn new(shared: &'a Shared) -> Self {
+        shared.listeners.fetch_add(1, Ordering::Relaxed);
+        Self(shared)
+    }
+}
+
+impl Drop for Listening<'_> {
+    fn drop(&mut self) {
+        self.0.listeners.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, shared: &Shared) -> anyhow::Result<()> {
+    let request = read_request(&stream)?;
+    if shared.stopped.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    if request.method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"Only GET is supported\n");
+    }
+
+    match request.path.as_str() {
+        "/" | "/index.html" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", INDEX_PAGE.as_bytes()),
+        "/frame.png" | "/state.json" => {
+            let _listening = Listening::new(shared);
+            let Some((_, frame, state)) = shared.next_frame(shared.current_generation()) else {
+                return respond(&mut stream, "503 Service Unavailable", "text/plain", b"No frame is being shown\n");
+            };
+            if request.path == "/frame.png" {
+                let mut png = std::io::Cursor::new(Vec::new());
+                frame.write_to(&mut png, ImageFormat::Png)?;
+                respond(&mut stream, "200 OK", "image/png", png.get_ref())
+            } else {
+                respond(&mut stream, "200 OK", "application/json", state.as_bytes())
+            }
+        }
+        "/stream.mjpg" => stream_mjpeg(stream, shared),
+        "/ws" => match request.websocket_key {
+            Some(key) => stream_websocket(stream, shared, &key),
+            None => respond(&mut stream, "426 Upgrade Required", "text/plain", b"Connect with a websocket\n"),
+        },
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found\n"),
+    }
+}
+
+struct Request {
+    method: String,
+    /// Without the query string
+    path: String,
+    websocket_key: Option<String>,
+}
+
+fn read_request(stream: &TcpStream) -> anyhow::Result<Request> {
+    let mut reader = BufReader::new(stream).take(MAX_REQUEST_BYTES as u64);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("malformed request line {line:?}");
+    };
+    let method = method.to_owned();
+    let path = target.split('?').next().unwrap_or(target).to_owned();
+
+    let mut websocket_key = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            anyhow::bail!("the request ended before its headers did");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    Ok(Request { method, path, websocket_key })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> anyhow::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+        Cache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+fn stream_mjpeg(mut stream: TcpStream, shared: &Shared) -> anyhow::Result<()> {
+    let _listening = Listening::new(shared);
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+        Cache-Control: no-store\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let mut generation = shared.current_generation();
+    let mut jpeg = Vec::new();
+    // Keeps going through pauses in publishing, until the client hangs up or the server stops
+    while !shared.stopped.load(Ordering::Relaxed) {
+        let Some((frame_generation, frame, _)) = shared.next_frame(generation) else {
+            // Sends the last frame again, so a client that's gone shows up as a failed write.
+            // Before there is one, a blank line is part of the preamble that clients skip.
+            if jpeg.is_empty() {
+                stream.write_all(b"\r\n")?;
+            } else {
+                write_mjpeg_part(&mut stream, &jpeg)?;
+            }
+            continue;
+        };
+        generation = frame_generation;
+
+        // JPEG has no alpha, so transparent areas come out black
+        let rgb: RgbImage = frame.convert();
+        jpeg.clear();
+        JpegEncoder::new_with_quality(&mut jpeg, MJPEG_QUALITY).encode_image(&rgb)?;
+        write_mjpeg_part(&mut stream, &jpeg)?;
+    }
+    Ok(())
+}
+
+fn write_mjpeg_part(stream: &mut TcpStream, jpeg: &[u8]) -> std::io::Result<()> {
+    write!(stream, "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", jpeg.len())?;
+    stream.write_all(jpeg)?;
+    stream.write_all(b"\r\n")
+}
+
+fn stream_websocket(mut stream: TcpStream, shared: &Shared, key: &str) -> anyhow::Result<()> {
+    let _listening = Listening::new(shared);
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket_accept(key)
+    )?;
+
+    // Nothing the client sends is needed, so it's never read. A client that
+    // closes shows up as a failed write.
+    let mut generation = shared.current_generation();
+    let mut message = Vec::new();
+    while !shared.stopped.load(Ordering::Relaxed) {
+        let Some((frame_generation, _, state)) = shared.next_frame(generation) else {
+            stream.write_all(&PING_FRAME)?;
+            continue;
+        };
+        generation = frame_generation;
+        encode_text_frame(&mut message, &state);
+        stream.write_all(&message)?;
+    }
+    Ok(())
+}
+
+/// The `Sec-WebSocket-Accept` answer to a client's `Sec-WebSocket-Key`.
+fn websocket_accept(key: &str) -> String {
+    let digest = Sha1::new()
+        .chain_update(key.as_bytes())
+        .chain_update(WEBSOCKET_GUID.as_bytes())
+        .finalize();
+    base64::engine::general_purpose::STANDARD.encode(digest)
+}
+
+/// An empty websocket ping, sent while there's no state so a closed connection still fails a write
+const PING_FRAME: [u8; 2] = [0x89, 0x00];
+
+/// Writes `text` to `buffer` as a single unmasked websocket text frame, replacing what was there.
+fn encode_text_frame(buffer: &mut Vec<u8>, text: &str) {
+    buffer.clear();
+    // FIN and the text opcode
+    buffer.push(0x81);
+    let len = text.len();
+    if len < 126 {
+        buffer.push(len as u8);
+    } else if let Ok(len) = u16::try_from(len) {
+        buffer.push(126);
+        buffer.extend(len.to_be_bytes());
+    } else {
+        buffer.push(127);
+        buffer.extend((len as u64).to_be_bytes());
+    }
+    buffer.extend(text.as_bytes());
+}
+
+/// What's playing and the notes on screen, as sent to `/state.json` and `/ws`.
+pub fn state_json(music_state: &MusicState, notes: &[Note]) -> String {
+    let (track, position, length, playing) = match music_state {
+        MusicState::Loaded(music) => (json::string(&music.display_name()), music.position(), music.len(), music.is_playing()),
+        MusicState::Loading(meta) => (json::string(&meta.display_name()), 0.0, 0.0, false),
+        MusicState::Silence => ("null".to_string(), 0.0, 0.0, false),
+    };
+    let notes = notes.iter()
+        .map(|note| {
+            format!(
+                "{{\"name\":{},\"midi\":{},\"frequency\":{},\"amplitude\":{}}}",
+                json::string(&note.peak_pitch.name()),
+                json::number(note.midi() as f64),
+                json::number(note.frequency() as f64),
+                json::number(note.amplitude() as f64),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"track\":{track},\"position\":{},\"length\":{},\"playing\":{playing},\"notes\":[{notes}]}}",
+        json::number(position),
+        json::number(length),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn websocket_handshake_matches_the_rfc() {
+        // The example from RFC 6455, section 1.3
+        assert_eq!(websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn text_frames_have_the_right_length_header() {
+        let mut buffer = Vec::new();
+        encode_text_frame(&mut buffer, "hi");
+        assert_eq!(buffer, [0x81, 2, b'h', b'i']);
+
+        let long = "x".repeat(300);
+        encode_text_frame(&mut buffer, &long);
+        assert_eq!(&buffer[..4], [0x81, 126, 1, 44]);
+        assert_eq!(buffer.len(), 4 + 300);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{fs, path::{Path, PathBuf}, sync::mpsc::{self, Receiver, SyncSender, TrySendError}, thread::{self, JoinHandle}};
+
+use image::{ImageFormat, RgbaImage};
+
+use crate::json;
+
+/// Frames waiting to be written before new ones get dropped, so a slow disk can't fill up memory
+const MAX_QUEUED_FRAMES: usize = 64;
+const SIDECAR_FILE_NAME: &str = "session.json";
+
+/// The frames shown while a song plays, saved as a numbered PNG sequence on a background
+/// thread, with a `session.json` next to them that has what's needed to mux them with the audio.
+///
+/// Frames are taken at a fixed rate of track time rather than whenever the screen repaints,
+/// so frame `n` always shows the song at `start_position + n / fps`.
+pub struct SessionRecording {
+    folder: PathBuf,
+    fps: f32,
+    audio_path: Option<PathBuf>,
+    /// Track position of the first frame, in seconds
+    start_position: Option<f64>,
+    frame_count: u64,
+    dropped_frames: u64,
+    sender: SyncSender<(u64, RgbaImage)>,
+    join_handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl SessionRecording {
+    /// Starts writing frames to `folder`. `audio_path` is the song's file, referenced
+    /// in the sidecar, or `None` for music that didn't come from a file.
+    pub fn start(folder: PathBuf, audio_path: Option<PathBuf>, fps: f32) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+        let frames_folder = folder.clone();
+        let join_handle = thread::spawn(move || write_frames(&frames_folder, receiver));
+
+        Self {
+            folder,
+            fps,
+            audio_path,
+            start_position: None,
+            frame_count: 0,
+            dropped_frames: 0,
+            sender,
+            join_handle,
+        }
+    }
+
+    /// Queues `image`, the frame on screen at `position` seconds into the track, once for every
+    /// frame slot it covers. Nothing is queued while the position doesn't move, like when paused.
+    pub fn record(&mut self, image: &RgbaImage, position: f64) {
+        let start = *self.start_position.get_or_insert(position);
+        let frame_duration = 1.0 / self.fps as f64;
+        while start + self.frame_count as f64 * frame_duration <= position {
+            match self.sender.try_send((self.frame_count, image.clone())) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => self.dropped_frames += 1,
+                // The writer stopped on an error, which `finish` reports
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+            self.frame_count += 1;
+        }
+    }
+
+    /// Waits for the queued frames to be written and writes the sidecar,
+    /// returning the folder everything was saved to.
+    pub fn finish(self) -> anyhow::Result<PathBuf> {
+        drop(self.sender);
+        match self.join_handle.join() {
+            Ok(result) => result?,
+            Err(_) => anyhow::bail!("the session recording thread panicked"),
+        }
+
+        if self.dropped_frames > 0 {
+            // Dropped frames keep their numbers, so the rest stay in sync
+            log::warn!("{} frames of the session recording couldn't be written in time.", self.dropped_frames);
+        }
+        let sidecar = sidecar_json(self.fps, self.frame_count, self.start_position.unwrap_or(0.0), self.audio_path.as_deref());
+        fs::write(self.folder.join(SIDECAR_FILE_NAME), sidecar)?;
+        log::info!("Session recording saved to {}.", self.folder.display());
+        Ok(self.folder)
+    }
+}
+
+fn frame_file_name(index: u64) -> String {
+    format!("frame_{index:06}.png")
+}
+
+fn write_frames(folder: &Path, receiver: Receiver<(u64, RgbaImage)>) -> anyhow::Result<()> {
+    for (index, image) in receiver {
+        image.save_with_format(folder.join(frame_file_name(index)), ImageFormat::Png)?;
+    }
+    Ok(())
+}
+
+fn sidecar_json(fps: f32, frame_count: u64, start_position: f64, audio_path: Option<&Path>) -> String {
+    let audio = match audio_path {
+        Some(path) => json::string(&path.to_string_lossy()),
+        None => "null".to_string(),
+    };
+    let start_position = json::number(start_position);
+    format!(
+        "{{\n  \"fps\": {fps},\n  \"frame_count\": {frame_count},\n  \"frame_pattern\": \"frame_%06d.png\",\n  \"start_position\": {start_position},\n  \"audio\": {audio}\n}}\n"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_follow_the_track_position() {
+        let folder = std::env::temp_dir().join(format!("session_recording_{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let image = RgbaImage::new(4, 4);
+
+        let mut recording = SessionRecording::start(folder.clone(), Some(PathBuf::from("C:\\music\\song.flac")), 10.);
+        recording.record(&image, 2.0);
+        // Paused, then a slow repaint that covers two frames
+        recording.record(&image, 2.0);
+        recording.record(&image, 2.25);
+        recording.finish().unwrap();
+
+        for index in 0..3 {
+            assert!(folder.join(frame_file_name(index)).exists());
+        }
+        assert!(!folder.join(frame_file_name(3)).exists());
+        let sidecar = fs::read_to_string(folder.join(SIDECAR_FILE_NAME)).unwrap();
+        assert!(sidecar.contains("\"fps\": 10,"));
+        assert!(sidecar.contains("\"frame_count\": 3,"));
+        assert!(sidecar.contains("\"start_position\": 2,"));
+        assert!(sidecar.contains(r#""audio": "C:\\music\\song.flac""#));
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
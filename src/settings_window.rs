@@ -1,28 +1,1220 @@
-#[derive(Debug, Clone, Default)]
+use std::{collections::VecDeque, path::PathBuf};
+
+use image::Rgba;
+
+use crate::{audio_device, note::{Pitch, PITCH_CLASS_NAMES, STANDARD_A4_FREQ}, synesthetizer::{ColorPalette, B8_FREQ, C0_FREQ, MIDI_MAX_FREQ, MIDI_MIN_FREQ}};
+
+/// How many palette edits can be undone
+const MAX_PALETTE_HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WindowFunction {
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    Rectangular,
+}
+
+impl WindowFunction {
+    pub const ALL: [Self; 4] = [Self::Hann, Self::Hamming, Self::BlackmanHarris, Self::Rectangular];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Hann => "Hann",
+            Self::Hamming => "Hamming",
+            Self::BlackmanHarris => "Blackman-Harris",
+            Self::Rectangular => "Rectangular (none)",
+        }
+    }
+}
+
+/// How the raw FFT magnitudes are scaled before they become note amplitudes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SpectrumScaling {
+    /// Raw magnitudes, which grow with the window length and are far too loud for most songs.
+    None,
+    /// Divided by the window length. Quiet, and only the strongest notes stand out.
+    DivideByN,
+    /// Divided by the square root of the window length. A balance between the two.
+    DivideBySqrtN,
+    /// Decibels, so quiet notes show up nearly as big as loud ones.
+    Decibels,
+}
+
+impl SpectrumScaling {
+    pub const ALL: [Self; 4] = [Self::None, Self::DivideByN, Self::DivideBySqrtN, Self::Decibels];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::DivideByN => "Divide by N",
+            Self::DivideBySqrtN => "Divide by √N",
+            Self::Decibels => "Decibels",
+        }
+    }
+
+    /// What the scaling does to the picture, for people who don't know DSP.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::None => "Unscaled. Almost everything is huge and bright.",
+            Self::DivideByN => "Quiet overall. Only the strongest notes stand out.",
+            Self::DivideBySqrtN => "The default. Loud notes dominate, soft ones still show.",
+            Self::Decibels => "Like hearing does. Soft notes look almost as big as loud ones.",
+        }
+    }
+}
+
+/// Which part of a stereo signal feeds the analyzer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ChannelMode {
+    Left,
+    Right,
+    /// The average of both channels.
+    MonoSum,
+    /// (L + R) / √2
+    Mid,
+    /// (L - R) / √2, only what differs between the channels.
+    Side,
+}
+
+impl ChannelMode {
+    pub const ALL: [Self; 5] = [Self::Left, Self::Right, Self::MonoSum, Self::Mid, Self::Side];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Left => "Left",
+            Self::Right => "Right",
+            Self::MonoSum => "Mono (sum)",
+            Self::Mid => "Mid",
+            Self::Side => "Side",
+        }
+    }
+}
+
+/// The shape each note is drawn as, stretched to the note's width and height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NoteShape {
+    Star,
+    Circle,
+    Square,
+    Triangle,
+}
+
+/// How a note's amplitude maps to its height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NoteSizing {
+    /// Height grows in proportion to the amplitude.
+    Linear,
+    /// Height grows with the amplitude in decibels, closer to how loud notes sound,
+    /// so quiet notes stay visible and loud ones don't fill the frame.
+    Decibels,
+}
+
+/// How a note's color comes from the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ColorMode {
+    /// Each pitch class has its palette color, and notes between two mix them.
+    Palette,
+    /// The palette colors go around a color wheel, and notes between two take the hue between them.
+    ChromaticWheel,
+    /// Like `Palette`, but lower octaves are darker and higher ones brighter.
+    OctaveBrightness,
+}
+
+impl ColorMode {
+    pub const ALL: [Self; 3] = [Self::Palette, Self::ChromaticWheel, Self::OctaveBrightness];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Palette => "Palette",
+            Self::ChromaticWheel => "Color wheel",
+            Self::OctaveBrightness => "Brighter octaves",
+        }
+    }
+}
+
+/// Where on the frame something shown over it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum OverlayPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl OverlayPosition {
+    pub const ALL: [Self; 5] = [Self::TopLeft, Self::TopRight, Self::BottomLeft, Self::BottomRight, Self::Center];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::TopLeft => "Top left",
+            Self::TopRight => "Top right",
+            Self::BottomLeft => "Bottom left",
+            Self::BottomRight => "Bottom right",
+            Self::Center => "Center",
+        }
+    }
+
+    pub fn align(&self) -> egui::Align2 {
+        match self {
+            Self::TopLeft => egui::Align2::LEFT_TOP,
+            Self::TopRight => egui::Align2::RIGHT_TOP,
+            Self::BottomLeft => egui::Align2::LEFT_BOTTOM,
+            Self::BottomRight => egui::Align2::RIGHT_BOTTOM,
+            Self::Center => egui::Align2::CENTER_CENTER,
+        }
+    }
+}
+
+/// Which notes end up in front where they overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DrawOrder {
+    /// The loudest notes are painted last, over the quieter ones.
+    LoudOnTop,
+    /// The quietest notes are painted last, so they aren't hidden behind loud ones.
+    QuietOnTop,
+}
+
+/// How a note's color combines with what's already under it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BlendMode {
+    /// Painted over what's under it, hiding it where the note is opaque.
+    Over,
+    /// The colors are added, so overlapping notes glow brighter, up to white.
+    Additive,
+    /// Like additive, but brightening less the brighter it already is, so it doesn't wash out as quickly.
+    Screen,
+    /// The brighter of the two colors, channel by channel.
+    Max,
+}
+
+/// How the display blends from one frame to the next, between the frames rendered at `Settings::fps`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FrameCrossfade {
+    /// A new frame is rendered every time the window repaints, with nothing blended.
+    Off,
+    /// Frames are rendered at `fps` and fade into each other at an even pace.
+    Linear,
+    /// Like `Linear`, but easing in and out of each frame, so it lingers on them a little.
+    Smooth,
+}
+
+impl FrameCrossfade {
+    /// How far the blend from one frame to the next has got, `t` of the way through the time between them.
+    pub fn ease(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Off => 1.0,
+            Self::Linear => t,
+            Self::Smooth => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// How the frame is scaled to fit the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CanvasFilter {
+    /// Each pixel of the frame becomes a crisp block.
+    Nearest,
+    /// Pixels blend into each other, which looks less blocky on high-DPI displays.
+    Linear,
+}
+
+impl CanvasFilter {
+    pub fn texture_options(&self) -> egui::TextureOptions {
+        match self {
+            Self::Nearest => egui::TextureOptions::NEAREST,
+            Self::Linear => egui::TextureOptions::LINEAR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SnapshotFormat {
+    Png,
+    Jpeg,
+    /// Lossless
+    WebP,
+}
+
+impl SnapshotFormat {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::Jpeg => "JPEG",
+            Self::WebP => "WebP",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_lowercase();
+        [Self::Png, Self::Jpeg, Self::WebP].into_iter()
+            .find(|format| format.extensions().contains(&extension.as_str()))
+    }
+
+    /// The first one is used when a path has no extension.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Png => &["png"],
+            Self::Jpeg => &["jpg", "jpeg"],
+            Self::WebP => &["webp"],
+        }
+    }
+}
+
+/// How a note's pitch maps to its horizontal position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PitchLayout {
+    /// Evenly spaced by MIDI number across the whole MIDI range.
+    Linear,
+    /// Evenly spaced by frequency ratio across the analyzed frequency range.
+    Logarithmic,
+}
+
+/// How notes are placed vertically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NoteLayout {
+    /// Every note on the horizontal center line.
+    Center,
+    /// One horizontal band per octave, low octaves at the bottom.
+    ByOctave,
+    /// The current notes spread evenly from bottom to top, in pitch order.
+    Spread,
+}
+
+/// What each frame shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DisplayMode {
+    Notes,
+    /// One bar per pitch class, showing how much of the frame's energy is in it.
+    Chromagram,
+    /// Notes, with a smaller chromagram along the bottom.
+    Both,
+    /// Notes of the left channel on the top half, and of the right channel on the bottom half.
+    StereoTopBottom,
+    /// Notes of the left channel on the left half, and of the right channel on the right half.
+    StereoLeftRight,
+    /// The last few seconds of spectra scrolling by, with log frequency going up.
+    Spectrogram,
+    /// Notes over a spectrogram.
+    NotesOverSpectrogram,
+}
+
+impl DisplayMode {
+    pub const ALL: [Self; 7] = [
+        Self::Notes,
+        Self::Chromagram,
+        Self::Both,
+        Self::StereoTopBottom,
+        Self::StereoLeftRight,
+        Self::Spectrogram,
+        Self::NotesOverSpectrogram,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Notes => "Notes",
+            Self::Chromagram => "Chromagram",
+            Self::Both => "Notes and chromagram",
+            Self::StereoTopBottom => "Stereo, top and bottom",
+            Self::StereoLeftRight => "Stereo, left and right",
+            Self::Spectrogram => "Spectrogram",
+            Self::NotesOverSpectrogram => "Notes over spectrogram",
+        }
+    }
+}
+
+/// How the bins of the spectrum are grouped into notes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NoteAggregation {
+    /// Each bin joins the closest note it fits in, or starts a new one.
+    Clustering,
+    /// Only bins louder than the bins on either side become notes.
+    PeakPicking,
+    /// One note per semitone, from all the bins nearest to it.
+    SemitoneBins,
+}
+
+impl NoteAggregation {
+    pub const ALL: [Self; 3] = [Self::Clustering, Self::PeakPicking, Self::SemitoneBins];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Clustering => "Clustering",
+            Self::PeakPicking => "Peaks",
+            Self::SemitoneBins => "Semitones",
+        }
+    }
+}
+
+/// The look of the controls and windows. The visualization itself isn't affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Theme {
+    Dark,
+    Light,
+    /// Whichever the operating system uses, or dark if it's unknown.
+    System,
+}
+
+impl Theme {
+    pub const ALL: [Self; 3] = [Self::Dark, Self::Light, Self::System];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Dark => "Dark",
+            Self::Light => "Light",
+            Self::System => "System",
+        }
+    }
+}
+
+/// Frame heights, with the width coming from the aspect ratio
+pub const RESOLUTION_PRESETS: [(&str, u32); 4] = [
+    ("720p", 720),
+    ("900p", 900),
+    ("1080p", 1080),
+    ("4K", 2160),
+];
+
+/// The shape of the frames, snapshots and videos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AspectRatio {
+    Widescreen,
+    Standard,
+    Square,
+    Ultrawide,
+}
+
+impl AspectRatio {
+    pub const ALL: [Self; 4] = [Self::Widescreen, Self::Standard, Self::Square, Self::Ultrawide];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Widescreen => "16:9",
+            Self::Standard => "4:3",
+            Self::Square => "1:1",
+            Self::Ultrawide => "21:9",
+        }
+    }
+
+    /// Width to height
+    fn ratio(&self) -> (u32, u32) {
+        match self {
+            Self::Widescreen => (16, 9),
+            Self::Standard => (4, 3),
+            Self::Square => (1, 1),
+            Self::Ultrawide => (21, 9),
+        }
+    }
+
+    /// The resolution of this shape that's `height` pixels high. The width is
+    /// rounded to an even number, since video encoders need one.
+    pub fn resolution(&self, height: u32) -> (u32, u32) {
+        let (width_part, height_part) = self.ratio();
+        let width = (height as f32 * width_part as f32 / height_part as f32 / 2.).round() as u32 * 2;
+        (width.max(2), height)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub is_overlay: bool,
+    /// How much of the overlay is kept each frame. 1.0 keeps it forever.
+    pub overlay_decay: f32,
+    pub frame_crossfade: FrameCrossfade,
+    /// How many spectrum frames to analyze per second of audio.
+    pub fps: f32,
+    /// Applied to each frame of samples before the FFT.
+    pub window_function: WindowFunction,
+    pub spectrum_scaling: SpectrumScaling,
+    pub channel_mode: ChannelMode,
+    pub pitch_layout: PitchLayout,
+    pub note_layout: NoteLayout,
+    pub note_shape: NoteShape,
+    pub note_sizing: NoteSizing,
+    pub draw_order: DrawOrder,
+    pub blend_mode: BlendMode,
+    pub canvas_filter: CanvasFilter,
+    pub display_mode: DisplayMode,
+    /// How much history the spectrogram shows across the frame.
+    pub spectrogram_seconds: f32,
+    pub color_mode: ColorMode,
+    /// How much each note's hue and brightness vary with its exact frequency, from 0.0 to 1.0.
+    pub color_jitter: f32,
+    /// How note amplitude maps to color intensity: `color_brightness * amplitude ^ color_gamma`.
+    pub color_gamma: f32,
+    pub color_brightness: f32,
+    /// Width and height of the frames, snapshots and videos.
+    pub resolution: (u32, u32),
+    /// Which `resolution` follows when the height is picked
+    pub aspect_ratio: AspectRatio,
+    /// How much of the previous frames' amplitude carries over into each note, from 0.0 to 1.0.
+    pub smoothing: f32,
+    /// Frames in a row a note has to be found in before it's painted, 1 to paint it right away.
+    pub note_attack_frames: u32,
+    /// Frames a note keeps being painted for after it's no longer found.
+    pub note_release_frames: u32,
+    /// Spectrum bins quieter than this are ignored.
+    pub noise_floor: f32,
+    /// Frames quieter than this many dB below full scale, by RMS, are treated as silent and not analyzed.
+    pub silence_threshold_db: f32,
+    /// Find low notes with a longer FFT window, so neighboring bass semitones don't smear together.
+    pub multi_resolution: bool,
+    /// Scale note amplitudes so the loudest recent notes stay the same size, however loud the track is.
+    pub auto_gain: bool,
+    /// How many seconds auto-gain takes to turn up after the music gets quieter.
+    pub auto_gain_release_seconds: f32,
+    /// Flash the frame when an onset, like a drum hit, is detected.
+    pub beat_flash: bool,
+    /// Draw a fading line through where each note was in the last `trail_length` frames.
+    pub note_trails: bool,
+    pub trail_length: usize,
+    /// Opacity of the newest part of a trail, from 0.0 to 1.0.
+    pub trail_opacity: f32,
+    /// From 0.0 to 1.0. Higher picks up softer onsets.
+    pub beat_sensitivity: f32,
+    /// Only frequencies from `min_frequency` to `max_frequency` Hz are analyzed.
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+    /// How many semitones of neighboring spectrum bins can merge into one note.
+    /// Wider ranges merge neighboring semitones, giving fewer and fatter notes.
+    pub max_midi_range: f32,
+    /// How different in amplitude two bins can be and still merge into one note.
+    pub max_amplitude_range: f32,
+    pub note_aggregation: NoteAggregation,
+    /// Only the loudest this many notes are kept each frame, or all of them if 0.
+    pub max_notes: usize,
+    /// The frequency of A4 in Hz, which the names and colors of notes are tuned to.
+    pub a4_frequency: f32,
+    pub snapshot_format: SnapshotFormat,
+    /// From 1 to 100, only used for JPEG snapshots.
+    pub jpeg_quality: u8,
+    /// Shown behind the notes, in sRGB.
+    pub background_color: [u8; 3],
+    /// Leave the background out of PNG and WebP snapshots, so they can be laid over other images.
+    pub transparent_snapshots: bool,
+    /// Burn the track name and position into snapshots, though not into the frame on screen.
+    pub snapshot_caption: bool,
+    pub snapshot_caption_position: OverlayPosition,
+    /// In sRGB.
+    pub snapshot_caption_color: [u8; 3],
+    /// Seconds of the song between filmstrip snapshots.
+    pub filmstrip_interval: f64,
+    /// Grid in seconds that exported MIDI notes snap to, or 0 to leave them as detected.
+    pub midi_quantization: f64,
+    /// Exported MIDI notes shorter than this many seconds are left out.
+    pub midi_min_note_duration: f64,
+    /// Save snapshots here under an automatic name, instead of asking where to save each one.
+    pub snapshot_folder: Option<PathBuf>,
+    pub show_note_readout: bool,
+    /// Show the loudest note and how far out of tune it is in large text over the frame, like a tuner.
+    pub show_dominant_note: bool,
+    pub dominant_note_position: OverlayPosition,
+    /// Show the estimated tempo over the frame, next to a light that pulses on the beat.
+    pub show_tempo: bool,
+    pub tempo_position: OverlayPosition,
+    /// Hide the control panel while the music plays and the mouse is still. Always on in fullscreen.
+    pub auto_hide_panel: bool,
+    /// The YAML palette file last loaded, or `None` for the built-in palette.
+    pub palette_path: Option<PathBuf>,
+    /// A font file used in front of the bundled fonts, for scripts they don't cover.
+    pub font_path: Option<PathBuf>,
+    /// The name of the audio output device, or `None` for the system default.
+    pub output_device: Option<String>,
+    /// Seconds that playing, pausing and stopping fade in or out for.
+    pub fade_seconds: f64,
+    pub theme: Theme,
+    /// The color of selected and highlighted controls, in sRGB.
+    pub accent_color: [u8; 3],
+    /// Send the detected notes over OSC to `osc_host` and `osc_port` every frame.
+    pub osc_enabled: bool,
+    pub osc_host: String,
+    pub osc_port: u16,
+    /// Serve the frame and notes on `localhost:server_port` for stream overlays.
+    /// Only has an effect in builds with the `server` feature.
+    pub server_enabled: bool,
+    pub server_port: u16,
+    /// Keep showing the last frame while the music plays on.
+    #[serde(skip)]
+    pub freeze_visual: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            is_overlay: false,
+            overlay_decay: 1.0,
+            frame_crossfade: FrameCrossfade::Off,
+            fps: 12.0,
+            window_function: WindowFunction::Hann,
+            spectrum_scaling: SpectrumScaling::DivideBySqrtN,
+            channel_mode: ChannelMode::MonoSum,
+            pitch_layout: PitchLayout::Linear,
+            note_layout: NoteLayout::Center,
+            note_shape: NoteShape::Star,
+            note_sizing: NoteSizing::Linear,
+            draw_order: DrawOrder::LoudOnTop,
+            blend_mode: BlendMode::Over,
+            canvas_filter: CanvasFilter::Nearest,
+            display_mode: DisplayMode::Notes,
+            spectrogram_seconds: 10.0,
+            color_mode: ColorMode::Palette,
+            color_jitter: 0.0,
+            color_gamma: 0.5,
+            color_brightness: 0.5,
+            resolution: (1600, 900),
+            aspect_ratio: AspectRatio::Widescreen,
+            smoothing: 0.0,
+            note_attack_frames: 1,
+            note_release_frames: 0,
+            noise_floor: 0.0,
+            silence_threshold_db: -60.0,
+            multi_resolution: false,
+            auto_gain: false,
+            auto_gain_release_seconds: 3.0,
+            beat_flash: false,
+            note_trails: false,
+            trail_length: 12,
+            trail_opacity: 0.6,
+            beat_sensitivity: 0.5,
+            min_frequency: C0_FREQ,
+            max_frequency: B8_FREQ,
+            max_midi_range: 1.0,
+            max_amplitude_range: 0.25,
+            note_aggregation: NoteAggregation::Clustering,
+            max_notes: 0,
+            a4_frequency: STANDARD_A4_FREQ,
+            snapshot_format: SnapshotFormat::Png,
+            jpeg_quality: 90,
+            snapshot_caption: false,
+            snapshot_caption_position: OverlayPosition::BottomLeft,
+            snapshot_caption_color: [255, 255, 255],
+            background_color: [0, 0, 0],
+            transparent_snapshots: false,
+            filmstrip_interval: 10.0,
+            midi_quantization: 0.0,
+            midi_min_note_duration: 0.1,
+            snapshot_folder: None,
+            show_note_readout: false,
+            show_dominant_note: false,
+            dominant_note_position: OverlayPosition::TopRight,
+            show_tempo: false,
+            tempo_position: OverlayPosition::TopLeft,
+            auto_hide_panel: false,
+            palette_path: None,
+            font_path: None,
+            output_device: None,
+            fade_seconds: 0.05,
+            theme: Theme::Dark,
+            accent_color: [0, 92, 128],
+            osc_enabled: false,
+            osc_host: String::from("127.0.0.1"),
+            osc_port: 9000,
+            server_enabled: false,
+            server_port: 8787,
+            freeze_visual: false,
+        }
+    }
+}
+
+impl Settings {
+    /// The analyzed frequency range in Hz, at least a semitone wide and kept within
+    /// the MIDI range at `a4_frequency`, so every note found has a band and a pitch class.
+    pub fn frequency_range(&self) -> (f32, f32) {
+        const SEMITONE: f32 = 1.059_463_1;
+
+        let lowest = Pitch::from_midi_tuned(0., self.a4_frequency).frequency();
+        let highest = Pitch::from_midi_tuned(127., self.a4_frequency).frequency();
+        let min = self.min_frequency.clamp(lowest, highest / SEMITONE);
+        let max = self.max_frequency.clamp(min * SEMITONE, highest);
+        (min, max)
+    }
+}
+
+pub enum SettingsControl {
+    ReloadFps,
+    ChangeOutputDevice,
+    ChangeFade,
+    Resize,
+    LoadPalette,
+    ExportPalettePreview,
+    LoadFont,
+    UseBundledFonts,
+    PickSnapshotFolder,
+    Nothing,
 }
 
 pub struct SettingsWindow {
     is_open: bool,
+    palette_error: Option<String>,
+    output_devices: Vec<String>,
+    /// The OSC host being typed, only applied once the field loses focus
+    osc_host: Option<String>,
+    palette_history: PaletteHistory,
+}
+
+/// The palettes from before each edit and each undo, so they can be undone and redone.
+#[derive(Default)]
+struct PaletteHistory {
+    undo: VecDeque<ColorPalette>,
+    redo: Vec<ColorPalette>,
+    /// The pitch class whose color is being dragged. Its changes make one edit, until the mouse is released.
+    editing: Option<usize>,
+}
+
+impl PaletteHistory {
+    /// Remembers `palette` from before an edit, forgetting the oldest past `MAX_PALETTE_HISTORY`.
+    fn push(&mut self, palette: ColorPalette) {
+        if self.undo.len() >= MAX_PALETTE_HISTORY {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(palette);
+        self.redo.clear();
+    }
+
+    fn undo(&mut self, palette: &mut ColorPalette) {
+        if let Some(previous) = self.undo.pop_back() {
+            self.redo.push(std::mem::replace(palette, previous));
+            self.editing = None;
+        }
+    }
+
+    fn redo(&mut self, palette: &mut ColorPalette) {
+        if let Some(next) = self.redo.pop() {
+            self.undo.push_back(std::mem::replace(palette, next));
+            self.editing = None;
+        }
+    }
 }
 
 impl SettingsWindow {
     pub fn new() -> Self {
         Self {
             is_open: false,
+            palette_error: None,
+            output_devices: Vec::new(),
+            osc_host: None,
+            palette_history: PaletteHistory::default(),
         }
     }
 
+    /// Lets a palette change made outside the window, like loading one, be undone.
+    pub fn record_palette_change(&mut self, previous: ColorPalette) {
+        self.palette_history.push(previous);
+        self.palette_history.editing = None;
+    }
+
+    pub fn set_palette_error(&mut self, error: Option<String>) {
+        self.palette_error = error;
+    }
+
     pub fn toggle_open(&mut self) {
         self.is_open = !self.is_open;
+
+        if self.is_open {
+            // Listing devices is slow, so only do it when the window opens
+            self.output_devices = audio_device::output_device_names();
+        }
     }
 
-    pub fn show(&mut self, ctx: &egui::Context, settings: &mut Settings) {
+    /// Shows the window, with an editor for `palette`.
+    pub fn show(&mut self, ctx: &egui::Context, settings: &mut Settings, palette: &mut ColorPalette) -> SettingsControl {
+        let mut control = SettingsControl::Nothing;
+
+        // Text fields have their own undo
+        if self.is_open && ctx.memory(|memory| memory.focus().is_none()) {
+            let (undo, redo) = ctx.input_mut(|i| {
+                let redo = i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y)
+                    || i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+                (i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z), redo)
+            });
+            if undo {
+                self.palette_history.undo(palette);
+            } else if redo {
+                self.palette_history.redo(palette);
+            }
+        }
+
         egui::Window::new("Settings")
             .open(&mut self.is_open)
             .show(ctx, |ui| {
+                ui.checkbox(&mut settings.freeze_visual, "Freeze visualization");
+                ui.checkbox(&mut settings.show_note_readout, "Show detected notes");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.show_dominant_note, "Show loudest note");
+                    ui.add_enabled_ui(settings.show_dominant_note, |ui| {
+                        egui::ComboBox::from_id_source("dominant_note_position")
+                            .selected_text(settings.dominant_note_position.label())
+                            .show_ui(ui, |ui| {
+                                for position in OverlayPosition::ALL {
+                                    ui.selectable_value(&mut settings.dominant_note_position, position, position.label());
+                                }
+                            });
+                    });
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.show_tempo, "Show tempo");
+                    ui.add_enabled_ui(settings.show_tempo, |ui| {
+                        egui::ComboBox::from_id_source("tempo_position")
+                            .selected_text(settings.tempo_position.label())
+                            .show_ui(ui, |ui| {
+                                for position in OverlayPosition::ALL {
+                                    ui.selectable_value(&mut settings.tempo_position, position, position.label());
+                                }
+                            });
+                    });
+                });
+                ui.checkbox(&mut settings.auto_hide_panel, "Hide controls while playing");
                 ui.checkbox(&mut settings.is_overlay, "Overlay");
+                ui.add_enabled(
+                    settings.is_overlay,
+                    egui::Slider::new(&mut settings.overlay_decay, 0.8..=1.0).text("Overlay decay"),
+                );
+
+                let fps_slider = egui::Slider::new(&mut settings.fps, 1.0..=60.0)
+                    .text("Target FPS")
+                    .max_decimals(0);
+                if ui.add(fps_slider).changed() {
+                    control = SettingsControl::ReloadFps;
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Crossfade:");
+                    ui.radio_value(&mut settings.frame_crossfade, FrameCrossfade::Off, "Off");
+                    ui.radio_value(&mut settings.frame_crossfade, FrameCrossfade::Linear, "Linear");
+                    ui.radio_value(&mut settings.frame_crossfade, FrameCrossfade::Smooth, "Smooth");
+                })
+                .response
+                .on_hover_text("Render at the target FPS and blend between frames, for fluid motion at low frame rates");
+
+                let (width, height) = settings.resolution;
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Resolution")
+                        .selected_text(format!("{width}×{height}"))
+                        .show_ui(ui, |ui| {
+                            for (name, height) in RESOLUTION_PRESETS {
+                                let resolution = settings.aspect_ratio.resolution(height);
+                                let (width, height) = resolution;
+                                let label = format!("{name} ({width}×{height})");
+                                if ui.selectable_value(&mut settings.resolution, resolution, label).changed() {
+                                    control = SettingsControl::Resize;
+                                }
+                            }
+                        });
+                    egui::ComboBox::from_label("Aspect ratio")
+                        .selected_text(settings.aspect_ratio.label())
+                        .show_ui(ui, |ui| {
+                            for aspect_ratio in AspectRatio::ALL {
+                                if ui.selectable_value(&mut settings.aspect_ratio, aspect_ratio, aspect_ratio.label()).changed() {
+                                    settings.resolution = aspect_ratio.resolution(height);
+                                    control = SettingsControl::Resize;
+                                }
+                            }
+                        });
+                });
+
+                egui::ComboBox::from_label("Window function")
+                    .selected_text(settings.window_function.label())
+                    .show_ui(ui, |ui| {
+                        for window_function in WindowFunction::ALL {
+                            ui.selectable_value(&mut settings.window_function, window_function, window_function.label());
+                        }
+                    });
+                egui::ComboBox::from_label("Spectrum scaling")
+                    .selected_text(settings.spectrum_scaling.label())
+                    .show_ui(ui, |ui| {
+                        for scaling in SpectrumScaling::ALL {
+                            ui.selectable_value(&mut settings.spectrum_scaling, scaling, scaling.label())
+                                .on_hover_text(scaling.description());
+                        }
+                    });
+
+                ui.add(egui::Slider::new(&mut settings.smoothing, 0.0..=0.95).text("Smoothing"));
+                ui.add(egui::Slider::new(&mut settings.note_attack_frames, 1..=10).text("Note attack (frames)"))
+                    .on_hover_text("How many frames in a row a note has to be found in before it's shown");
+                ui.add(egui::Slider::new(&mut settings.note_release_frames, 0..=10).text("Note release (frames)"))
+                    .on_hover_text("How many frames a note stays on screen after it's gone");
+                ui.add(egui::Slider::new(&mut settings.noise_floor, 0.0..=0.1)
+                    .logarithmic(true)
+                    .text("Noise floor"));
+                ui.add(egui::Slider::new(&mut settings.silence_threshold_db, -120.0..=-20.0)
+                    .suffix(" dB")
+                    .max_decimals(0)
+                    .text("Silence below"));
+                ui.checkbox(&mut settings.multi_resolution, "Sharper bass notes (slower)");
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.auto_gain, "Auto-gain");
+                    ui.add_enabled(
+                        settings.auto_gain,
+                        egui::Slider::new(&mut settings.auto_gain_release_seconds, 0.5..=20.0)
+                            .logarithmic(true)
+                            .suffix(" s")
+                            .text("Release"),
+                    );
+                });
+                ui.checkbox(&mut settings.beat_flash, "Flash on beats");
+                ui.add_enabled(
+                    settings.beat_flash,
+                    egui::Slider::new(&mut settings.beat_sensitivity, 0.0..=1.0).text("Beat sensitivity"),
+                );
+                ui.checkbox(&mut settings.note_trails, "Note trails");
+                ui.add_enabled(
+                    settings.note_trails,
+                    egui::Slider::new(&mut settings.trail_length, 2..=60).text("Trail length (frames)"),
+                );
+                ui.add_enabled(
+                    settings.note_trails,
+                    egui::Slider::new(&mut settings.trail_opacity, 0.0..=1.0).text("Trail opacity"),
+                );
+                // C0 to B8 by default, and as far as the MIDI range for power users
+                ui.add(egui::Slider::new(&mut settings.min_frequency, MIDI_MIN_FREQ..=MIDI_MAX_FREQ)
+                    .logarithmic(true)
+                    .suffix(" Hz")
+                    .text("Lowest frequency"));
+                ui.add(egui::Slider::new(&mut settings.max_frequency, MIDI_MIN_FREQ..=MIDI_MAX_FREQ)
+                    .logarithmic(true)
+                    .suffix(" Hz")
+                    .text("Highest frequency"));
+                ui.add(egui::Slider::new(&mut settings.a4_frequency, 415.0..=466.0)
+                    .suffix(" Hz")
+                    .max_decimals(1)
+                    .text("A4 tuning"));
+                egui::ComboBox::from_label("Note grouping")
+                    .selected_text(settings.note_aggregation.label())
+                    .show_ui(ui, |ui| {
+                        for aggregation in NoteAggregation::ALL {
+                            ui.selectable_value(&mut settings.note_aggregation, aggregation, aggregation.label());
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut settings.max_notes, 0..=64)
+                    .custom_formatter(|n, _| if n == 0.0 { String::from("Unlimited") } else { n.to_string() })
+                    .text("Most notes"));
+                // Only clustering has limits on how wide notes get
+                ui.add_enabled_ui(settings.note_aggregation == NoteAggregation::Clustering, |ui| {
+                    ui.add(egui::Slider::new(&mut settings.max_midi_range, 0.1..=6.0).text("Note width (semitones)"));
+                    ui.add(egui::Slider::new(&mut settings.max_amplitude_range, 0.01..=1.0).text("Note amplitude range"));
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Pitch layout:");
+                    ui.radio_value(&mut settings.pitch_layout, PitchLayout::Linear, "Linear");
+                    ui.radio_value(&mut settings.pitch_layout, PitchLayout::Logarithmic, "Logarithmic");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Note layout:");
+                    ui.radio_value(&mut settings.note_layout, NoteLayout::Center, "Center");
+                    ui.radio_value(&mut settings.note_layout, NoteLayout::ByOctave, "By octave");
+                    ui.radio_value(&mut settings.note_layout, NoteLayout::Spread, "Spread");
+                });
+
+                egui::ComboBox::from_label("Channel")
+                    .selected_text(settings.channel_mode.label())
+                    .show_ui(ui, |ui| {
+                        for channel_mode in ChannelMode::ALL {
+                            ui.selectable_value(&mut settings.channel_mode, channel_mode, channel_mode.label());
+                        }
+                    });
+
+                egui::ComboBox::from_label("Display")
+                    .selected_text(settings.display_mode.label())
+                    .show_ui(ui, |ui| {
+                        for display_mode in DisplayMode::ALL {
+                            ui.selectable_value(&mut settings.display_mode, display_mode, display_mode.label());
+                        }
+                    });
+                ui.add_enabled(
+                    matches!(settings.display_mode, DisplayMode::Spectrogram | DisplayMode::NotesOverSpectrogram),
+                    egui::Slider::new(&mut settings.spectrogram_seconds, 2.0..=60.0).suffix(" s").text("Spectrogram length"),
+                );
+
+                egui::ComboBox::from_label("Colors")
+                    .selected_text(settings.color_mode.label())
+                    .show_ui(ui, |ui| {
+                        for color_mode in ColorMode::ALL {
+                            ui.selectable_value(&mut settings.color_mode, color_mode, color_mode.label());
+                        }
+                    });
+                ui.add(egui::Slider::new(&mut settings.color_jitter, 0.0..=1.0).text("Color variation"));
+                ui.add(egui::Slider::new(&mut settings.color_gamma, 0.1..=2.0).text("Color gamma"));
+                ui.add(egui::Slider::new(&mut settings.color_brightness, 0.1..=2.0).text("Color brightness"));
+
+                ui.horizontal(|ui| {
+                    ui.label("Note shape:");
+                    ui.radio_value(&mut settings.note_shape, NoteShape::Star, "Star");
+                    ui.radio_value(&mut settings.note_shape, NoteShape::Circle, "Circle");
+                    ui.radio_value(&mut settings.note_shape, NoteShape::Square, "Square");
+                    ui.radio_value(&mut settings.note_shape, NoteShape::Triangle, "Triangle");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Note size:");
+                    ui.radio_value(&mut settings.note_sizing, NoteSizing::Linear, "Linear");
+                    ui.radio_value(&mut settings.note_sizing, NoteSizing::Decibels, "Decibels");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("On top:");
+                    ui.radio_value(&mut settings.draw_order, DrawOrder::LoudOnTop, "Loud notes");
+                    ui.radio_value(&mut settings.draw_order, DrawOrder::QuietOnTop, "Quiet notes");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Blending:");
+                    ui.radio_value(&mut settings.blend_mode, BlendMode::Over, "Over");
+                    ui.radio_value(&mut settings.blend_mode, BlendMode::Additive, "Additive");
+                    ui.radio_value(&mut settings.blend_mode, BlendMode::Screen, "Screen");
+                    ui.radio_value(&mut settings.blend_mode, BlendMode::Max, "Max");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Scaling:");
+                    ui.radio_value(&mut settings.canvas_filter, CanvasFilter::Nearest, "Sharp");
+                    ui.radio_value(&mut settings.canvas_filter, CanvasFilter::Linear, "Smooth");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Background:");
+                    ui.color_edit_button_srgb(&mut settings.background_color);
+                });
+
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Theme")
+                        .selected_text(settings.theme.label())
+                        .show_ui(ui, |ui| {
+                            for theme in Theme::ALL {
+                                ui.selectable_value(&mut settings.theme, theme, theme.label());
+                            }
+                        });
+                    ui.label("Accent:");
+                    ui.color_edit_button_srgb(&mut settings.accent_color);
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Snapshot format:");
+                    ui.radio_value(&mut settings.snapshot_format, SnapshotFormat::Png, "PNG");
+                    ui.radio_value(&mut settings.snapshot_format, SnapshotFormat::Jpeg, "JPEG");
+                    ui.radio_value(&mut settings.snapshot_format, SnapshotFormat::WebP, "WebP");
+                });
+                if settings.snapshot_format == SnapshotFormat::Jpeg {
+                    ui.add(egui::Slider::new(&mut settings.jpeg_quality, 1..=100).text("JPEG quality"));
+                }
+                ui.add_enabled(
+                    settings.snapshot_format != SnapshotFormat::Jpeg,
+                    egui::Checkbox::new(&mut settings.transparent_snapshots, "Transparent snapshot background"),
+                );
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.snapshot_caption, "Caption snapshots")
+                        .on_hover_text("Write the track name and time into saved snapshots");
+                    ui.add_enabled_ui(settings.snapshot_caption, |ui| {
+                        egui::ComboBox::from_id_source("snapshot_caption_position")
+                            .selected_text(settings.snapshot_caption_position.label())
+                            .show_ui(ui, |ui| {
+                                for position in OverlayPosition::ALL {
+                                    ui.selectable_value(&mut settings.snapshot_caption_position, position, position.label());
+                                }
+                            });
+                        ui.color_edit_button_srgb(&mut settings.snapshot_caption_color);
+                    });
+                });
+                ui.add(egui::Slider::new(&mut settings.filmstrip_interval, 1.0..=60.0)
+                    .suffix(" s")
+                    .text("Filmstrip interval"));
+                ui.horizontal(|ui| {
+                    ui.label("Snapshot folder:");
+                    match &settings.snapshot_folder {
+                        Some(folder) => ui.label(folder.display().to_string()),
+                        None => ui.label("Ask every time"),
+                    };
+                    if ui.button("Choose...").clicked() {
+                        control = SettingsControl::PickSnapshotFolder;
+                    }
+                    if settings.snapshot_folder.is_some() && ui.button("Clear").clicked() {
+                        settings.snapshot_folder = None;
+                    }
+                });
+
+                ui.add(egui::Slider::new(&mut settings.midi_quantization, 0.0..=1.0)
+                    .suffix(" s")
+                    .text("MIDI quantization"));
+                ui.add(egui::Slider::new(&mut settings.midi_min_note_duration, 0.0..=1.0)
+                    .suffix(" s")
+                    .text("Shortest MIDI note"));
+
+                egui::ComboBox::from_label("Output device")
+                    .selected_text(settings.output_device.as_deref().unwrap_or("System default"))
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_value(&mut settings.output_device, None, "System default").changed() {
+                            control = SettingsControl::ChangeOutputDevice;
+                        }
+                        for device in &self.output_devices {
+                            if ui.selectable_value(&mut settings.output_device, Some(device.clone()), device).changed() {
+                                control = SettingsControl::ChangeOutputDevice;
+                            }
+                        }
+                    });
+
+                ui.checkbox(&mut settings.osc_enabled, "Send notes over OSC");
+                ui.add_enabled_ui(settings.osc_enabled, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Host:");
+                        let host = self.osc_host.get_or_insert_with(|| settings.osc_host.clone());
+                        if ui.text_edit_singleline(host).lost_focus() {
+                            settings.osc_host = self.osc_host.take().unwrap();
+                        }
+                        ui.label("Port:");
+                        ui.add(egui::DragValue::new(&mut settings.osc_port));
+                    });
+                });
+
+                #[cfg(feature = "server")]
+                {
+                    ui.checkbox(&mut settings.server_enabled, "Serve frames and notes for stream overlays")
+                        .on_hover_text("Add http://localhost:<port>/ as a browser source in OBS");
+                    ui.add_enabled_ui(settings.server_enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Port:");
+                            ui.add(egui::DragValue::new(&mut settings.server_port).clamp_range(1024..=65535));
+                        });
+                    });
+                }
+
+                let fade_slider = egui::Slider::new(&mut settings.fade_seconds, 0.0..=1.0)
+                    .suffix(" s")
+                    .text("Play and pause fade");
+                if ui.add(fade_slider).changed() {
+                    control = SettingsControl::ChangeFade;
+                }
+
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Palette:");
+                    let before = palette.clone();
+                    for (idx, color) in palette.0.iter_mut().enumerate() {
+                        let mut rgb = [color[0], color[1], color[2]];
+                        if ui.color_edit_button_srgb(&mut rgb).on_hover_text(PITCH_CLASS_NAMES[idx]).changed() {
+                            *color = Rgba([rgb[0], rgb[1], rgb[2], color[3]]);
+                            if self.palette_history.editing != Some(idx) {
+                                self.palette_history.push(before.clone());
+                                self.palette_history.editing = Some(idx);
+                            }
+                        }
+                    }
+                });
+                if ctx.input(|i| i.pointer.any_released()) {
+                    self.palette_history.editing = None;
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Load palette...").clicked() {
+                        control = SettingsControl::LoadPalette;
+                    }
+                    if ui.button("Export palette preview...").clicked() {
+                        control = SettingsControl::ExportPalettePreview;
+                    }
+                    if ui.add_enabled(!self.palette_history.undo.is_empty(), egui::Button::new("Undo"))
+                        .on_hover_text("Ctrl+Z")
+                        .clicked()
+                    {
+                        self.palette_history.undo(palette);
+                    }
+                    if ui.add_enabled(!self.palette_history.redo.is_empty(), egui::Button::new("Redo"))
+                        .on_hover_text("Ctrl+Y")
+                        .clicked()
+                    {
+                        self.palette_history.redo(palette);
+                    }
+                });
+                if let Some(error) = &self.palette_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Font:");
+                    match &settings.font_path {
+                        Some(path) => ui.label(path.display().to_string()),
+                        None => ui.label("Bundled"),
+                    };
+                    if ui.button("Load font...").clicked() {
+                        control = SettingsControl::LoadFont;
+                    }
+                    if settings.font_path.is_some() && ui.button("Use bundled fonts").clicked() {
+                        control = SettingsControl::UseBundledFonts;
+                    }
+                });
             });
+
+        control
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette(value: u8) -> ColorPalette {
+        ColorPalette([Rgba([value, value, value, 255]); 12])
+    }
+
+    #[test]
+    fn palette_edits_undo_and_redo() {
+        let mut history = PaletteHistory::default();
+        let mut current = palette(0);
+        for value in 1..=3 {
+            history.push(std::mem::replace(&mut current, palette(value)));
+        }
+
+        history.undo(&mut current);
+        history.undo(&mut current);
+        assert_eq!(current.0[0][0], 1);
+        history.redo(&mut current);
+        assert_eq!(current.0[0][0], 2);
+
+        // A new edit drops what could be redone
+        history.push(std::mem::replace(&mut current, palette(9)));
+        history.redo(&mut current);
+        assert_eq!(current.0[0][0], 9);
+    }
+
+    #[test]
+    fn palette_history_is_bounded() {
+        let mut history = PaletteHistory::default();
+        let mut current = palette(0);
+        for value in 1..=MAX_PALETTE_HISTORY as u8 + 10 {
+            history.push(std::mem::replace(&mut current, palette(value)));
+        }
+        assert_eq!(history.undo.len(), MAX_PALETTE_HISTORY);
+
+        for _ in 0..MAX_PALETTE_HISTORY + 10 {
+            history.undo(&mut current);
+        }
+        assert_eq!(current.0[0][0], 10);
+    }
+
+    #[test]
+    fn aspect_ratios_give_even_widths() {
+        assert_eq!(AspectRatio::Widescreen.resolution(900), (1600, 900));
+        assert_eq!(AspectRatio::Standard.resolution(720), (960, 720));
+        assert_eq!(AspectRatio::Square.resolution(1080), (1080, 1080));
+        assert_eq!(AspectRatio::Ultrawide.resolution(1080), (2520, 1080));
+        for (_, height) in RESOLUTION_PRESETS {
+            for aspect_ratio in AspectRatio::ALL {
+                assert_eq!(aspect_ratio.resolution(height).0 % 2, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn the_frequency_range_stays_within_midi() {
+        for a4_frequency in [415.0, STANDARD_A4_FREQ, 466.0] {
+            let settings = Settings { min_frequency: 1.0, max_frequency: 20000.0, a4_frequency, ..Default::default() };
+            let (min, max) = settings.frequency_range();
+            let lowest = Pitch::from_frequency_tuned(min, a4_frequency).midi();
+            let highest = Pitch::from_frequency_tuned(max, a4_frequency).midi();
+            assert!(lowest >= -0.001 && highest <= 127.001, "{lowest} to {highest} at A4 = {a4_frequency} Hz");
+        }
+
+        // The defaults are the palette's nine octaves
+        assert_eq!(Settings::default().frequency_range(), (C0_FREQ, B8_FREQ));
     }
 }
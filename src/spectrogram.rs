@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+
+use image::{Rgba, RgbaImage};
+use spectrum_analyzer::FrequencySpectrum;
+
+use crate::{note::{Note, Pitch}, settings_window::Settings, synesthetizer::ColorPalette};
+
+/// The last few seconds of spectra, as one column of colors per `1 / settings.fps`
+/// seconds of the track, with time going left to right and log frequency going up.
+#[derive(Clone, Default)]
+pub struct Spectrogram {
+    /// From oldest to newest, each with one color per row from the lowest frequency up
+    columns: VecDeque<Vec<Rgba<u8>>>,
+    /// Seconds of the track since the newest column
+    since_column: f32,
+}
+
+impl Spectrogram {
+    /// Adds a column for `spectrum`, `rows` pixels high, for each `1 / settings.fps` seconds
+    /// in the `seconds` of the track since the last frame, dropping the oldest ones past
+    /// `settings.spectrogram_seconds`. Frames closer together than that don't add any.
+    pub fn push(&mut self, seconds: f32, spectrum: &FrequencySpectrum, rows: usize, color_palette: &ColorPalette, settings: &Settings) {
+        let due = self.columns_due(seconds, settings);
+        if due == 0 {
+            return;
+        }
+        let mut column = self.take_column(rows, settings);
+        let magnitudes = row_magnitudes(spectrum, rows, settings);
+        let (min_frequency, max_frequency) = settings.frequency_range();
+        column.extend(magnitudes.into_iter().enumerate().map(|(row, magnitude)| {
+            let frequency = min_frequency * (max_frequency / min_frequency).powf((row as f32 + 0.5) / rows as f32);
+            Note::new(Pitch::from_frequency_tuned(frequency, settings.a4_frequency), magnitude).color(color_palette, settings)
+        }));
+        self.push_repeated(column, due, settings);
+    }
+
+    /// Like `push`, with empty columns, for a frame that was too quiet to analyze.
+    pub fn push_silence(&mut self, seconds: f32, rows: usize, settings: &Settings) {
+        let due = self.columns_due(seconds, settings);
+        if due == 0 {
+            return;
+        }
+        let mut column = self.take_column(rows, settings);
+        column.resize(rows, Rgba([0; 4]));
+        self.push_repeated(column, due, settings);
+    }
+
+    /// Moves on `seconds` of the track, returning how many columns have come due since the last one.
+    fn columns_due(&mut self, seconds: f32, settings: &Settings) -> usize {
+        let column_seconds = 1.0 / settings.fps.max(1.0);
+        self.since_column += seconds;
+        // A little slack, so a frame exactly a column later isn't lost to rounding
+        let due = (self.since_column / column_seconds + 1e-3).floor();
+        self.since_column = (self.since_column - due * column_seconds).max(0.0);
+        (due as usize).min(Self::capacity(settings))
+    }
+
+    /// Adds `column` `count` times, for frames further apart than a column.
+    fn push_repeated(&mut self, column: Vec<Rgba<u8>>, count: usize, settings: &Settings) {
+        for _ in 1..count {
+            let mut copy = self.take_column(column.len(), settings);
+            copy.extend_from_slice(&column);
+            self.columns.push_back(copy);
+        }
+        self.columns.push_back(column);
+    }
+
+    /// Makes room for a new column, returning an empty one to fill.
+    fn take_column(&mut self, rows: usize, settings: &Settings) -> Vec<Rgba<u8>> {
+        let capacity = Self::capacity(settings);
+        if self.columns.front().is_some_and(|column| column.len() != rows) {
+            self.columns.clear();
+        }
+        // The oldest column is reused for the new one
+        let mut column = None;
+        while self.columns.len() >= capacity {
+            column = self.columns.pop_front();
+        }
+        let mut column = column.unwrap_or_default();
+        column.clear();
+        column
+    }
+
+    pub fn clear(&mut self) {
+        self.columns.clear();
+        self.since_column = 0.0;
+    }
+
+    /// Fills `image` with the spectrogram, the newest column on the right.
+    /// Until the history fills up, the left side stays transparent.
+    pub fn paint(&self, image: &mut RgbaImage, settings: &Settings) {
+        let capacity = Self::capacity(settings);
+        let (width, height) = image.dimensions();
+        let missing = capacity.saturating_sub(self.columns.len());
+
+        for x in 0..width {
+            let slot = x as usize * capacity / width as usize;
+            let column = slot.checked_sub(missing).and_then(|idx| self.columns.get(idx));
+            for y in 0..height {
+                let pixel = match column {
+                    // Row 0 is the lowest frequency, at the bottom
+                    Some(column) => column[((height - 1 - y) as usize * column.len() / height as usize).min(column.len() - 1)],
+                    None => Rgba([0; 4]),
+                };
+                image.put_pixel(x, y, pixel);
+            }
+        }
+    }
+
+    fn capacity(settings: &Settings) -> usize {
+        ((settings.spectrogram_seconds * settings.fps).round() as usize).max(2)
+    }
+}
+
+/// The loudest bin in each of `rows` equal slices of log frequency. Slices with no
+/// bins in them, which happens in the bass, are interpolated from the bins around them.
+fn row_magnitudes(spectrum: &FrequencySpectrum, rows: usize, settings: &Settings) -> Vec<f32> {
+    let (min_frequency, max_frequency) = settings.frequency_range();
+    let log_range = (max_frequency / min_frequency).ln();
+    let row_of = |frequency: f32| (frequency / min_frequency).ln() / log_range * rows as f32;
+
+    let mut magnitudes = vec![f32::NAN; rows];
+    for (frequency, magnitude) in spectrum.data() {
+        let row = row_of(frequency.val()).floor();
+        if (0. ..rows as f32).contains(&row) {
+            let slot = &mut magnitudes[row as usize];
+            *slot = if slot.is_nan() { magnitude.val() } else { slot.max(magnitude.val()) };
+        }
+    }
+
+    let bins = spectrum.data();
+    let mut next_bin = 0;
+    for (row, magnitude) in magnitudes.iter_mut().enumerate() {
+        if !magnitude.is_nan() {
+            continue;
+        }
+
+        let position = row as f32 + 0.5;
+        while next_bin < bins.len() && row_of(bins[next_bin].0.val()) < position {
+            next_bin += 1;
+        }
+        *magnitude = match (next_bin.checked_sub(1).map(|idx| &bins[idx]), bins.get(next_bin)) {
+            (Some((below_fr, below)), Some((above_fr, above))) => {
+                let (below_row, above_row) = (row_of(below_fr.val()), row_of(above_fr.val()));
+                let t = ((position - below_row) / (above_row - below_row).max(f32::EPSILON)).clamp(0., 1.);
+                below.val() + (above.val() - below.val()) * t
+            }
+            (Some((_, only)), None) | (None, Some((_, only))) => only.val(),
+            (None, None) => 0.,
+        };
+    }
+
+    magnitudes
+}
+
+#[cfg(test)]
+mod tests {
+    use spectrum_analyzer::{samples_fft_to_spectrum, scaling, FrequencyLimit};
+
+    use super::*;
+
+    #[test]
+    fn a_tone_is_loudest_in_its_row() {
+        const SAMPLE_RATE: u32 = 44100;
+        const ROWS: usize = 500;
+
+        let settings = Settings::default();
+        let samples: Vec<f32> = (0..4096)
+            .map(|i| (i as f32 / SAMPLE_RATE as f32 * 440. * std::f32::consts::TAU).sin())
+            .collect();
+        let (min_frequency, max_frequency) = settings.frequency_range();
+        let spectrum = samples_fft_to_spectrum(
+            &samples,
+            SAMPLE_RATE,
+            FrequencyLimit::Range(min_frequency, max_frequency),
+            Some(&scaling::divide_by_N_sqrt),
+        ).unwrap();
+
+        let magnitudes = row_magnitudes(&spectrum, ROWS, &settings);
+        assert!(magnitudes.iter().all(|magnitude| magnitude.is_finite()));
+
+        let loudest = (0..ROWS).max_by(|&a, &b| magnitudes[a].total_cmp(&magnitudes[b])).unwrap();
+        let expected = (440. / min_frequency).ln() / (max_frequency / min_frequency).ln() * ROWS as f32;
+        assert!((loudest as f32 - expected).abs() < 3., "loudest row {loudest}, expected {expected}");
+    }
+
+    #[test]
+    fn columns_follow_the_track_time() {
+        let settings = Settings { spectrogram_seconds: 10., ..Default::default() };
+        let columns_after_a_second = |rate: usize| {
+            let mut spectrogram = Spectrogram::default();
+            for _ in 0..rate {
+                spectrogram.push_silence(1. / rate as f32, 4, &settings);
+            }
+            spectrogram.columns.len()
+        };
+
+        let expected = settings.fps.round() as usize;
+        for rate in [60, expected, 5] {
+            assert_eq!(columns_after_a_second(rate), expected, "at {rate} frames a second");
+        }
+    }
+}
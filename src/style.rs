@@ -1,27 +1,28 @@
-pub fn load_style(ctx: &egui::Context) {
-    let mut style = (*ctx.style()).clone();
-    
-    use egui::{Color32, Rounding};
-    style.visuals.panel_fill = Color32::BLACK;
-    style.visuals.window_rounding = Rounding::same(5.0);
-    style.visuals.window_fill = Color32::BLACK;
+use std::path::Path;
 
-    use egui::{TextStyle::*, FontId, FontFamily};
-    style.text_styles = [
-        (Heading, FontId::new(18., FontFamily::Proportional)),
-        (Body, FontId::new(14., FontFamily::Proportional)),
-        (Button, FontId::new(14., FontFamily::Proportional)),
-        (Monospace, FontId::new(14., FontFamily::Monospace)),
-        (Small, FontId::new(10., FontFamily::Proportional)),
-    ].into();
+use crate::settings_window::{Settings, Theme};
 
-    ctx.set_style(style);
+/// The bundled proportional font
+pub const UNAGEO: &[u8] = include_bytes!("../assets/fonts/Unageo/fonts/ttf/Unageo-Regular.ttf");
+
+/// Loads the style and fonts for `settings`.
+///
+/// If `settings.font_path` can't be loaded, the bundled fonts are used and the error returned.
+pub fn load_style(ctx: &egui::Context, settings: &Settings, system_theme: Option<eframe::Theme>) -> anyhow::Result<()> {
+    reload_style(ctx, settings, system_theme);
+    load_fonts(ctx, settings.font_path.as_deref())
+}
 
+/// Installs the bundled fonts, behind the TrueType or OpenType font at `custom_font` if there is one.
+///
+/// If the custom font can't be loaded, the bundled fonts are installed alone and the error returned.
+pub fn load_fonts(ctx: &egui::Context, custom_font: Option<&Path>) -> anyhow::Result<()> {
+    use egui::FontFamily;
     let mut fonts = egui::FontDefinitions::default();
 
     fonts.font_data.insert(
         "Unageo".to_string(),
-        egui::FontData::from_static(include_bytes!("../assets/fonts/Unageo/fonts/ttf/Unageo-Regular.ttf"))
+        egui::FontData::from_static(UNAGEO)
     );
     fonts.font_data.insert(
         "TJF Optik".to_string(),
@@ -31,5 +32,59 @@ pub fn load_style(ctx: &egui::Context) {
     fonts.families.get_mut(&FontFamily::Proportional).unwrap().insert(0, "Unageo".to_string());
     fonts.families.get_mut(&FontFamily::Monospace).unwrap().insert(0, "TJF Optik".to_string());
 
+    let custom = custom_font.map(read_font).transpose();
+    if let Ok(Some(font)) = &custom {
+        fonts.font_data.insert("Custom".to_string(), font.clone());
+        fonts.families.get_mut(&FontFamily::Proportional).unwrap().insert(0, "Custom".to_string());
+        // Only for the characters TJF Optik doesn't have, so numbers keep lining up
+        fonts.families.get_mut(&FontFamily::Monospace).unwrap().insert(1, "Custom".to_string());
+    }
+
     ctx.set_fonts(fonts);
+    custom.map(|_| ())
+}
+
+fn read_font(path: &Path) -> anyhow::Result<egui::FontData> {
+    let bytes = std::fs::read(path)?;
+    // egui panics on fonts it can't read, so check first
+    ab_glyph::FontRef::try_from_slice(&bytes)
+        .map_err(|_| anyhow::anyhow!("{} isn't a font file that can be used", path.display()))?;
+    Ok(egui::FontData::from_owned(bytes))
+}
+
+/// Rebuilds the style for `settings.theme` and `settings.accent_color`.
+///
+/// `system_theme` is used for `Theme::System`, which falls back to dark when it's `None`.
+pub fn reload_style(ctx: &egui::Context, settings: &Settings, system_theme: Option<eframe::Theme>) {
+    let mut style = (*ctx.style()).clone();
+
+    let is_dark = match settings.theme {
+        Theme::Dark => true,
+        Theme::Light => false,
+        Theme::System => system_theme != Some(eframe::Theme::Light),
+    };
+
+    use egui::{Color32, Rounding, Visuals};
+    style.visuals = if is_dark { Visuals::dark() } else { Visuals::light() };
+    if is_dark {
+        style.visuals.panel_fill = Color32::BLACK;
+        style.visuals.window_fill = Color32::BLACK;
+    }
+    style.visuals.window_rounding = Rounding::same(5.0);
+
+    let [r, g, b] = settings.accent_color;
+    let accent = Color32::from_rgb(r, g, b);
+    style.visuals.selection.bg_fill = accent;
+    style.visuals.hyperlink_color = accent;
+
+    use egui::{TextStyle::*, FontId, FontFamily};
+    style.text_styles = [
+        (Heading, FontId::new(18., FontFamily::Proportional)),
+        (Body, FontId::new(14., FontFamily::Proportional)),
+        (Button, FontId::new(14., FontFamily::Proportional)),
+        (Monospace, FontId::new(14., FontFamily::Monospace)),
+        (Small, FontId::new(10., FontFamily::Proportional)),
+    ].into();
+
+    ctx.set_style(style);
 }
@@ -1,94 +1,597 @@
-use std::path::PathBuf;
+use std::{borrow::Cow, f32::consts::FRAC_1_SQRT_2, fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, sync::atomic::{AtomicUsize, Ordering}, time::Instant};
 
-use image::{Rgba, RgbaImage};
-use spectrum_analyzer::{samples_fft_to_spectrum, scaling, windows::hann_window, FrequencyLimit, FrequencySpectrum};
+use anyhow::Context;
 
-use crate::{app::MusicState, music::Music, note::{Note, Pitch}, settings_window::Settings};
+use image::{codecs::{jpeg::JpegEncoder, webp::WebPEncoder}, ColorType, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use imageproc::rect::Rect;
+use kira::sound::static_sound::StaticSoundData;
+use spectrum_analyzer::{samples_fft_to_spectrum, scaling::{self, SpectrumDataStats, SpectrumScalingFunction}, windows::{blackman_harris_4term, hamming_window, hann_window}, Frequency, FrequencyLimit, FrequencySpectrum, FrequencyValue};
 
+use crate::{app::MusicState, chromagram, frame_stats::FrameTimings, music::{self, Music}, note::{Note, Pitch}, osc::OscSender, settings_window::{ChannelMode, DisplayMode, DrawOrder, FrameCrossfade, NoteAggregation, Settings, SnapshotFormat, SpectrumScaling, WindowFunction}, spectrogram::Spectrogram, tempo::TempoTracker, text, trails::NoteTrails};
+
+/// The default analyzed range, the nine octaves from C0 to B8 that the palette's octave colors span
 pub const C0_FREQ: f32 = 16.35;
-pub const A8_FREQ: f32 = 7902.13;
+pub const B8_FREQ: f32 = 7902.13;
+/// How far the analyzed range can be stretched: the MIDI range, C-1 to G9 at standard tuning,
+/// which is what the per-band state is sized for
+pub const MIDI_MIN_FREQ: f32 = 8.18;
+pub const MIDI_MAX_FREQ: f32 = 12543.85;
+/// One per MIDI note
+const BANDS: usize = 128;
 
-pub const FRAME_WIDTH: u32 = 1600;
-pub const FRAME_HEIGHT: u32 = 900;
-pub const FRAME_WIDTH_F32: f32 = FRAME_WIDTH as f32;
-pub const FRAME_HEIGHT_F32: f32 = FRAME_HEIGHT as f32;
 
-#[derive(serde::Deserialize)]
+#[derive(Clone, serde::Deserialize)]
 pub struct ColorPalette(#[serde(deserialize_with = "from_hex")] pub [Rgba<u8>; 12]);
 
+impl ColorPalette {
+    /// The palette the app ships with, from `colors.yaml`.
+    pub fn bundled() -> Self {
+        serde_yaml::from_slice(include_bytes!("colors.yaml")).unwrap()
+    }
+
+    /// Reads the 12 colors in the YAML file at `path`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::read(path)?;
+        Ok(serde_yaml::from_slice(&file)?)
+    }
+}
+
 fn from_hex<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<[Rgba<u8>; 12], D::Error> {
+    use serde::de::Error;
+
     let list: Vec<&str> = serde::Deserialize::deserialize(deserializer)?;
-    assert_eq!(list.len(), 12);
+    if list.len() != 12 {
+        return Err(D::Error::invalid_length(list.len(), &"12 colors, one per pitch class"));
+    }
 
     let mut colors = [Rgba([255, 255, 255, 255]); 12];
 
     for (idx, str) in list.iter().enumerate() {
-        assert_eq!(str.len(), 7);
-        let r = u8::from_str_radix(&str[1..3], 16).unwrap();
-        let g = u8::from_str_radix(&str[3..5], 16).unwrap();
-        let b = u8::from_str_radix(&str[5..7], 16).unwrap();
+        // Checked byte by byte first, so slicing can't land inside a multi-byte character
+        let is_hex = str.len() == 7 && str.starts_with('#') && str.bytes().skip(1).all(|byte| byte.is_ascii_hexdigit());
+        if !is_hex {
+            return Err(D::Error::custom(format!("expected a color like '#RRGGBB', found '{str}'")));
+        }
+        let channel = |range| u8::from_str_radix(&str[range], 16).unwrap();
+        let (r, g, b) = (channel(1..3), channel(3..5), channel(5..7));
         colors[idx] = Rgba([r, g, b, 255]);
     }
 
     Ok(colors)
 }
 
+/// What a `Synesthetizer` starts out with, before it's given any `Settings`.
+///
+/// The calls that take `Settings` switch the analysis over to the ones in them,
+/// so changes in the settings window apply from the next frame.
+#[derive(Clone)]
+pub struct SynesthetizerConfig {
+    pub palette: ColorPalette,
+    /// Width and height of the frames in pixels
+    pub resolution: (u32, u32),
+    /// Frames per second of track time, which sets how many samples a frame analyzes
+    pub fps: f32,
+    pub window_function: WindowFunction,
+    pub spectrum_scaling: SpectrumScaling,
+    pub channel_mode: ChannelMode,
+}
+
+impl SynesthetizerConfig {
+    /// The frame size and analysis of `settings`, drawn in `palette`.
+    pub fn from_settings(settings: &Settings, palette: ColorPalette) -> Self {
+        Self {
+            palette,
+            resolution: settings.resolution,
+            fps: settings.fps,
+            window_function: settings.window_function,
+            spectrum_scaling: settings.spectrum_scaling,
+            channel_mode: settings.channel_mode,
+        }
+    }
+}
+
+impl Default for SynesthetizerConfig {
+    /// The default settings with the bundled palette.
+    fn default() -> Self {
+        Self::from_settings(&Settings::default(), ColorPalette::bundled())
+    }
+}
+
+/// Snapshots taken automatically every `interval` seconds of the song.
+#[derive(Clone)]
+struct Filmstrip {
+    folder: PathBuf,
+    interval: f64,
+    /// How many intervals into the song the last snapshot was taken
+    last_interval: Option<u64>,
+}
+
+/// How long a MIDI semitone band has had a note, for `settings.note_attack_frames`
+/// and `settings.note_release_frames`.
+#[derive(Clone, Default)]
+struct BandHold {
+    /// Frames in a row the band has had a note in, up to now
+    active_frames: u32,
+    /// Frames the band's last shown note keeps being painted for once it's gone
+    release_frames_left: u32,
+    /// The loudest note of the band in the last frame it was shown
+    last_note: Option<Note>,
+}
+
+#[derive(Clone)]
 pub struct Synesthetizer {
+    frame_width: u32,
+    frame_height: u32,
+    /// The analysis from the config, then from the last `Settings` given
+    fps: f32,
+    window_function: WindowFunction,
+    spectrum_scaling: SpectrumScaling,
+    channel_mode: ChannelMode,
     samples_per_frame: usize,
     current_frame: Vec<f32>,
+    /// A longer window for the low notes, empty unless `settings.multi_resolution` is on
+    bass_frame: Vec<f32>,
+    /// Sorted from quietest to loudest by `peak_amplitude`
     current_notes: Vec<Note>,
+    /// Smoothed amplitude of each MIDI semitone band, carried over between frames.
+    band_amplitudes: [f32; BANDS],
+    /// `band_amplitudes` for the right channel, when the channels are split
+    right_band_amplitudes: [f32; BANDS],
+    band_holds: [BandHold; BANDS],
+    /// `band_holds` for the right channel, when the channels are split
+    right_band_holds: [BandHold; BANDS],
+    /// Whether the last frame was quieter than `settings.silence_threshold_db`
+    is_silent: bool,
+    /// `is_silent` for the right channel, when the channels are split
+    right_is_silent: bool,
+    /// The last frame's spectrum magnitudes, for onset detection
+    previous_spectrum: Vec<f32>,
+    /// A running average of the spectral flux
+    average_flux: f32,
+    /// Whether an onset, like a drum hit, started in the last frame
+    is_beat: bool,
+    /// Whether the spectral flux was above the onset threshold in the last frame
+    was_onset: bool,
+    /// Track position of the last beat, to keep `MIN_BEAT_SECONDS` between beats
+    last_beat: Option<f64>,
+    /// Pitch class energy of the notes, averaged over the last `KEY_WINDOW_SECONDS`
+    chroma: [f32; 12],
+    /// The track position of the last analyzed frame, until playback stops
+    last_position: Option<f64>,
+    /// Seconds of the track since the frame before, which running averages are weighted by,
+    /// since frames come at the repaint rate rather than at `fps`
+    frame_seconds: f32,
+    tempo: TempoTracker,
+    /// The running peak note amplitude that auto-gain scales to `AUTO_GAIN_TARGET`
+    gain_peak: f32,
+    trails: NoteTrails,
+    spectrogram: Spectrogram,
     palette: ColorPalette,
     previous_image: RgbaImage,
+    /// The last frame returned by `new_frame`
+    last_frame: RgbaImage,
+    /// With `settings.frame_crossfade`, the frame before `last_frame`, which the display blends from
+    crossfade_from: RgbaImage,
+    /// The blend of `crossfade_from` and `last_frame` on screen, reused every repaint
+    crossfade_frame: RgbaImage,
+    /// When `last_frame` was rendered, while crossfading
+    last_render: Option<Instant>,
+    /// How long the parts of the last render took, kept only while the frame stats are shown
+    timings: Option<FrameTimings>,
     is_overlay: bool,
     snapshot_request: Option<PathBuf>,
+    filmstrip: Option<Filmstrip>,
+    osc: OscSender,
+    /// Kept as a message so the synesthetizer stays `Clone`.
+    snapshot_result: Option<Result<PathBuf, String>>,
 }
 
 impl Synesthetizer {
-    pub fn new() -> Self {
-        let palette = serde_yaml::from_slice(include_bytes!("colors.yaml")).unwrap();
+    /// A synesthetizer for `settings`, with the bundled palette.
+    pub fn new(settings: &Settings) -> Self {
+        Self::with_config(SynesthetizerConfig::from_settings(settings, ColorPalette::bundled()))
+    }
+
+    /// A synesthetizer that starts out with `config`, for use before there are any `Settings`.
+    pub fn with_config(config: SynesthetizerConfig) -> Self {
+        let SynesthetizerConfig { palette, resolution: (frame_width, frame_height), fps, window_function, spectrum_scaling, channel_mode } = config;
 
         Self {
+            frame_width,
+            frame_height,
+            fps,
+            window_function,
+            spectrum_scaling,
+            channel_mode,
             samples_per_frame: 0,
             current_frame: Vec::new(),
+            bass_frame: Vec::new(),
             current_notes: Vec::with_capacity(64), // Allocate a lot to avoid reallocating
+            band_amplitudes: [0.0; BANDS],
+            right_band_amplitudes: [0.0; BANDS],
+            band_holds: std::array::from_fn(|_| BandHold::default()),
+            right_band_holds: std::array::from_fn(|_| BandHold::default()),
+            is_silent: false,
+            right_is_silent: false,
+            gain_peak: 0.0,
+            previous_spectrum: Vec::new(),
+            average_flux: 0.0,
+            is_beat: false,
+            was_onset: false,
+            last_beat: None,
+            chroma: [0.0; 12],
+            last_position: None,
+            frame_seconds: 0.0,
+            tempo: TempoTracker::default(),
+            trails: NoteTrails::default(),
+            spectrogram: Spectrogram::default(),
             palette,
-            previous_image: RgbaImage::new(FRAME_WIDTH, FRAME_HEIGHT),
+            previous_image: RgbaImage::new(frame_width, frame_height),
+            last_frame: RgbaImage::new(frame_width, frame_height),
+            crossfade_from: RgbaImage::new(0, 0),
+            crossfade_frame: RgbaImage::new(0, 0),
+            last_render: None,
+            timings: None,
             is_overlay: false,
             snapshot_request: None,
+            filmstrip: None,
+            osc: OscSender::default(),
+            snapshot_result: None,
         }
     }
 
-    pub fn clear_overlay(&mut self) {
-        self.previous_image = RgbaImage::new(FRAME_WIDTH, FRAME_HEIGHT);
+    /// Replaces the color palette with the 12 colors in the YAML file at `path`.
+    pub fn load_palette(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.palette = ColorPalette::load(path)?;
+        log::info!("Palette loaded from {}.", path.display());
+        Ok(())
     }
 
-    pub fn load_music(&mut self, music: &Music) {
-        let target_fps = 12.;
+    pub fn palette(&self) -> &ColorPalette {
+        &self.palette
+    }
 
-        self.samples_per_frame = 2;
+    pub fn palette_mut(&mut self) -> &mut ColorPalette {
+        &mut self.palette
+    }
 
-        // The number of samples needs to be a power of two for the spectrum analyzer.
-        loop {
-            let current_diff = (music.sample_rate() as f64 / self.samples_per_frame as f64 - target_fps).abs();
-            let times_2_diff = (music.sample_rate() as f64 / (self.samples_per_frame * 2) as f64 - target_fps).abs();
+    /// The notes found in the last frame, from quietest to loudest.
+    pub fn current_notes(&self) -> &[Note] {
+        &self.current_notes
+    }
 
-            if times_2_diff < current_diff {
-                self.samples_per_frame *= 2;
-            } else {
-                break;
-            }
-        }
+    /// The energy in each pitch class from C, smoothed over the last few seconds.
+    pub fn chroma(&self) -> &[f32; 12] {
+        &self.chroma
+    }
+
+    /// The tempo estimated from the last few seconds of onsets, in beats per minute.
+    pub fn bpm(&self) -> Option<f32> {
+        self.tempo.bpm()
+    }
+
+    /// 1.0 on each beat at the estimated tempo, falling to 0.0 just before the next one.
+    pub fn beat_pulse(&self) -> f32 {
+        self.tempo.beat_pulse()
+    }
+
+    pub fn clear_overlay(&mut self) {
+        self.previous_image.fill(0);
+    }
+
+    /// Changes the size of the frames. This clears the overlay.
+    pub fn set_resolution(&mut self, (frame_width, frame_height): (u32, u32)) {
+        self.frame_width = frame_width;
+        self.frame_height = frame_height;
+        self.last_frame = RgbaImage::new(frame_width, frame_height);
+        self.previous_image = RgbaImage::new(frame_width, frame_height);
+        self.crossfade_from = RgbaImage::new(0, 0);
+    }
+
+    /// Picks `samples_per_frame` for `music` based on `settings.fps`.
+    ///
+    /// Call again whenever the target FPS changes to re-tune the loaded music.
+    pub fn load_music(&mut self, music: &Music, settings: &Settings) {
+        self.tune(music.sample_rate(), settings.fps);
+    }
+
+    fn tune(&mut self, sample_rate: u32, target_fps: f32) {
+        self.fps = target_fps;
+        self.samples_per_frame = samples_per_frame(sample_rate, target_fps);
 
         self.current_frame.clear();
         self.current_frame.reserve(self.samples_per_frame);
+        self.band_amplitudes = [0.0; BANDS];
+        self.right_band_amplitudes = [0.0; BANDS];
+        self.band_holds = std::array::from_fn(|_| BandHold::default());
+        self.right_band_holds = std::array::from_fn(|_| BandHold::default());
+        self.is_silent = false;
+        self.right_is_silent = false;
+        self.gain_peak = 0.0;
+        self.previous_spectrum.clear();
+        self.average_flux = 0.0;
+        self.was_onset = false;
+        self.last_beat = None;
+        self.last_position = None;
     }
 
-    pub fn request_snapshot(&mut self, path: PathBuf) {
+    /// Switches the analysis over to the one in `settings`.
+    ///
+    /// The target FPS only changes how many samples are analyzed once the music is re-tuned.
+    fn follow_settings(&mut self, settings: &Settings) {
+        self.window_function = settings.window_function;
+        self.spectrum_scaling = settings.spectrum_scaling;
+        self.channel_mode = settings.channel_mode;
+    }
+
+    /// Moves the track clock on to a frame at `position`. After a seek, or on the first
+    /// frame, the frame is taken to be `1 / fps` seconds after the one before.
+    fn advance_clock(&mut self, position: f64) {
+        self.frame_seconds = match self.last_position {
+            Some(last) if (last..=last + MAX_FRAME_GAP_SECONDS).contains(&position) => (position - last) as f32,
+            _ => 1.0 / self.fps.max(1.0),
+        };
+        self.last_position = Some(position);
+    }
+
+    /// Saves the next frame to `path` in `settings.snapshot_format`.
+    ///
+    /// A missing extension is filled in, but one that doesn't match the format is an error.
+    pub fn request_snapshot(&mut self, mut path: PathBuf, settings: &Settings) -> anyhow::Result<()> {
+        let format = settings.snapshot_format;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            None => {
+                path.set_extension(format.extensions()[0]);
+            }
+            Some(ext) if format.extensions().contains(&ext.to_lowercase().as_str()) => {}
+            Some(ext) => anyhow::bail!("'.{ext}' isn't a {} file extension", format.label()),
+        }
+
         self.snapshot_request = Some(path);
         log::info!("Snapshot requested.");
+        Ok(())
+    }
+
+    /// Saves a snapshot into `folder` every `interval` seconds of the song, until it stops.
+    pub fn start_filmstrip(&mut self, folder: PathBuf, interval: f64) {
+        self.filmstrip = Some(Filmstrip {
+            folder,
+            interval: interval.max(MIN_FILMSTRIP_INTERVAL),
+            last_interval: None,
+        });
+        log::info!("Filmstrip started.");
+    }
+
+    pub fn stop_filmstrip(&mut self) {
+        self.filmstrip = None;
+    }
+
+    pub fn is_filmstrip_running(&self) -> bool {
+        self.filmstrip.is_some()
+    }
+
+    /// Requests a filmstrip snapshot whenever the music crosses into a new interval.
+    fn schedule_filmstrip_snapshot(&mut self, music_state: &MusicState, settings: &Settings) {
+        let Some(filmstrip) = &mut self.filmstrip else {
+            return;
+        };
+
+        let music = match music_state {
+            MusicState::Loaded(music) if !music.is_stopped() => music,
+            MusicState::Loading(_) => return,
+            _ => {
+                log::info!("Filmstrip finished.");
+                self.filmstrip = None;
+                return;
+            }
+        };
+
+        let position = music.position();
+        let interval = (position / filmstrip.interval) as u64;
+        if filmstrip.last_interval == Some(interval) {
+            return;
+        }
+        filmstrip.last_interval = Some(interval);
+
+        let seconds = (interval as f64 * filmstrip.interval).round() as u64;
+        let file_name = format!(
+            "{}_{:02}m{:02}s.{}",
+            safe_file_name(music.name()),
+            seconds / 60,
+            seconds % 60,
+            settings.snapshot_format.extensions()[0],
+        );
+        self.snapshot_request = Some(filmstrip.folder.join(file_name));
+    }
+
+    /// The frame on screen as a snapshot would save it, with the background
+    /// or with straight alpha if `settings.transparent_snapshots` is on.
+    pub fn snapshot_image(&self, settings: &Settings) -> RgbaImage {
+        if settings.transparent_snapshots {
+            unpremultiply(&self.last_frame)
+        } else {
+            with_background(&self.last_frame, settings.background_color)
+        }
+    }
+
+    /// The outcome of the last requested snapshot, once it has been saved.
+    pub fn take_snapshot_result(&mut self) -> Option<Result<PathBuf, String>> {
+        self.snapshot_result.take()
+    }
+
+    /// Starts or stops timing the FFT, tone finding and painting of each render, for the frame stats.
+    pub fn set_timing(&mut self, on: bool) {
+        self.timings = on.then(FrameTimings::default);
+    }
+
+    /// How long the parts of the last render took, while timing.
+    pub fn timings(&self) -> Option<FrameTimings> {
+        self.timings
     }
 
     pub fn new_frame(&mut self, music_state: &MusicState, settings: &Settings) -> egui::ColorImage {
+        let playing = match music_state {
+            MusicState::Loaded(music) if !music.is_stopped() => Some((music.data(), music.position())),
+            _ => None,
+        };
+        // While crossfading, frames are rendered at `settings.fps` and blended in between,
+        // so the display lags a frame behind the music
+        let frame_duration = 1.0 / settings.fps.max(1.0);
+        let crossfading = settings.frame_crossfade != FrameCrossfade::Off;
+        let is_due = !crossfading || self.last_render.is_none_or(|last| last.elapsed().as_secs_f32() >= frame_duration);
+        if !crossfading {
+            self.last_render = None;
+        }
+
+        // While frozen, keep showing the last frame without analyzing anything
+        if !settings.freeze_visual && is_due {
+            if crossfading {
+                self.crossfade_from.clone_from(&self.last_frame);
+                self.last_render = Some(Instant::now());
+            }
+            self.render_frame(playing, settings);
+
+            if settings.osc_enabled {
+                self.osc.send_notes(&self.current_notes, &settings.osc_host, settings.osc_port);
+            }
+        }
+        self.schedule_filmstrip_snapshot(music_state, settings);
+        let image = &self.last_frame;
+
+        if let Some(path) = self.snapshot_request.take() {
+            // Burned into a copy, so the frame on screen stays clean
+            let image = match music_state {
+                MusicState::Loaded(music) if settings.snapshot_caption => {
+                    let mut image = image.clone();
+                    let caption = snapshot_caption(&music.display_name(), music.position());
+                    text::paint_caption(&mut image, &caption, settings.snapshot_caption_position, settings.snapshot_caption_color);
+                    Cow::Owned(image)
+                }
+                _ => Cow::Borrowed(image),
+            };
+            self.snapshot_result = Some(match save_snapshot(&image, &path, settings) {
+                Ok(()) => {
+                    log::info!("Snapshot saved!");
+                    Ok(path)
+                }
+                Err(e) => {
+                    log::error!("There was a problem saving the snapshot: {e:?}");
+                    // Every later one would fail the same way
+                    self.filmstrip = None;
+                    Err(e.to_string())
+                }
+            });
+        }
+
+        // Snapshots above are of the rendered frame, and only the display is blended
+        let image = match self.last_render {
+            Some(last_render) if crossfading && self.crossfade_from.dimensions() == image.dimensions() => {
+                let t = settings.frame_crossfade.ease(last_render.elapsed().as_secs_f32() / frame_duration);
+                crossfade(&self.crossfade_from, image, t, &mut self.crossfade_frame);
+                &self.crossfade_frame
+            }
+            _ => image,
+        };
+
+        egui::ColorImage {
+            size: [image.width() as usize, image.height() as usize],
+            pixels: image.pixels()
+                .map(|&pixel| {
+                    let Rgba([r, g, b, a]) = over_background(pixel, settings.background_color);
+                    egui::Color32::from_rgba_premultiplied(r, g, b, a)
+                })
+                .collect(),
+        }
+    }
+
+    /// Renders the whole song to a video file at `path` by piping raw frames into `ffmpeg`.
+    ///
+    /// Frames are generated as fast as possible at `settings.fps`, independently of
+    /// playback. `frames_done` is updated after every frame so the caller can show progress.
+    /// The audio at `audio_path` is muxed in; without one, the video is silent.
+    pub fn render_to_video(
+        &mut self,
+        sound_data: &StaticSoundData,
+        audio_path: Option<&Path>,
+        settings: &Settings,
+        path: &Path,
+        frames_done: &AtomicUsize,
+    ) -> anyhow::Result<()> {
+        self.tune(sound_data.sample_rate, settings.fps);
+        self.clear_overlay();
+
+        let mut ffmpeg = Command::new("ffmpeg");
+        ffmpeg
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", self.frame_width, self.frame_height)])
+            .args(["-r", &settings.fps.to_string()])
+            .args(["-i", "-"]);
+        if let Some(audio_path) = audio_path {
+            ffmpeg.arg("-i").arg(audio_path).args(["-map", "0:v", "-map", "1:a", "-shortest"]);
+        }
+        let mut ffmpeg = ffmpeg
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("couldn't start ffmpeg, is it installed?")?;
+
+        let mut stdin = ffmpeg.stdin.take().context("couldn't open ffmpeg's input")?;
+        let frame_count = video_frame_count(sound_data, settings.fps);
+        // Reused for every frame
+        let mut composited = Vec::with_capacity(self.last_frame.len());
+
+        for frame in 0..frame_count {
+            let position = frame as f64 / settings.fps as f64;
+            self.render_frame(Some((sound_data, position)), settings);
+
+            composited.clear();
+            composited.extend(self.last_frame.pixels().flat_map(|&pixel| over_background(pixel, settings.background_color).0));
+            stdin.write_all(&composited)?;
+            frames_done.store(frame + 1, Ordering::Relaxed);
+        }
+
+        drop(stdin);
+        let status = ffmpeg.wait()?;
+        anyhow::ensure!(status.success(), "ffmpeg exited with {status}");
+
+        log::info!("Video saved to {}.", path.display());
+        Ok(())
+    }
+
+    /// Renders the single frame heard `position` seconds into `sound_data`, without any playback.
+    pub fn render_at(&mut self, sound_data: &StaticSoundData, position: f64, settings: &Settings) -> RgbaImage {
+        self.tune(sound_data.sample_rate, settings.fps);
+        self.render_frame(Some((sound_data, position)), settings);
+        self.last_frame.clone()
+    }
+
+    /// Finds the notes of every frame of the song at `settings.fps`, without painting anything.
+    ///
+    /// `on_frame` gets each frame's position in seconds and its notes.
+    pub fn analyze_song(&mut self, sound_data: &StaticSoundData, settings: &Settings, mut on_frame: impl FnMut(f64, &[Note])) {
+        self.tune(sound_data.sample_rate, settings.fps);
+
+        self.follow_settings(settings);
+
+        for frame in 0..video_frame_count(sound_data, settings.fps) {
+            let position = frame as f64 / settings.fps as f64;
+            self.advance_clock(position);
+            self.analyze(sound_data, position, self.channel_mode, settings);
+            on_frame(position, &self.current_notes);
+        }
+    }
+
+    /// Renders one frame into `last_frame` with the notes heard at `playing`'s position, or no notes if `None`.
+    fn render_frame(&mut self, playing: Option<(&StaticSoundData, f64)>, settings: &Settings) {
+        let start = self.timings.is_some().then(Instant::now);
+        if let Some(timings) = &mut self.timings {
+            *timings = FrameTimings::default();
+        }
+        self.follow_settings(settings);
+
         if settings.is_overlay != self.is_overlay {
             self.is_overlay = settings.is_overlay;
 
@@ -98,89 +601,1104 @@ impl Synesthetizer {
             }
         }
 
-        let mut image = if self.is_overlay {
-            self.previous_image.clone()
+        // Drawn over the last frame's buffer instead of a new one
+        let mut image = std::mem::take(&mut self.last_frame);
+        if self.is_overlay {
+            if settings.overlay_decay < 1.0 {
+                fade(&mut self.previous_image, settings.overlay_decay);
+            }
+            image.copy_from_slice(&self.previous_image);
         } else {
-            RgbaImage::new(FRAME_WIDTH, FRAME_HEIGHT)
-        };
+            image.fill(0);
+        }
+        let area = Rect::at(0, 0).of_size(self.frame_width, self.frame_height);
 
-        match music_state {
-            MusicState::Loaded(music) if !music.is_stopped() => {
-                self.update_samples(music);
-                let spectrum = samples_fft_to_spectrum(
-                    &self.current_frame,
-                    music.sample_rate(),
-                    FrequencyLimit::Range(C0_FREQ, A8_FREQ),
-                    Some(&scaling::divide_by_N_sqrt),
-                ).unwrap();
-                self.find_tones(&spectrum);
-
-                for note in &self.current_notes {
-                    note.paint(&mut image, &self.palette);
+        let has_spectrogram = matches!(settings.display_mode, DisplayMode::Spectrogram | DisplayMode::NotesOverSpectrogram);
+        if !has_spectrogram || playing.is_none() {
+            self.spectrogram.clear();
+        }
+
+        if let Some((sound_data, position)) = playing {
+            self.advance_clock(position);
+            match settings.display_mode {
+                DisplayMode::StereoTopBottom | DisplayMode::StereoLeftRight => {
+                    self.render_stereo(&mut image, sound_data, position, settings);
+                }
+                _ => {
+                    let spectrum = self.analyze(sound_data, position, self.channel_mode, settings);
+                    self.detect_onset(spectrum.as_ref(), position, settings);
+
+                    if has_spectrogram {
+                        match &spectrum {
+                            Some(spectrum) => self.spectrogram.push(self.frame_seconds, spectrum, self.frame_height as usize, &self.palette, settings),
+                            None => self.spectrogram.push_silence(self.frame_seconds, self.frame_height as usize, settings),
+                        }
+                        self.spectrogram.paint(&mut image, settings);
+                    }
+                    if settings.display_mode != DisplayMode::Chromagram && settings.display_mode != DisplayMode::Spectrogram {
+                        paint_notes(&mut image, area, &self.current_notes, &self.palette, settings);
+                    }
+                    match settings.display_mode {
+                        DisplayMode::Chromagram => chromagram::paint(&mut image, &self.current_notes, &self.palette, settings, self.frame_height),
+                        DisplayMode::Both => chromagram::paint(&mut image, &self.current_notes, &self.palette, settings, self.frame_height / 4),
+                        _ => {}
+                    }
                 }
             }
-            _ => {}
+
+            self.tempo.update(position, self.is_beat);
+
+            let energy = chromagram::pitch_class_energy(&self.current_notes);
+            let weight = (self.frame_seconds / KEY_WINDOW_SECONDS).min(1.0);
+            for (chroma, energy) in self.chroma.iter_mut().zip(energy) {
+                *chroma += (energy - *chroma) * weight;
+            }
+        } else {
+            self.current_notes.clear();
+            self.is_beat = false;
+            self.was_onset = false;
+            self.last_beat = None;
+            self.chroma = [0.0; 12];
+            self.last_position = None;
+            self.tempo.clear();
         }
 
         if self.is_overlay {
-            self.previous_image = image.clone();
+            self.previous_image.copy_from_slice(&image);
+        }
+
+        // Also after saving the overlay, since the whole trail is drawn again every frame
+        let has_trails = settings.note_trails
+            && playing.is_some()
+            && matches!(settings.display_mode, DisplayMode::Notes | DisplayMode::Both | DisplayMode::NotesOverSpectrogram);
+        if has_trails {
+            self.trails.update(&self.current_notes, area, &self.palette, settings);
+            self.trails.paint(&mut image, settings);
+        } else {
+            self.trails.clear();
         }
 
-        if let Some(path) = &self.snapshot_request {
-            image::save_buffer(
-                path,
-                &image,
-                FRAME_WIDTH,
-                FRAME_HEIGHT,
-                image::ColorType::Rgba8
-            ).unwrap();
-            self.snapshot_request = None;
-            log::info!("Snapshot saved!");
+        // After saving the overlay, so flashes don't build up in it
+        if self.is_beat && settings.beat_flash {
+            flash(&mut image, BEAT_FLASH_STRENGTH);
         }
 
-        egui::ColorImage::from_rgba_premultiplied(
-            [FRAME_WIDTH as usize, FRAME_HEIGHT as usize],
-            &image,
-        )
+        self.last_frame = image;
+
+        if let (Some(start), Some(timings)) = (start, &mut self.timings) {
+            timings.paint = start.elapsed().saturating_sub(timings.fft + timings.find_tones);
+        }
     }
 
-    /// Call before `samples_fft_to_spectrum`
-    fn update_samples(&mut self, music: &Music) {
-        self.current_frame.clear();
+    /// Finds the notes in `channel_mode` of the samples at `position`, returning the spectrum they came from.
+    ///
+    /// Frames quieter than `settings.silence_threshold_db` skip the FFT and have no spectrum.
+    /// Their notes still fade out with `settings.smoothing`, the same as when a sound stops.
+    /// After silence, it takes `SILENCE_MARGIN_DB` more than the threshold to count as sound
+    /// again, so a level hovering around it doesn't make the notes flicker.
+    fn analyze(&mut self, sound_data: &StaticSoundData, position: f64, channel_mode: ChannelMode, settings: &Settings) -> Option<FrequencySpectrum> {
+        let level_db = self.update_samples(sound_data, position, channel_mode, settings);
+        let margin = if self.is_silent { SILENCE_MARGIN_DB } else { 0.0 };
+        self.is_silent = level_db < settings.silence_threshold_db + margin;
+        if self.is_silent {
+            self.current_notes.clear();
+            self.finish_notes(settings);
+            return None;
+        }
+
+        // Low sample rates, like 8 kHz voice recordings, can't hold the highest notes
+        let (min_frequency, max_frequency) = settings.frequency_range();
+        let max_frequency = max_frequency.min(sound_data.sample_rate as f32 / 2.0);
+        if min_frequency >= max_frequency {
+            self.current_notes.clear();
+            self.finish_notes(settings);
+            return None;
+        }
+        let fft_start = self.timings.is_some().then(Instant::now);
+        let spectrum = samples_fft_to_spectrum(
+            &self.current_frame,
+            sound_data.sample_rate,
+            FrequencyLimit::Range(min_frequency, max_frequency),
+            scaling_function(self.spectrum_scaling),
+        ).unwrap();
+
+        let bass_spectrum = (!self.bass_frame.is_empty() && min_frequency < BASS_CROSSOVER_FREQ).then(|| {
+            samples_fft_to_spectrum(
+                &self.bass_frame,
+                sound_data.sample_rate,
+                FrequencyLimit::Range(min_frequency, BASS_CROSSOVER_FREQ.min(max_frequency)),
+                scaling_function(self.spectrum_scaling),
+            ).unwrap()
+        });
 
-        let start_sample = (music.position() * music.sample_rate() as f64) as usize;
-        // Don't go past the end of the song!
-        let end_sample = (start_sample + self.samples_per_frame).min(music.data().frames.len());
+        let find_tones_start = self.timings.is_some().then(Instant::now);
+        self.find_tones(&spectrum, bass_spectrum.as_ref(), settings);
 
-        if end_sample > start_sample {
-            for frame in &music.data().frames[start_sample..end_sample] {
-                self.current_frame.push(frame.as_mono().left);
+        // Added to, since split channels are analyzed one after the other
+        if let (Some(fft_start), Some(find_tones_start), Some(timings)) = (fft_start, find_tones_start, &mut self.timings) {
+            timings.fft += find_tones_start - fft_start;
+            timings.find_tones += find_tones_start.elapsed();
+        }
+
+        Some(spectrum)
+    }
+
+    /// Analyzes the left and right channels separately and paints each on its own half of `image`.
+    ///
+    /// `current_notes` ends up with the notes of both channels.
+    fn render_stereo(&mut self, image: &mut RgbaImage, sound_data: &StaticSoundData, position: f64, settings: &Settings) {
+        let (width, height) = image.dimensions();
+        let (half_width, half_height, (right_x, right_y)) = match settings.display_mode {
+            DisplayMode::StereoLeftRight => (width / 2, height, (width / 2, 0)),
+            _ => (width, height / 2, (0, height / 2)),
+        };
+
+        let mut notes = Vec::new();
+        for (channel_mode, (x, y)) in [(ChannelMode::Left, (0, 0)), (ChannelMode::Right, (right_x as i32, right_y as i32))] {
+            // Each channel smooths its notes with its own history
+            if channel_mode == ChannelMode::Right {
+                std::mem::swap(&mut self.band_amplitudes, &mut self.right_band_amplitudes);
+                std::mem::swap(&mut self.band_holds, &mut self.right_band_holds);
+                std::mem::swap(&mut self.is_silent, &mut self.right_is_silent);
+            }
+
+            let spectrum = self.analyze(sound_data, position, channel_mode, settings);
+            if channel_mode == ChannelMode::Left {
+                self.detect_onset(spectrum.as_ref(), position, settings);
+            } else {
+                std::mem::swap(&mut self.band_amplitudes, &mut self.right_band_amplitudes);
+                std::mem::swap(&mut self.band_holds, &mut self.right_band_holds);
+                std::mem::swap(&mut self.is_silent, &mut self.right_is_silent);
             }
+
+            let half = Rect::at(x, y).of_size(half_width, half_height);
+            paint_notes(image, half, &self.current_notes, &self.palette, settings);
+
+            notes.append(&mut self.current_notes);
+        }
+
+        notes.sort_by(|a, b| a.peak_amplitude.total_cmp(&b.peak_amplitude));
+        self.current_notes = notes;
+    }
+
+    /// Sets `is_beat` on the frame at `position` where the spectral flux, the total rise
+    /// in magnitude since the last frame, jumps above its running average.
+    ///
+    /// An onset that lasts several frames is one beat, and beats are at least
+    /// `MIN_BEAT_SECONDS` apart. A silent frame, with no spectrum, is never a beat,
+    /// but the sound after it rises from nothing.
+    fn detect_onset(&mut self, spectrum: Option<&FrequencySpectrum>, position: f64, settings: &Settings) {
+        const AVERAGE_WEIGHT: f32 = 0.1;
+
+        let Some(spectrum) = spectrum else {
+            self.previous_spectrum.fill(0.0);
+            self.is_beat = false;
+            self.was_onset = false;
+            self.average_flux -= AVERAGE_WEIGHT * self.average_flux;
+            return;
+        };
+
+        let magnitudes = spectrum.data().iter().map(|(_, amp)| amp.val());
+
+        let flux: f32 = if self.previous_spectrum.len() == spectrum.data().len() {
+            magnitudes.clone()
+                .zip(&self.previous_spectrum)
+                .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+                .sum()
+        } else {
+            0.0
+        };
+
+        self.previous_spectrum.clear();
+        self.previous_spectrum.extend(magnitudes);
+
+        // From 3 times the average at the lowest sensitivity, down to just above it at the highest
+        let threshold = self.average_flux * (1.0 + 2.0 * (1.0 - settings.beat_sensitivity.clamp(0.0, 1.0)));
+        let is_onset = flux > threshold && flux > 0.0;
+        // A beat lasts one frame, even if the flux keeps rising. Seeking back allows one at once.
+        let is_too_soon = self.last_beat.is_some_and(|last| (last..last + MIN_BEAT_SECONDS).contains(&position));
+        self.is_beat = is_onset && !self.was_onset && !is_too_soon;
+        self.was_onset = is_onset;
+        if self.is_beat {
+            self.last_beat = Some(position);
         }
 
-        self.current_frame = hann_window(&self.current_frame);
-        self.current_frame.resize(self.samples_per_frame, 0.0)
+        self.average_flux += AVERAGE_WEIGHT * (flux - self.average_flux);
+    }
+
+    /// Call before `samples_fft_to_spectrum`
+    ///
+    /// The window is always `samples_per_frame` samples of the track, starting at
+    /// `position` seconds. Since the playback position is measured in track time, changing
+    /// the playback rate mid-song keeps the window lined up with what is heard;
+    /// only the amount of overlap between consecutive frames changes.
+    ///
+    /// With `settings.multi_resolution`, `bass_frame` also gets a longer window centered on the same samples.
+    fn update_samples(&mut self, sound_data: &StaticSoundData, position: f64, channel_mode: ChannelMode, settings: &Settings) -> f32 {
+        let start_sample = (position * sound_data.sample_rate as f64) as usize;
+        let level_db = read_samples(&mut self.current_frame, sound_data, start_sample, self.samples_per_frame, channel_mode, self.window_function);
+
+        let bass_samples = (self.samples_per_frame * BASS_WINDOW_FACTOR).min(MAX_SAMPLES_PER_FRAME);
+        if settings.multi_resolution && bass_samples > self.samples_per_frame {
+            let bass_start = (start_sample + self.samples_per_frame / 2).saturating_sub(bass_samples / 2);
+            read_samples(&mut self.bass_frame, sound_data, bass_start, bass_samples, channel_mode, self.window_function);
+        } else {
+            self.bass_frame.clear();
+        }
+        level_db
     }
 
     /// Call after `samples_fft_to_spectrum`
-    fn find_tones(&mut self, spectrum: &FrequencySpectrum) {
+    ///
+    /// Below `BASS_CROSSOVER_FREQ`, bins come from `bass_spectrum` instead when there is one.
+    fn find_tones(&mut self, spectrum: &FrequencySpectrum, bass_spectrum: Option<&FrequencySpectrum>, settings: &Settings) {
         self.current_notes.clear();
 
-        for (fr, amp) in spectrum.data() {
-            let pitch = Pitch::from_frequency(fr.val());
-            let amplitude = amp.val();
+        match bass_spectrum {
+            Some(bass_spectrum) => {
+                // Longer windows give louder bins for the same tone, by the square root of the length ratio
+                let bass_scale = (self.samples_per_frame as f32 / self.bass_frame.len() as f32).sqrt();
+                let bass_bins = bass_spectrum.data().iter().filter(|(fr, _)| fr.val() < BASS_CROSSOVER_FREQ);
+                self.merge_bins(bass_bins, bass_scale, settings);
+                let bins = spectrum.data().iter().filter(|(fr, _)| fr.val() >= BASS_CROSSOVER_FREQ);
+                self.merge_bins(bins, 1.0, settings);
+            }
+            None => self.merge_bins(spectrum.data().iter(), 1.0, settings),
+        }
+
+        // Notes merged from bins at the edges can drift just past them
+        let (min_frequency, max_frequency) = settings.frequency_range();
+        self.current_notes.retain(|note| (min_frequency..=max_frequency).contains(&note.frequency()));
+
+        if settings.auto_gain {
+            self.apply_auto_gain(settings);
+        }
+        self.finish_notes(settings);
+    }
+
+    /// Follows the loudest note of each frame, quickly when it gets louder and over
+    /// `settings.auto_gain_release_seconds` when it gets quieter, and scales the notes
+    /// so that peak comes out at `AUTO_GAIN_TARGET`.
+    fn apply_auto_gain(&mut self, settings: &Settings) {
+        let frame_peak = self.current_notes.iter().map(|note| note.peak_amplitude).fold(0.0, f32::max);
+        let seconds = if frame_peak > self.gain_peak { AUTO_GAIN_ATTACK_SECONDS } else { settings.auto_gain_release_seconds };
+        let weight = (self.frame_seconds / seconds.max(f32::EPSILON)).min(1.0);
+        self.gain_peak += (frame_peak - self.gain_peak) * weight;
+
+        let gain = (AUTO_GAIN_TARGET / self.gain_peak.max(f32::MIN_POSITIVE)).min(MAX_AUTO_GAIN);
+        for note in &mut self.current_notes {
+            note.peak_amplitude *= gain;
+            note.amp_range = note.amp_range.start * gain..note.amp_range.end * gain;
+        }
+    }
+
+    /// Smooths, debounces, limits, ranks and sorts the notes found this frame.
+    fn finish_notes(&mut self, settings: &Settings) {
+        self.smooth_amplitudes(settings.smoothing, settings.a4_frequency);
+        self.debounce_notes(settings);
+
+        if settings.max_notes > 0 && self.current_notes.len() > settings.max_notes {
+            self.current_notes.sort_by(|a, b| a.peak_amplitude.total_cmp(&b.peak_amplitude));
+            let quietest = self.current_notes.len() - settings.max_notes;
+            self.current_notes.drain(..quietest);
+        }
+
+        self.current_notes.sort_by(|a, b| a.midi().total_cmp(&b.midi()));
+        for (rank, note) in self.current_notes.iter_mut().enumerate() {
+            note.pitch_rank = rank;
+        }
+
+        self.current_notes.sort_by(|a, b| a.peak_amplitude.total_cmp(&b.peak_amplitude));
+    }
+
+    /// Groups the bins into notes as `settings.note_aggregation` says, adding to `current_notes`.
+    fn merge_bins<'a>(&mut self, bins: impl Iterator<Item = &'a (Frequency, FrequencyValue)>, scale: f32, settings: &Settings) {
+        match settings.note_aggregation {
+            NoteAggregation::Clustering => self.cluster_bins(bins, scale, settings),
+            NoteAggregation::PeakPicking => self.pick_peaks(bins, scale, settings),
+            NoteAggregation::SemitoneBins => self.bin_by_semitone(bins, scale, settings),
+        }
+    }
+
+    /// Adds each bin to the closest note, or starts a new note if it can't be included.
+    fn cluster_bins<'a>(&mut self, bins: impl Iterator<Item = &'a (Frequency, FrequencyValue)>, scale: f32, settings: &Settings) {
+        for (fr, amp) in bins {
+            let amplitude = amp.val() * scale;
+            if amplitude < settings.noise_floor {
+                continue;
+            }
+
+            let pitch = Pitch::from_frequency_tuned(fr.val(), settings.a4_frequency);
             if let Some(closest) = self.current_notes.iter_mut().min_by(|a, b| {
                 // Closest in frequency
                 a.distance_from_midi(pitch.midi()).total_cmp(&b.distance_from_midi(pitch.midi()))
             }) {
-                if let Err(_) = closest.try_include(pitch, amplitude) {
+                if let Err(_) = closest.try_include(pitch, amplitude, settings) {
                     self.current_notes.push(Note::new(pitch, amplitude))
                 }
             } else {
                 self.current_notes.push(Note::new(pitch, amplitude));
             }
         }
+    }
 
-        self.current_notes.sort_by(|a, b| a.peak_amplitude.total_cmp(&b.peak_amplitude));
+    /// Starts a note at each bin louder than its neighbors.
+    fn pick_peaks<'a>(&mut self, bins: impl Iterator<Item = &'a (Frequency, FrequencyValue)>, scale: f32, settings: &Settings) {
+        let bins: Vec<(f32, f32)> = bins.map(|(fr, amp)| (fr.val(), amp.val() * scale)).collect();
+
+        for (idx, &(frequency, amplitude)) in bins.iter().enumerate() {
+            let below = idx.checked_sub(1).map_or(0.0, |below| bins[below].1);
+            let above = bins.get(idx + 1).map_or(0.0, |&(_, above)| above);
+            // Ties go to the lower bin, so a flat peak still gives one note
+            if amplitude >= settings.noise_floor && amplitude > below && amplitude >= above {
+                self.current_notes.push(Note::new(Pitch::from_frequency_tuned(frequency, settings.a4_frequency), amplitude));
+            }
+        }
+    }
+
+    /// Adds each bin to the note of its nearest semitone.
+    fn bin_by_semitone<'a>(&mut self, bins: impl Iterator<Item = &'a (Frequency, FrequencyValue)>, scale: f32, settings: &Settings) {
+        for (fr, amp) in bins {
+            let amplitude = amp.val() * scale;
+            if amplitude < settings.noise_floor {
+                continue;
+            }
+
+            let pitch = Pitch::from_frequency_tuned(fr.val(), settings.a4_frequency);
+            let semitone = pitch.midi().round();
+            match self.current_notes.iter_mut().find(|note| note.midi_range.start.round() == semitone) {
+                Some(note) => note.include(pitch, amplitude),
+                None => self.current_notes.push(Note::new(pitch, amplitude)),
+            }
+        }
+    }
+
+    /// Hides the notes of bands that haven't had one for `settings.note_attack_frames` frames
+    /// in a row yet, and keeps painting a band's last note for `settings.note_release_frames`
+    /// frames after it goes, so notes that only show up for a frame or two don't flicker.
+    ///
+    /// A band that comes back while its note is still being released is shown at once.
+    fn debounce_notes(&mut self, settings: &Settings) {
+        let attack_frames = settings.note_attack_frames.max(1);
+        let band_of = |note: &Note| band(note.midi());
+
+        let mut has_note = [false; BANDS];
+        for note in &self.current_notes {
+            let band = band_of(note);
+            if !has_note[band] {
+                has_note[band] = true;
+                self.band_holds[band].active_frames += 1;
+            }
+        }
+
+        let holds = &mut self.band_holds;
+        let mut is_shown = [false; BANDS];
+        self.current_notes.retain(|note| {
+            let band = band_of(note);
+            let hold = &mut holds[band];
+            if hold.active_frames < attack_frames && hold.release_frames_left == 0 {
+                return false;
+            }
+
+            hold.release_frames_left = settings.note_release_frames;
+            // Two notes can round to the same band, so keep the louder
+            if !is_shown[band] || hold.last_note.as_ref().is_some_and(|last| note.peak_amplitude > last.peak_amplitude) {
+                hold.last_note = Some(note.clone());
+            }
+            is_shown[band] = true;
+            true
+        });
+
+        for (band, hold) in holds.iter_mut().enumerate() {
+            if has_note[band] {
+                continue;
+            }
+
+            hold.active_frames = 0;
+            if hold.release_frames_left > 0 {
+                hold.release_frames_left -= 1;
+                self.current_notes.extend(hold.last_note.clone());
+            } else {
+                hold.last_note = None;
+            }
+        }
+    }
+
+    /// Blends each note's amplitude with the previous frames' amplitude in its semitone band.
+    ///
+    /// Bands that were loud recently but have no note this frame fade out
+    /// instead of disappearing at once.
+    fn smooth_amplitudes(&mut self, smoothing: f32, a4_frequency: f32) {
+        const FADED_AMPLITUDE: f32 = 0.01;
+
+        if smoothing <= 0.0 {
+            return;
+        }
+
+        let mut has_note = [false; BANDS];
+
+        for note in &mut self.current_notes {
+            let band = band(note.midi());
+            let amplitude = smoothing * self.band_amplitudes[band] + (1.0 - smoothing) * note.peak_amplitude;
+
+            // Two notes can round to the same band, so keep the louder
+            if !has_note[band] || amplitude > self.band_amplitudes[band] {
+                self.band_amplitudes[band] = amplitude;
+            }
+            has_note[band] = true;
+            note.peak_amplitude = amplitude;
+        }
+
+        for (band, amplitude) in self.band_amplitudes.iter_mut().enumerate() {
+            if has_note[band] {
+                continue;
+            }
+
+            *amplitude *= smoothing;
+
+            if *amplitude > FADED_AMPLITUDE {
+                self.current_notes.push(Note::new(Pitch::from_midi_tuned(band as f32, a4_frequency), *amplitude));
+            } else {
+                *amplitude = 0.0;
+            }
+        }
+    }
+}
+
+/// The band a note at `midi` is tracked in. Notes past the ends of the MIDI range,
+/// which a widened range or an unusual tuning can find, share the end bands.
+fn band(midi: f32) -> usize {
+    midi.round().clamp(0.0, (BANDS - 1) as f32) as usize
+}
+
+/// The number of frames `render_to_video` produces for `sound_data`.
+pub fn video_frame_count(sound_data: &StaticSoundData, fps: f32) -> usize {
+    (music::duration(sound_data) * fps as f64).ceil() as usize
+}
+
+/// Filmstrip snapshots are at least this many seconds apart, so files don't pile up
+const MIN_FILMSTRIP_INTERVAL: f64 = 1.0;
+
+/// `name` with the characters that can't go in file names replaced.
+pub fn safe_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+/// Bounds for the FFT size, so frequency resolution and cost stay reasonable at any sample rate
+const MIN_SAMPLES_PER_FRAME: usize = 256;
+const MAX_SAMPLES_PER_FRAME: usize = 16384;
+
+/// The power of two number of samples that analyzes `sample_rate` audio closest to `target_fps` times a second.
+fn samples_per_frame(sample_rate: u32, target_fps: f32) -> usize {
+    if sample_rate == 0 || target_fps.is_nan() || target_fps <= 0.0 {
+        log::warn!("Can't tune to {target_fps} FPS at {sample_rate} Hz, using {MIN_SAMPLES_PER_FRAME} samples per frame.");
+        return MIN_SAMPLES_PER_FRAME;
+    }
+
+    let sample_rate = sample_rate as f64;
+    let target_fps = target_fps as f64;
+
+    let mut samples_per_frame = 2;
+
+    // The number of samples needs to be a power of two for the spectrum analyzer.
+    loop {
+        let current_diff = (sample_rate / samples_per_frame as f64 - target_fps).abs();
+        let times_2_diff = (sample_rate / (samples_per_frame * 2) as f64 - target_fps).abs();
+
+        if times_2_diff < current_diff {
+            samples_per_frame *= 2;
+        } else {
+            break;
+        }
+    }
+
+    let clamped = samples_per_frame.clamp(MIN_SAMPLES_PER_FRAME, MAX_SAMPLES_PER_FRAME);
+    if clamped != samples_per_frame {
+        log::warn!(
+            "{target_fps} FPS can't be met at {sample_rate} Hz, analyzing at {:.1} FPS instead.",
+            sample_rate / clamped as f64,
+        );
+    }
+
+    clamped
+}
+
+/// How many seconds of notes the chroma, and so the estimated key, goes by
+const KEY_WINDOW_SECONDS: f32 = 4.0;
+/// Onsets closer together than this many seconds are one beat, like a drum hit and its echo
+const MIN_BEAT_SECONDS: f64 = 0.1;
+/// How much louder than `settings.silence_threshold_db` a frame has to be to end a silence
+const SILENCE_MARGIN_DB: f32 = 3.0;
+/// Frames further apart than this in track time came from a seek rather than playback
+const MAX_FRAME_GAP_SECONDS: f64 = 1.0;
+
+/// The amplitude auto-gain brings the loudest recent notes to
+const AUTO_GAIN_TARGET: f32 = 1.0;
+/// How many seconds auto-gain takes to turn down when the music gets louder
+const AUTO_GAIN_ATTACK_SECONDS: f32 = 0.1;
+/// Near-silence is never turned up more than this, so it doesn't become loud noise
+const MAX_AUTO_GAIN: f32 = 100.0;
+
+/// Below this, notes are found in a window `BASS_WINDOW_FACTOR` times longer when
+/// `settings.multi_resolution` is on, since low semitones are only a few Hz apart.
+const BASS_CROSSOVER_FREQ: f32 = 261.63; // C4
+const BASS_WINDOW_FACTOR: usize = 4;
+
+/// Reads `len` samples of `channel_mode` from `start_sample` into `buffer` and applies
+/// `window_function`, padding with silence past the end of the song.
+///
+/// Returns the RMS level of the samples before windowing, in decibels relative to full scale.
+///
+/// Frames are always stereo by the time they get here: mono files have the same sample on both
+/// sides, so every mode but `Side` reads the mono signal, and surround files are downmixed on loading.
+fn read_samples(
+    buffer: &mut Vec<f32>,
+    sound_data: &StaticSoundData,
+    start_sample: usize,
+    len: usize,
+    channel_mode: ChannelMode,
+    window_function: WindowFunction,
+) -> f32 {
+    buffer.clear();
+
+    // Don't go past the end of the song!
+    let end_sample = (start_sample + len).min(sound_data.frames.len());
+
+    if end_sample > start_sample {
+        for frame in &sound_data.frames[start_sample..end_sample] {
+            buffer.push(match channel_mode {
+                ChannelMode::Left => frame.left,
+                ChannelMode::Right => frame.right,
+                ChannelMode::MonoSum => frame.as_mono().left,
+                ChannelMode::Mid => (frame.left + frame.right) * FRAC_1_SQRT_2,
+                ChannelMode::Side => (frame.left - frame.right) * FRAC_1_SQRT_2,
+            });
+        }
+    }
+
+    let mean_square = buffer.iter().map(|sample| sample * sample).sum::<f32>() / len.max(1) as f32;
+
+    match window_function {
+        WindowFunction::Hann => *buffer = hann_window(buffer),
+        WindowFunction::Hamming => *buffer = hamming_window(buffer),
+        WindowFunction::BlackmanHarris => *buffer = blackman_harris_4term(buffer),
+        WindowFunction::Rectangular => {}
+    }
+    buffer.resize(len, 0.0);
+    // Power, so 10 rather than 20 times the log
+    10.0 * mean_square.log10()
+}
+
+/// Decibels at and below this show as silence with `SpectrumScaling::Decibels`
+const DECIBEL_FLOOR: f32 = -60.0;
+
+fn scaling_function(scaling: SpectrumScaling) -> Option<&'static SpectrumScalingFunction> {
+    match scaling {
+        SpectrumScaling::None => None,
+        SpectrumScaling::DivideByN => Some(&scaling::divide_by_N),
+        SpectrumScaling::DivideBySqrtN => Some(&scaling::divide_by_N_sqrt),
+        SpectrumScaling::Decibels => Some(&scale_to_decibels),
+    }
+}
+
+/// Decibels of the `divide_by_N_sqrt` magnitude, mapped from `DECIBEL_FLOOR`..0 dB to 0.0..1.0,
+/// since notes need positive amplitudes.
+fn scale_to_decibels(magnitude: f32, stats: &SpectrumDataStats) -> f32 {
+    let decibels = scaling::scale_20_times_log10(scaling::divide_by_N_sqrt(magnitude, stats), stats);
+    ((decibels - DECIBEL_FLOOR) / -DECIBEL_FLOOR).max(0.0)
+}
+
+/// How far towards white a beat flashes the frame
+const BEAT_FLASH_STRENGTH: f32 = 0.2;
+
+/// Moves every pixel `strength` of the way towards opaque white.
+fn flash(image: &mut RgbaImage, strength: f32) {
+    for channel in image.iter_mut() {
+        *channel += ((255 - *channel) as f32 * strength) as u8;
+    }
+}
+
+/// Lays the premultiplied `image` over an opaque `color`.
+fn with_background(image: &RgbaImage, color: [u8; 3]) -> RgbaImage {
+    let mut composited = image.clone();
+    for pixel in composited.pixels_mut() {
+        *pixel = over_background(*pixel, color);
+    }
+    composited
+}
+
+/// Paints `notes`, sorted from quietest to loudest like `current_notes`, so that
+/// the ones `settings.draw_order` puts on top are painted last.
+fn paint_notes(image: &mut RgbaImage, area: Rect, notes: &[Note], color_palette: &ColorPalette, settings: &Settings) {
+    let paint = |note: &Note| note.paint(image, area, color_palette, settings, notes.len());
+    match settings.draw_order {
+        DrawOrder::LoudOnTop => notes.iter().for_each(paint),
+        DrawOrder::QuietOnTop => notes.iter().rev().for_each(paint),
+    }
+}
+
+/// Lays one premultiplied pixel over an opaque `color`.
+fn over_background(Rgba([r, g, b, a]): Rgba<u8>, color: [u8; 3]) -> Rgba<u8> {
+    let transparency = (255 - a) as f32 / 255.;
+    let over = |channel: u8, background: u8| channel.saturating_add((background as f32 * transparency) as u8);
+    Rgba([over(r, color[0]), over(g, color[1]), over(b, color[2]), 255])
+}
+
+/// Converts the premultiplied `image` to the straight alpha image files expect.
+fn unpremultiply(image: &RgbaImage) -> RgbaImage {
+    let mut straight = image.clone();
+    for pixel in straight.pixels_mut() {
+        let Rgba([r, g, b, a]) = *pixel;
+        if a > 0 {
+            let unmultiply = |channel: u8| (channel as u32 * 255 / a as u32).min(255) as u8;
+            *pixel = Rgba([unmultiply(r), unmultiply(g), unmultiply(b), a]);
+        }
+    }
+    straight
+}
+
+/// Writes the blend of two premultiplied frames the same size to `output`,
+/// from all `from` at a `t` of 0.0 to all `to` at 1.0.
+fn crossfade(from: &RgbaImage, to: &RgbaImage, t: f32, output: &mut RgbaImage) {
+    if output.dimensions() != to.dimensions() {
+        *output = RgbaImage::new(to.width(), to.height());
+    }
+    for ((output, &from), &to) in output.iter_mut().zip(from.iter()).zip(to.iter()) {
+        *output = (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    }
+}
+
+/// Scales every pixel of `image` towards transparent by `factor`.
+///
+/// The frames are displayed as premultiplied alpha, so the color channels
+/// are scaled along with the alpha.
+fn fade(image: &mut RgbaImage, factor: f32) {
+    for channel in image.iter_mut() {
+        *channel = (*channel as f32 * factor) as u8;
+    }
+}
+
+/// Like "Artist — Title  01:23.4".
+fn snapshot_caption(track_name: &str, position: f64) -> String {
+    // In tenths of a second, so rounding can't show 60 seconds
+    let tenths = (position.max(0.0) * 10.0).round() as u64;
+    let (minutes, tenths) = (tenths / 600, tenths % 600);
+    format!("{track_name}  {minutes:02}:{:02}.{}", tenths / 10, tenths % 10)
+}
+
+pub fn save_snapshot(image: &RgbaImage, path: &Path, settings: &Settings) -> anyhow::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    // JPEG has no alpha, so it always gets the background
+    let image = if settings.transparent_snapshots && settings.snapshot_format != SnapshotFormat::Jpeg {
+        unpremultiply(image)
+    } else {
+        with_background(image, settings.background_color)
+    };
+
+    match settings.snapshot_format {
+        SnapshotFormat::Png => {
+            image.write_to(&mut file, ImageFormat::Png)?;
+        }
+        SnapshotFormat::Jpeg => {
+            let image = DynamicImage::ImageRgba8(image).into_rgb8();
+            JpegEncoder::new_with_quality(&mut file, settings.jpeg_quality).encode_image(&image)?;
+        }
+        SnapshotFormat::WebP => {
+            WebPEncoder::new_lossless(&mut file).encode(&image, image.width(), image.height(), ColorType::Rgba8)?;
+        }
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{SQRT_2, TAU};
+
+    use kira::dsp::Frame;
+
+    use super::*;
+    use crate::{frame_stats, music};
+
+    /// A few seconds of a C major chord.
+    fn chord(sample_rate: u32) -> StaticSoundData {
+        tones(&[261.63, 329.63, 392.0, 523.25], sample_rate)
+    }
+
+    /// A few seconds of sine waves at `frequencies`, played together.
+    fn tones(frequencies: &[f32], sample_rate: u32) -> StaticSoundData {
+        let frames = (0..sample_rate * 4)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                Frame::from_mono(frequencies.iter().map(|f| (TAU * f * t).sin()).sum::<f32>() * 0.2)
+            })
+            .collect();
+
+        music::sound_data_from_frames(frames, sample_rate)
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture --test-threads=1 frame_benchmark`
+    #[test]
+    #[ignore]
+    fn frame_benchmark() {
+        const FRAMES: usize = 48;
+
+        let settings = Settings { resolution: (1920, 1080), is_overlay: true, ..Default::default() };
+        let sound_data = chord(44100);
+        let mut synesthetizer = Synesthetizer::new(&settings);
+        synesthetizer.render_at(&sound_data, 0.0, &settings);
+
+        frame_stats::count_allocations(true);
+        let (allocations, bytes) = frame_stats::allocation_counts().unwrap();
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            synesthetizer.render_frame(Some((&sound_data, frame as f64 / settings.fps as f64)), &settings);
+        }
+        let elapsed = start.elapsed();
+        frame_stats::count_allocations(false);
+        let (end_allocations, end_bytes) = frame_stats::allocation_counts().unwrap();
+
+        println!(
+            "1080p: {:.2} ms, {} allocations, {} KiB allocated per frame",
+            elapsed.as_secs_f64() * 1000.0 / FRAMES as f64,
+            (end_allocations - allocations) / FRAMES,
+            (end_bytes - bytes) / FRAMES / 1024,
+        );
+    }
+
+    /// Plays the first `seconds` of `sound_data`, rendering `rate` frames per second of the track.
+    fn play(synesthetizer: &mut Synesthetizer, sound_data: &StaticSoundData, seconds: f64, rate: f64, settings: &Settings) {
+        for frame in 0..(seconds * rate) as usize {
+            synesthetizer.render_frame(Some((sound_data, frame as f64 / rate)), settings);
+        }
+    }
+
+    #[test]
+    fn the_key_window_is_in_track_time() {
+        let settings = Settings { resolution: (64, 36), ..Default::default() };
+        let sound_data = chord(44100);
+        let chroma_at = |rate: f64| {
+            let mut synesthetizer = tuned(&sound_data, &settings);
+            play(&mut synesthetizer, &sound_data, 2.0, rate, &settings);
+            *synesthetizer.chroma()
+        };
+
+        // Repainting faster than `settings.fps` doesn't shorten the window
+        let (slow, fast) = (chroma_at(settings.fps as f64), chroma_at(60.0));
+        for (slow, fast) in slow.iter().zip(fast) {
+            assert!((slow - fast).abs() <= 0.05 * slow.max(fast), "{slow} at the target FPS, {fast} at 60 FPS");
+        }
+    }
+
+    /// A synesthetizer for `settings`, tuned to `sound_data`.
+    fn tuned(sound_data: &StaticSoundData, settings: &Settings) -> Synesthetizer {
+        let mut synesthetizer = Synesthetizer::new(settings);
+        synesthetizer.tune(sound_data.sample_rate, settings.fps);
+        synesthetizer
+    }
+
+    /// A synesthetizer for `settings` that has analyzed the frame at `position` in `sound_data`.
+    fn analyzed(sound_data: &StaticSoundData, position: f64, settings: &Settings) -> Synesthetizer {
+        let mut synesthetizer = tuned(sound_data, settings);
+        synesthetizer.advance_clock(position);
+        synesthetizer.analyze(sound_data, position, settings.channel_mode, settings);
+        synesthetizer
+    }
+
+    fn chord_notes(note_aggregation: NoteAggregation) -> Vec<Note> {
+        let settings = Settings { note_aggregation, ..Default::default() };
+        analyzed(&chord(44100), 1.0, &settings).current_notes
+    }
+
+    #[test]
+    fn a_sine_wave_is_found_at_its_note() {
+        let settings = Settings::default();
+        for (frequency, midi) in [(220.0, 57), (440.0, 69), (1760.0, 93)] {
+            let sound_data = tones(&[frequency], 48000);
+            let synesthetizer = analyzed(&sound_data, 1.0, &settings);
+
+            let loudest = synesthetizer.current_notes().last().expect("no notes found");
+            assert_eq!(loudest.midi().round() as i32, midi, "at {frequency} Hz");
+        }
+    }
+
+    #[test]
+    fn every_spectrum_scaling_finds_the_tone() {
+        let sound_data = tones(&[440.0], 48000);
+        for spectrum_scaling in SpectrumScaling::ALL {
+            let settings = Settings { spectrum_scaling, ..Default::default() };
+            let synesthetizer = analyzed(&sound_data, 1.0, &settings);
+
+            let loudest = synesthetizer.current_notes().last().expect("no notes found");
+            assert_eq!(loudest.midi().round() as i32, 69, "with {spectrum_scaling:?}");
+            assert!(loudest.amplitude().is_finite() && loudest.amplitude() > 0.0, "with {spectrum_scaling:?}");
+        }
+    }
+
+    #[test]
+    fn auto_gain_evens_out_loudness() {
+        let settings = Settings { auto_gain: true, ..Default::default() };
+        let loudest_after_a_while = |volume: f32| {
+            let frames = tones(&[440.0], 48000).frames.iter().map(|&frame| frame * volume).collect();
+            let sound_data = music::sound_data_from_frames(frames, 48000);
+            let mut synesthetizer = tuned(&sound_data, &settings);
+            for frame in 0..24 {
+                let position = frame as f64 / settings.fps as f64;
+                synesthetizer.advance_clock(position);
+                synesthetizer.analyze(&sound_data, position, settings.channel_mode, &settings);
+            }
+            synesthetizer.current_notes().last().unwrap().amplitude()
+        };
+
+        let (quiet, loud) = (loudest_after_a_while(0.05), loudest_after_a_while(1.0));
+        assert!((quiet - AUTO_GAIN_TARGET).abs() < 0.1, "quiet track peaks at {quiet}");
+        assert!((loud - AUTO_GAIN_TARGET).abs() < 0.1, "loud track peaks at {loud}");
+    }
+
+    #[test]
+    fn auto_gain_releases_in_track_time() {
+        let settings = Settings { auto_gain: true, resolution: (64, 36), ..Default::default() };
+        // A loud second, then the same tone much quieter
+        let frames = tones(&[440.0], 48000).frames.iter().enumerate()
+            .map(|(idx, &frame)| if idx < 48000 { frame } else { frame * 0.1 })
+            .collect();
+        let sound_data = music::sound_data_from_frames(frames, 48000);
+        let gain_peak_at = |rate: f64| {
+            let mut synesthetizer = tuned(&sound_data, &settings);
+            play(&mut synesthetizer, &sound_data, 2.0, rate, &settings);
+            synesthetizer.gain_peak
+        };
+
+        // A second into the quiet part, a 3 second release has only come partway down at any repaint rate
+        let (slow, fast) = (gain_peak_at(settings.fps as f64), gain_peak_at(60.0));
+        assert!((slow - fast).abs() <= 0.05 * slow, "{slow} at the target FPS, {fast} at 60 FPS");
+    }
+
+    #[test]
+    fn silence_fades_the_notes_out() {
+        let settings = Settings { smoothing: 0.5, ..Default::default() };
+        let mut frames = tones(&[440.0], 48000).frames.to_vec();
+        frames.truncate(48000);
+        frames.resize(96000, Frame::ZERO);
+        let sound_data = music::sound_data_from_frames(frames, 48000);
+
+        let mut synesthetizer = tuned(&sound_data, &settings);
+        assert!(synesthetizer.analyze(&sound_data, 0.5, settings.channel_mode, &settings).is_some());
+        let loud = synesthetizer.current_notes().last().unwrap().amplitude();
+
+        // The first silent frame still has the fading note, and later ones have nothing
+        assert!(synesthetizer.analyze(&sound_data, 1.2, settings.channel_mode, &settings).is_none());
+        let fading = synesthetizer.current_notes().last().expect("the note vanished at once").amplitude();
+        assert!(fading < loud);
+        for _ in 0..20 {
+            synesthetizer.analyze(&sound_data, 1.5, settings.channel_mode, &settings);
+        }
+        assert!(synesthetizer.current_notes().is_empty());
+    }
+
+    #[test]
+    fn silence_is_measured_before_windowing_and_ends_above_a_margin() {
+        let settings = Settings::default();
+        let levels_db = [-50.0, -58.0, -70.0, -58.0, -50.0];
+        let frames = (0..48000 * levels_db.len())
+            .map(|i| {
+                let t = i as f32 / 48000.0;
+                let amplitude = SQRT_2 * 10f32.powf(levels_db[i / 48000] / 20.0);
+                Frame::from_mono((TAU * 440.0 * t).sin() * amplitude)
+            })
+            .collect();
+        let sound_data = music::sound_data_from_frames(frames, 48000);
+
+        let mut synesthetizer = tuned(&sound_data, &settings);
+        let is_sound: Vec<bool> = (0..levels_db.len())
+            .map(|second| synesthetizer.analyze(&sound_data, second as f64 + 0.5, settings.channel_mode, &settings).is_some())
+            .collect();
+        // -58 dB is under the threshold with a Hann window, and under the margin after silence
+        assert_eq!(is_sound, [true, true, false, false, true]);
+    }
+
+    #[test]
+    fn short_notes_are_debounced() {
+        let settings = Settings { note_attack_frames: 3, note_release_frames: 2, ..Default::default() };
+        let mut frames = tones(&[440.0], 48000).frames.to_vec();
+        frames.truncate(48000);
+        frames.resize(96000, Frame::ZERO);
+        let sound_data = music::sound_data_from_frames(frames, 48000);
+
+        let mut synesthetizer = tuned(&sound_data, &settings);
+        let mut has_a4 = |position: f64| {
+            synesthetizer.analyze(&sound_data, position, settings.channel_mode, &settings);
+            synesthetizer.current_notes().iter().any(|note| note.midi().round() == 69.0)
+        };
+
+        // Shown from the third frame in a row with the note, for two frames after it's gone
+        let shown: Vec<bool> = [0.1, 0.2, 0.3, 0.4, 1.2, 1.3, 1.4].into_iter().map(&mut has_a4).collect();
+        assert_eq!(shown, [false, false, true, true, true, true, false]);
+    }
+
+    #[test]
+    fn peak_picking_finds_the_chord() {
+        let notes = chord_notes(NoteAggregation::PeakPicking);
+        let mut loudest: Vec<i32> = notes.iter().rev().take(4).map(|note| note.midi().round() as i32).collect();
+        loudest.sort();
+        assert_eq!(loudest, [60, 64, 67, 72]);
+    }
+
+    #[test]
+    fn semitone_bins_give_one_note_per_semitone() {
+        let notes = chord_notes(NoteAggregation::SemitoneBins);
+        let mut semitones: Vec<i32> = notes.iter().map(|note| note.midi().round() as i32).collect();
+        semitones.sort();
+        let count = semitones.len();
+        semitones.dedup();
+        assert_eq!(semitones.len(), count);
+        for semitone in [60, 64, 67, 72] {
+            assert!(semitones.contains(&semitone), "no note at {semitone}");
+        }
+    }
+
+    #[test]
+    fn only_the_loudest_notes_are_kept() {
+        let all_notes = chord_notes(NoteAggregation::PeakPicking);
+
+        let settings = Settings { note_aggregation: NoteAggregation::PeakPicking, max_notes: 4, ..Default::default() };
+        let sound_data = chord(44100);
+        let synesthetizer = analyzed(&sound_data, 1.0, &settings);
+
+        let notes = synesthetizer.current_notes();
+        assert!(all_notes.len() > 4);
+        assert_eq!(notes.len(), 4);
+        let quietest_kept = notes[0].peak_amplitude;
+        assert_eq!(all_notes.iter().filter(|note| note.peak_amplitude >= quietest_kept).count(), 4);
+        // Ranks only count the kept notes
+        assert!(notes.iter().all(|note| note.pitch_rank < 4));
+    }
+
+    #[test]
+    fn samples_per_frame_stays_in_bounds() {
+        for sample_rate in [8000, 22050, 44100, 48000, 96000, 192000] {
+            for fps in [0.5, 1.0, 12.0, 60.0, 1000.0] {
+                let samples = samples_per_frame(sample_rate, fps);
+                assert!(samples.is_power_of_two(), "{samples} at {sample_rate} Hz, {fps} FPS");
+                assert!((MIN_SAMPLES_PER_FRAME..=MAX_SAMPLES_PER_FRAME).contains(&samples));
+            }
+        }
+    }
+
+    #[test]
+    fn samples_per_frame_is_closest_to_target_fps() {
+        assert_eq!(samples_per_frame(8000, 12.0), 512);
+        assert_eq!(samples_per_frame(22050, 12.0), 2048);
+        assert_eq!(samples_per_frame(44100, 12.0), 4096);
+        assert_eq!(samples_per_frame(48000, 12.0), 4096);
+        assert_eq!(samples_per_frame(96000, 12.0), 8192);
+        assert_eq!(samples_per_frame(192000, 12.0), 16384);
+    }
+
+    #[test]
+    fn samples_per_frame_handles_nonsense_input() {
+        assert_eq!(samples_per_frame(0, 12.0), MIN_SAMPLES_PER_FRAME);
+        assert_eq!(samples_per_frame(44100, 0.0), MIN_SAMPLES_PER_FRAME);
+        assert_eq!(samples_per_frame(44100, f32::NAN), MIN_SAMPLES_PER_FRAME);
+    }
+
+    #[test]
+    fn crossfades_blend_from_the_previous_frame() {
+        let from = RgbaImage::from_pixel(2, 2, Rgba([200, 0, 100, 255]));
+        let to = RgbaImage::from_pixel(2, 2, Rgba([0, 100, 100, 255]));
+        let mut output = RgbaImage::new(0, 0);
+
+        crossfade(&from, &to, 0.0, &mut output);
+        assert_eq!(output, from);
+        crossfade(&from, &to, 0.5, &mut output);
+        assert_eq!(*output.get_pixel(1, 1), Rgba([100, 50, 100, 255]));
+        crossfade(&from, &to, 1.0, &mut output);
+        assert_eq!(output, to);
+
+        for curve in [FrameCrossfade::Linear, FrameCrossfade::Smooth] {
+            assert_eq!(curve.ease(0.0), 0.0);
+            assert_eq!(curve.ease(0.5), 0.5);
+            // Repaints that come late stay on the new frame
+            assert_eq!(curve.ease(1.5), 1.0);
+        }
+        assert!(FrameCrossfade::Smooth.ease(0.1) < FrameCrossfade::Linear.ease(0.1));
+    }
+
+    #[test]
+    fn notes_are_painted_in_the_configured_palette() {
+        let settings = Settings { resolution: (160, 90), ..Default::default() };
+        let red = ColorPalette([Rgba([255, 0, 0, 255]); 12]);
+        let mut synesthetizer = Synesthetizer::with_config(SynesthetizerConfig::from_settings(&settings, red));
+        let image = synesthetizer.render_at(&tones(&[440.0], 48000), 1.0, &settings);
+
+        assert!(image.pixels().any(|pixel| pixel[0] > 0), "nothing was painted");
+        assert!(image.pixels().all(|pixel| pixel[1] == 0 && pixel[2] == 0));
+    }
+
+    #[test]
+    fn the_config_analyzes_until_settings_are_followed() {
+        let settings = Settings::default();
+        let sound_data = tones(&[440.0], 48000);
+        let loudest = |synesthetizer: &mut Synesthetizer| {
+            synesthetizer.tune(sound_data.sample_rate, settings.fps);
+            synesthetizer.analyze(&sound_data, 1.0, settings.channel_mode, &settings);
+            synesthetizer.current_notes().last().map(Note::amplitude)
+        };
+
+        let config = SynesthetizerConfig { spectrum_scaling: SpectrumScaling::None, ..Default::default() };
+        let mut configured = Synesthetizer::with_config(config);
+        let mut from_settings = Synesthetizer::new(&settings);
+        assert_ne!(loudest(&mut configured), loudest(&mut from_settings));
+
+        configured.follow_settings(&settings);
+        assert_eq!(loudest(&mut configured), loudest(&mut from_settings));
+    }
+
+    #[test]
+    fn malformed_palette_colors_are_rejected() {
+        let palette = |color: &str| {
+            let colors = vec![format!("'{color}'"); 12].join(", ");
+            serde_yaml::from_str::<ColorPalette>(&format!("[{colors}]"))
+        };
+        assert_eq!(palette("#FF8000").unwrap().0[0], Rgba([255, 128, 0, 255]));
+        for color in ["#aé€", "#+f+f+f", "#12345", "FF8000", "#GGGGGG"] {
+            let Err(error) = palette(color) else { panic!("{color} was read as a color") };
+            assert!(error.to_string().contains("#RRGGBB"), "{color}: {error}");
+        }
+    }
+
+    #[test]
+    fn a_sustained_onset_is_one_beat() {
+        let settings = Settings { resolution: (64, 36), ..Default::default() };
+        // A second under the silence threshold, then a swell that keeps getting louder for a second
+        let frames = tones(&[440.0], 48000).frames.iter().enumerate()
+            .map(|(idx, &frame)| {
+                let t = idx as f32 / 48000.0;
+                frame * if t < 1.0 { 0.005 } else { 0.005 * 100f32.powf((t - 1.0).min(1.0)) }
+            })
+            .collect();
+        let sound_data = music::sound_data_from_frames(frames, 48000);
+        let mut synesthetizer = tuned(&sound_data, &settings);
+
+        let mut beats = Vec::new();
+        for frame in 0..(2.0 * settings.fps) as usize {
+            let position = frame as f64 / settings.fps as f64;
+            synesthetizer.render_frame(Some((&sound_data, position)), &settings);
+            if synesthetizer.is_beat {
+                beats.push(position);
+            }
+        }
+        assert_eq!(beats.len(), 1, "beats at {beats:?}");
+    }
+
+    #[test]
+    fn snapshot_captions_round_into_the_next_minute() {
+        assert_eq!(snapshot_caption("Song", 0.0), "Song  00:00.0");
+        assert_eq!(snapshot_caption("Song", 61.24), "Song  01:01.2");
+        assert_eq!(snapshot_caption("Song", 59.96), "Song  01:00.0");
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+
+/// How many seconds of onsets the tempo goes by, so it follows tempo changes within a few bars
+const WINDOW_SECONDS: f64 = 8.0;
+/// Fewer onsets than this in the window aren't enough to tell a tempo
+const MIN_ONSETS: usize = 4;
+/// Onsets closer together or further apart than this aren't compared
+const MIN_INTERVAL: f64 = 0.2;
+const MAX_INTERVAL: f64 = 2.0;
+/// Tempos are folded into this octave, since half or double the tempo fits the same onsets
+const MIN_BPM: f32 = 80.0;
+const MAX_BPM: f32 = MIN_BPM * 2.0;
+/// How far apart two intervals' tempos can be and still count as the same one
+const BPM_TOLERANCE: f32 = 2.0;
+
+/// Estimates the tempo from the onsets of the last `WINDOW_SECONDS` of the song.
+#[derive(Clone, Default)]
+pub struct TempoTracker {
+    /// Track positions of the onsets, oldest first
+    onsets: VecDeque<f64>,
+    /// The track position of the last update
+    position: f64,
+    bpm: Option<f32>,
+}
+
+impl TempoTracker {
+    /// Call once a frame, with whether the frame at `position` seconds had an onset.
+    pub fn update(&mut self, position: f64, is_onset: bool) {
+        // Seeking back, or far enough ahead to skip the whole window, starts over
+        if position < self.position || position - self.position > WINDOW_SECONDS {
+            self.clear();
+        }
+        self.position = position;
+
+        let mut changed = false;
+        while self.onsets.front().is_some_and(|&onset| position - onset > WINDOW_SECONDS) {
+            self.onsets.pop_front();
+            changed = true;
+        }
+        if is_onset {
+            self.onsets.push_back(position);
+            changed = true;
+        }
+        if changed {
+            self.bpm = estimate_bpm(&self.onsets);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.onsets.clear();
+        self.position = 0.0;
+        self.bpm = None;
+    }
+
+    /// The estimated tempo in beats per minute, between `MIN_BPM` and `MAX_BPM`,
+    /// or `None` until there have been enough onsets.
+    pub fn bpm(&self) -> Option<f32> {
+        self.bpm
+    }
+
+    /// 1.0 on each beat, counted on from the last onset at the estimated tempo,
+    /// falling to 0.0 just before the next one. Always 0.0 without a tempo.
+    pub fn beat_pulse(&self) -> f32 {
+        let (Some(bpm), Some(&last_onset)) = (self.bpm, self.onsets.back()) else {
+            return 0.0;
+        };
+        let beats = (self.position - last_onset) * bpm as f64 / 60.0;
+        1.0 - beats.fract() as f32
+    }
+}
+
+/// The tempo most of the intervals between pairs of onsets agree on.
+///
+/// Every pair votes, not only neighbors, so a missed or extra onset doesn't throw
+/// the estimate off as much. Longer intervals count for more, since being off by
+/// a frame changes their tempo less.
+fn estimate_bpm(onsets: &VecDeque<f64>) -> Option<f32> {
+    if onsets.len() < MIN_ONSETS {
+        return None;
+    }
+
+    // Tempo and weight of each interval
+    let mut tempos = Vec::new();
+    for (idx, &earlier) in onsets.iter().enumerate() {
+        for &later in onsets.iter().skip(idx + 1) {
+            let interval = later - earlier;
+            if interval > MAX_INTERVAL {
+                break;
+            }
+            if interval >= MIN_INTERVAL {
+                tempos.push((fold_bpm(60.0 / interval as f32), interval as f32));
+            }
+        }
+    }
+
+    let votes = |candidate: f32| -> f32 {
+        tempos.iter()
+            .map(|&(bpm, weight)| weight * (1.0 - bpm_distance(bpm, candidate) / BPM_TOLERANCE).max(0.0))
+            .sum()
+    };
+    let candidates = (0..(MAX_BPM - MIN_BPM) as usize * 2).map(|half_bpm| MIN_BPM + half_bpm as f32 / 2.0);
+    let (best, best_votes) = candidates
+        .map(|candidate| (candidate, votes(candidate)))
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    if best_votes <= 0.0 {
+        return None;
+    }
+
+    // The average of the tempos that voted for it, which is finer than the candidates
+    let (sum, total_weight) = tempos.iter()
+        .filter(|&&(bpm, _)| bpm_distance(bpm, best) < BPM_TOLERANCE)
+        .fold((0.0, 0.0), |(sum, total_weight), &(bpm, weight)| {
+            // Unwrapped around the ends of the octave, so 159 and 81 average to 160, not 120
+            let bpm = if bpm - best > MIN_BPM / 2.0 { bpm / 2.0 } else if best - bpm > MIN_BPM / 2.0 { bpm * 2.0 } else { bpm };
+            (sum + bpm * weight, total_weight + weight)
+        });
+    Some(fold_bpm(sum / total_weight))
+}
+
+/// Doubles or halves `bpm` until it's between `MIN_BPM` and `MAX_BPM`.
+fn fold_bpm(mut bpm: f32) -> f32 {
+    while bpm < MIN_BPM {
+        bpm *= 2.0;
+    }
+    while bpm >= MAX_BPM {
+        bpm /= 2.0;
+    }
+    bpm
+}
+
+/// The difference between two folded tempos, where just under `MAX_BPM` is close to `MIN_BPM`.
+fn bpm_distance(a: f32, b: f32) -> f32 {
+    let (low, high) = if a < b { (a, b) } else { (b, a) };
+    (high - low).min(low * 2.0 - high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPS: f64 = 60.0;
+
+    /// Feeds a frame at a time, with an onset on the frame closest to each beat.
+    fn play_beats(tempo: &mut TempoTracker, bpm: f64, start: f64, seconds: f64) {
+        let beat_length = 60.0 / bpm;
+        for frame in (start * FPS) as usize..((start + seconds) * FPS) as usize {
+            let position = frame as f64 / FPS;
+            let beats = (position - start) / beat_length;
+            let is_onset = (beats - beats.round()).abs() * beat_length < 0.5 / FPS;
+            tempo.update(position, is_onset);
+        }
+    }
+
+    #[test]
+    fn steady_beats_give_their_tempo() {
+        let mut tempo = TempoTracker::default();
+        assert_eq!(tempo.bpm(), None);
+        play_beats(&mut tempo, 120.0, 0.0, 6.0);
+        let bpm = tempo.bpm().expect("no tempo");
+        assert!((bpm - 120.0).abs() < 1.0, "{bpm} BPM");
+    }
+
+    #[test]
+    fn the_estimate_follows_tempo_changes() {
+        let mut tempo = TempoTracker::default();
+        play_beats(&mut tempo, 100.0, 0.0, 10.0);
+        assert!((tempo.bpm().unwrap() - 100.0).abs() < 1.0);
+        play_beats(&mut tempo, 140.0, 10.0, 10.0);
+        let bpm = tempo.bpm().unwrap();
+        assert!((bpm - 140.0).abs() < 1.0, "{bpm} BPM");
+    }
+}
@@ -0,0 +1,122 @@
+use std::f64::consts::TAU;
+
+use kira::{dsp::Frame, sound::static_sound::StaticSoundData};
+
+use crate::music;
+
+const SAMPLE_RATE: u32 = 48000;
+/// Tones last long enough to look at, and sweeps are slow enough to follow
+const TONE_SECONDS: f64 = 60.0;
+const SWEEP_SECONDS: f64 = 30.0;
+/// Peak level, well under full scale so it isn't unpleasantly loud
+const AMPLITUDE: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    /// Odd harmonics above the tone, at a third, a fifth and so on of its amplitude.
+    Square,
+    /// A sine gliding from the lowest to the highest frequency shown, an octave every few seconds.
+    Sweep,
+}
+
+impl Waveform {
+    pub const ALL: [Self; 3] = [Self::Sine, Self::Square, Self::Sweep];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Sine => "Sine",
+            Self::Square => "Square",
+            Self::Sweep => "Sweep",
+        }
+    }
+}
+
+/// A generated signal to check the visualization against, like a 440 Hz sine that should light up A4.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TestTone {
+    pub waveform: Waveform,
+    /// Frequency of the sine and square waves, in Hz. Sweeps go over the whole range instead.
+    pub frequency: f32,
+}
+
+impl Default for TestTone {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Sine,
+            frequency: 440.0,
+        }
+    }
+}
+
+impl TestTone {
+    /// Shown in place of a file name.
+    pub fn name(&self) -> String {
+        match self.waveform {
+            Waveform::Sine => format!("{} Hz sine", self.frequency),
+            Waveform::Square => format!("{} Hz square wave", self.frequency),
+            Waveform::Sweep => String::from("Frequency sweep"),
+        }
+    }
+
+    /// The samples of the tone. Sweeps go from `sweep_range.0` to `sweep_range.1` Hz.
+    pub fn render(&self, sweep_range: (f32, f32)) -> StaticSoundData {
+        let sample_rate = SAMPLE_RATE as f64;
+        let frequency = self.frequency as f64;
+        let (sweep_start, sweep_end) = (sweep_range.0 as f64, sweep_range.1 as f64);
+        let seconds = if self.waveform == Waveform::Sweep { SWEEP_SECONDS } else { TONE_SECONDS };
+
+        let frames = (0..(seconds * sample_rate) as usize)
+            .map(|idx| {
+                let t = idx as f64 / sample_rate;
+                let sample = match self.waveform {
+                    Waveform::Sine => (TAU * frequency * t).sin(),
+                    Waveform::Square => (TAU * frequency * t).sin().signum(),
+                    Waveform::Sweep => {
+                        // Exponential, so every octave takes as long and it crosses the frame at an even speed
+                        let growth = (sweep_end / sweep_start).ln() / seconds;
+                        let phase = sweep_start * ((growth * t).exp() - 1.0) / growth;
+                        (TAU * phase).sin()
+                    }
+                };
+                Frame::from_mono(sample as f32 * AMPLITUDE)
+            })
+            .collect();
+
+        music::sound_data_from_frames(frames, SAMPLE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How often the signal crosses zero going up, per second, between `from` and `to` seconds.
+    fn crossings_per_second(sound_data: &StaticSoundData, from: f64, to: f64) -> f64 {
+        let rate = sound_data.sample_rate as f64;
+        let samples = &sound_data.frames[(from * rate) as usize..(to * rate) as usize];
+        let crossings = samples.windows(2).filter(|pair| pair[0].left < 0.0 && pair[1].left >= 0.0).count();
+        crossings as f64 / (to - from)
+    }
+
+    #[test]
+    fn tones_have_their_frequency() {
+        for waveform in [Waveform::Sine, Waveform::Square] {
+            let sound_data = TestTone { waveform, frequency: 440.0 }.render((20.0, 8000.0));
+            let frequency = crossings_per_second(&sound_data, 1.0, 2.0);
+            assert!((frequency - 440.0).abs() <= 1.0, "{waveform:?} at {frequency} Hz");
+        }
+    }
+
+    #[test]
+    fn sweeps_cover_the_range() {
+        let sound_data = TestTone { waveform: Waveform::Sweep, frequency: 440.0 }.render((100.0, 1600.0));
+        let start = crossings_per_second(&sound_data, 0.0, 0.5);
+        let middle = crossings_per_second(&sound_data, SWEEP_SECONDS / 2.0 - 0.25, SWEEP_SECONDS / 2.0 + 0.25);
+        let end = crossings_per_second(&sound_data, SWEEP_SECONDS - 0.5, SWEEP_SECONDS);
+        assert!((start - 100.0).abs() < 10.0, "starts at {start} Hz");
+        // Halfway through the four octaves is two octaves up
+        assert!((middle - 400.0).abs() < 20.0, "{middle} Hz halfway");
+        assert!((end - 1600.0).abs() < 80.0, "ends at {end} Hz");
+    }
+}
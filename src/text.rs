@@ -0,0 +1,89 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+use crate::{note, settings_window::OverlayPosition, style};
+
+/// Height of a caption's text, as a fraction of the image's height
+const CAPTION_SIZE: f32 = 0.035;
+
+/// The bundled font, for text drawn into saved images rather than shown by egui.
+pub fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(style::UNAGEO).expect("the bundled font is valid")
+}
+
+pub fn text_width(font: &FontRef, text: &str, size: f32) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum()
+}
+
+/// Draws `text` `size` pixels high, starting at `left` and sitting on `baseline`.
+/// `color` is premultiplied, like the pixels it's drawn over.
+pub fn paint_text(image: &mut RgbaImage, font: &FontRef, text: &str, size: f32, left: f32, baseline: f32, color: Rgba<u8>) {
+    let font = font.as_scaled(PxScale::from(size));
+
+    let mut caret = left;
+    for c in text.chars() {
+        let glyph = font.scaled_glyph(c);
+        let advance = font.h_advance(glyph.id);
+        let glyph = glyph.id.with_scale_and_position(font.scale(), ab_glyph::point(caret, baseline));
+        caret += advance;
+
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let (x, y) = (bounds.min.x as i32 + x as i32, bounds.min.y as i32 + y as i32);
+            if (0..image.width() as i32).contains(&x) && (0..image.height() as i32).contains(&y) {
+                note::blend_over(image.get_pixel_mut(x as u32, y as u32), color, coverage);
+            }
+        });
+    }
+}
+
+/// Burns `text` into `position` of `image` in `color`, with a shadow to keep it readable over bright notes.
+pub fn paint_caption(image: &mut RgbaImage, text: &str, position: OverlayPosition, color: [u8; 3]) {
+    let font = font();
+    let size = (image.height() as f32 * CAPTION_SIZE).max(10.);
+    let margin = size / 2.;
+    let scaled = font.as_scaled(PxScale::from(size));
+    let (ascent, descent) = (scaled.ascent(), scaled.descent());
+    let width = text_width(&font, text, size);
+    let height = ascent - descent;
+
+    let align = position.align();
+    let (image_width, image_height) = (image.width() as f32, image.height() as f32);
+    let left = match align.x() {
+        egui::Align::Min => margin,
+        egui::Align::Center => (image_width - width) / 2.,
+        egui::Align::Max => image_width - margin - width,
+    };
+    let top = match align.y() {
+        egui::Align::Min => margin,
+        egui::Align::Center => (image_height - height) / 2.,
+        egui::Align::Max => image_height - margin - height,
+    };
+    let baseline = top + ascent;
+
+    let shadow_offset = (size / 30.).max(1.);
+    paint_text(image, &font, text, size, left + shadow_offset, baseline + shadow_offset, Rgba([0, 0, 0, 200]));
+    paint_text(image, &font, text, size, left, baseline, Rgba([color[0], color[1], color[2], 255]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captions_go_in_their_corner() {
+        let mut image = RgbaImage::new(400, 300);
+        paint_caption(&mut image, "Song 01:23", OverlayPosition::BottomRight, [255, 255, 255]);
+
+        let painted: Vec<(u32, u32)> = image.enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert!(!painted.is_empty());
+        assert!(painted.iter().all(|&(x, y)| x > 200 && y > 250), "the caption spills out of the bottom right");
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+
+/// A short message shown at the top of the window for a few seconds.
+pub struct Toast {
+    message: Option<(String, f64)>,
+}
+
+impl Toast {
+    pub fn new() -> Self {
+        Self {
+            message: None,
+        }
+    }
+
+    pub fn show_message(&mut self, ctx: &egui::Context, message: impl Into<String>) {
+        let expires_at = ctx.input(|i| i.time) + TOAST_DURATION.as_secs_f64();
+        self.message = Some((message.into(), expires_at));
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let Some((message, expires_at)) = &self.message else {
+            return;
+        };
+
+        if ctx.input(|i| i.time) > *expires_at {
+            self.message = None;
+            return;
+        }
+
+        egui::Area::new("toast")
+            .anchor(egui::Align2::CENTER_TOP, (0.0, 20.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(message.as_str());
+                });
+            });
+    }
+}
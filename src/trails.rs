@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use image::{Rgba, RgbaImage};
+use imageproc::rect::Rect;
+
+use crate::{note::{self, Note}, settings_window::Settings, synesthetizer::ColorPalette};
+
+/// Notes further apart than this many semitones between frames start a new trail
+const MAX_TRAIL_JUMP: f32 = 2.;
+/// Thickness of a trail, at a frame height of 900 pixels
+const TRAIL_THICKNESS: f32 = 2.;
+
+/// Where notes were in the last few frames, so pitch bends and vibrato can be drawn as streaks.
+#[derive(Clone, Default)]
+pub struct NoteTrails {
+    trails: Vec<Trail>,
+}
+
+#[derive(Clone)]
+struct Trail {
+    midi: f32,
+    color: Rgba<u8>,
+    /// Centers of the note, from oldest to newest
+    points: VecDeque<(f32, f32)>,
+}
+
+impl NoteTrails {
+    /// Extends each trail to the nearest of this frame's `notes`, as painted in `area`.
+    ///
+    /// Trails without a note this frame end, and notes without a trail start one.
+    pub fn update(&mut self, notes: &[Note], area: Rect, color_palette: &ColorPalette, settings: &Settings) {
+        let mut unmatched = std::mem::take(&mut self.trails);
+
+        // Loudest first, so the loud notes get the trails they're closest to
+        for note in notes.iter().rev() {
+            let nearest = unmatched.iter()
+                .enumerate()
+                .map(|(idx, trail)| (idx, (trail.midi - note.midi()).abs()))
+                .filter(|&(_, distance)| distance <= MAX_TRAIL_JUMP)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(idx, _)| idx);
+
+            let mut trail = match nearest {
+                Some(idx) => unmatched.swap_remove(idx),
+                None => Trail {
+                    midi: note.midi(),
+                    color: Rgba([0; 4]),
+                    points: VecDeque::with_capacity(settings.trail_length),
+                },
+            };
+            trail.midi = note.midi();
+            trail.color = note.color(color_palette, settings);
+            trail.points.push_back(note.center(area, settings, notes.len()));
+            while trail.points.len() > settings.trail_length {
+                trail.points.pop_front();
+            }
+
+            self.trails.push(trail);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.trails.clear();
+    }
+
+    /// Draws every trail as a line that fades out towards its oldest end,
+    /// starting from `settings.trail_opacity` at the newest.
+    pub fn paint(&self, image: &mut RgbaImage, settings: &Settings) {
+        let half_thickness = (TRAIL_THICKNESS * image.height() as f32 / 900.).max(1.) / 2.;
+
+        for trail in &self.trails {
+            let segments = trail.points.len().saturating_sub(1);
+            for (idx, (&start, &end)) in trail.points.iter().zip(trail.points.iter().skip(1)).enumerate() {
+                let opacity = settings.trail_opacity * (idx + 1) as f32 / segments as f32;
+                paint_segment(image, start, end, half_thickness, trail.color, opacity);
+            }
+        }
+    }
+}
+
+/// Draws a line with rounded ends from `start` to `end`, with smooth edges.
+fn paint_segment(image: &mut RgbaImage, start: (f32, f32), end: (f32, f32), half_thickness: f32, color: Rgba<u8>, opacity: f32) {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = (dx * dx + dy * dy).max(f32::EPSILON);
+
+    let padding = half_thickness + 1.;
+    let left = ((start.0.min(end.0) - padding).floor() as i32).max(0);
+    let top = ((start.1.min(end.1) - padding).floor() as i32).max(0);
+    let right = ((start.0.max(end.0) + padding).ceil() as i32).min(image.width() as i32);
+    let bottom = ((start.1.max(end.1) + padding).ceil() as i32).min(image.height() as i32);
+
+    for y in top..bottom {
+        for x in left..right {
+            let (px, py) = (x as f32 + 0.5 - start.0, y as f32 + 0.5 - start.1);
+            // How far along the line the nearest point is, from 0.0 at `start` to 1.0 at `end`
+            let along = ((px * dx + py * dy) / length_squared).clamp(0., 1.);
+            let distance = (px - along * dx).hypot(py - along * dy) - half_thickness;
+            let coverage = (0.5 - distance).clamp(0., 1.) * opacity;
+            if coverage > 0. {
+                note::blend_over(image.get_pixel_mut(x as u32, y as u32), color, coverage);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::Pitch;
+
+    fn palette() -> ColorPalette {
+        ColorPalette([Rgba([255, 255, 255, 255]); 12])
+    }
+
+    #[test]
+    fn bends_continue_a_trail_and_jumps_start_a_new_one() {
+        let settings = Settings { trail_length: 3, ..Default::default() };
+        let area = Rect::at(0, 0).of_size(1600, 900);
+        let mut trails = NoteTrails::default();
+
+        for midi in [60., 60.5, 61., 61.5] {
+            trails.update(&[Note::new(Pitch::from_midi(midi), 1.)], area, &palette(), &settings);
+        }
+        assert_eq!(trails.trails.len(), 1);
+        assert_eq!(trails.trails[0].points.len(), 3);
+
+        trails.update(&[Note::new(Pitch::from_midi(72.), 1.)], area, &palette(), &settings);
+        assert_eq!(trails.trails.len(), 1);
+        assert_eq!(trails.trails[0].points.len(), 1);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread::{self, JoinHandle}};
+
+use crate::{music::Music, settings_window::Settings, synesthetizer::{self, Synesthetizer}};
+
+/// A video being rendered in the background by `Synesthetizer::render_to_video`.
+pub struct VideoExport {
+    frame_count: usize,
+    frames_done: Arc<AtomicUsize>,
+    join_handle: JoinHandle<anyhow::Result<()>>,
+}
+
+impl VideoExport {
+    /// Starts rendering `music` on another thread with a copy of `synesthetizer`,
+    /// so the one on screen keeps running.
+    pub fn start(synesthetizer: &Synesthetizer, music: &Music, settings: &Settings, path: PathBuf) -> Self {
+        let mut synesthetizer = synesthetizer.clone();
+        let sound_data = music.data().clone();
+        let audio_path = music.path().map(Path::to_path_buf);
+        let settings = settings.clone();
+
+        let frame_count = synesthetizer::video_frame_count(&sound_data, settings.fps);
+        let frames_done = Arc::new(AtomicUsize::new(0));
+
+        let join_handle = {
+            let frames_done = frames_done.clone();
+            thread::spawn(move || {
+                synesthetizer.render_to_video(&sound_data, audio_path.as_deref(), &settings, &path, &frames_done)
+            })
+        };
+
+        Self {
+            frame_count,
+            frames_done,
+            join_handle,
+        }
+    }
+
+    /// From 0.0 to 1.0
+    pub fn progress(&self) -> f32 {
+        if self.frame_count == 0 {
+            return 1.0;
+        }
+
+        self.frames_done.load(Ordering::Relaxed) as f32 / self.frame_count as f32
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.join_handle.is_finished()
+    }
+
+    /// Waits for the render thread. Call once `is_finished` returns true.
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self.join_handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!("the video export thread panicked")),
+        }
+    }
+}